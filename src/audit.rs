@@ -0,0 +1,102 @@
+//! Append-only audit log, one JSON object per access or failed attempt.
+
+use localsecret::Event;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+pub struct AuditLog {
+    file: Mutex<File>,
+    labels: BTreeMap<String, String>,
+}
+
+impl AuditLog {
+    /// Every record carries the `labels` of the share.
+    pub fn open(log_path: &Path, labels: BTreeMap<String, String>) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)
+            .map_err(|error| format!("Can't open audit log {:?}: {}", log_path, error))?;
+        Ok(AuditLog {
+            file: Mutex::new(file),
+            labels,
+        })
+    }
+
+    pub fn record(&self, event: &Event) {
+        let Some(record) = audit_record(event, &self.labels, SystemTime::now()) else {
+            return;
+        };
+        let mut file = self.file.lock().unwrap();
+        // Write the whole line at once, so concurrent writers don't interleave records
+        if let Err(error) = file.write_all(format!("{}\n", record).as_bytes()) {
+            tracing::error!("Can't write to the audit log: {}", error);
+        }
+    }
+}
+
+fn audit_record(
+    event: &Event,
+    labels: &BTreeMap<String, String>,
+    timestamp: SystemTime,
+) -> Option<Value> {
+    let timestamp = humantime::format_rfc3339_millis(timestamp).to_string();
+    let peer_ip = |peer: &Option<SocketAddr>| peer.map(|peer| peer.ip());
+    match event {
+        Event::Accessed {
+            peer,
+            user_agent,
+            bytes,
+            recipient,
+        } => Some(json!({
+            "timestamp": timestamp,
+            "outcome": "accessed",
+            "peer_ip": peer_ip(peer),
+            "user_agent": user_agent,
+            "bytes": bytes,
+            "recipient": recipient,
+            "labels": labels,
+        })),
+        Event::Denied { peer, user_agent } => Some(json!({
+            "timestamp": timestamp,
+            "outcome": "denied",
+            "peer_ip": peer_ip(peer),
+            "user_agent": user_agent,
+            "labels": labels,
+        })),
+        Event::Failed {
+            path,
+            peer,
+            user_agent,
+        } => Some(json!({
+            "timestamp": timestamp,
+            "outcome": "failed",
+            "peer_ip": peer_ip(peer),
+            "user_agent": user_agent,
+            "path": path,
+            "labels": labels,
+        })),
+        Event::LateAttempt {
+            path,
+            peer,
+            user_agent,
+        } => Some(json!({
+            "timestamp": timestamp,
+            "outcome": "late",
+            "peer_ip": peer_ip(peer),
+            "user_agent": user_agent,
+            "path": path,
+            "labels": labels,
+        })),
+        Event::UrlGenerated { .. } | Event::Shutdown(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,48 @@
+use super::*;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn test_audit_record() {
+    let timestamp = UNIX_EPOCH + Duration::from_secs(1_750_000_000);
+    let labels = BTreeMap::new();
+    let event = Event::Failed {
+        path: "/favicon.ico".to_string(),
+        peer: Some("10.11.12.13:4242".parse().unwrap()),
+        user_agent: Some("curl/8.5.0".to_string()),
+    };
+    assert_eq!(
+        audit_record(&event, &labels, timestamp)
+            .unwrap()
+            .to_string(),
+        r#"{"labels":{},"outcome":"failed","path":"/favicon.ico","peer_ip":"10.11.12.13","timestamp":"2025-06-15T15:06:40.000Z","user_agent":"curl/8.5.0"}"#
+    );
+    let event = Event::LateAttempt {
+        path: "/abc".to_string(),
+        peer: None,
+        user_agent: None,
+    };
+    assert_eq!(
+        audit_record(&event, &labels, timestamp).unwrap()["outcome"],
+        "late"
+    );
+    let event = Event::UrlGenerated {
+        url: "http://10.11.12.13:4242/abc".to_string(),
+    };
+    assert_eq!(audit_record(&event, &labels, timestamp), None);
+}
+
+#[test]
+fn test_audit_record_has_labels() {
+    let timestamp = UNIX_EPOCH + Duration::from_secs(1_750_000_000);
+    let labels = BTreeMap::from([("ticket".to_string(), "OPS-1".to_string())]);
+    let event = Event::Denied {
+        peer: Some("10.11.12.13:4242".parse().unwrap()),
+        user_agent: None,
+    };
+    assert_eq!(
+        audit_record(&event, &labels, timestamp)
+            .unwrap()
+            .to_string(),
+        r#"{"labels":{"ticket":"OPS-1"},"outcome":"denied","peer_ip":"10.11.12.13","timestamp":"2025-06-15T15:06:40.000Z","user_agent":null}"#
+    );
+}
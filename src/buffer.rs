@@ -0,0 +1,278 @@
+//! Serving a secret from memory, content or a file read on start, so it can go away meanwhile.
+//! Large content is served from a file that stays open, which might already be deleted.
+//!
+//! Like a served file, it answers `HEAD` and a single byte range, so download managers still
+//! count as one use. Multiple ranges are answered with the whole secret.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use tokio::sync::mpsc;
+
+pub(crate) struct Buffer {
+    pub(crate) content: Bytes,
+    pub(crate) content_type: HeaderValue,
+}
+
+impl Buffer {
+    /// Guesses the content type from the file name like a served file, or from the content.
+    pub(crate) fn new(content: Bytes, file_name: &std::path::Path) -> Self {
+        let content_type = match mime_guess::from_path(file_name).first() {
+            Some(content_type) => HeaderValue::from_str(content_type.as_ref())
+                .unwrap_or(HeaderValue::from_static("application/octet-stream")),
+            None => sniff_content_type(&content),
+        };
+        Buffer {
+            content,
+            content_type,
+        }
+    }
+}
+
+/// This many bytes tell whether the content is text.
+pub(crate) const SNIFF_LENGTH: usize = 8192;
+
+/// Well known file formats by the bytes at an offset they start with.
+const SIGNATURES: [(usize, &[u8], &str); 15] = [
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz"),
+    (0, b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"PK\x05\x06", "application/zip"),
+    (0, b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"\x00asm", "application/wasm"),
+    (257, b"ustar", "application/x-tar"),
+];
+
+/// A well known binary format by its magic bytes, text if the start of the content is valid
+/// UTF-8, binary data otherwise.
+pub(crate) fn sniff_content_type(content: &[u8]) -> HeaderValue {
+    let signature = SIGNATURES.iter().find(|(offset, magic, _)| {
+        content
+            .get(*offset..)
+            .is_some_and(|content| content.starts_with(magic))
+    });
+    if let Some((_, _, content_type)) = signature {
+        return HeaderValue::from_static(content_type);
+    }
+    let prefix = &content[..content.len().min(SNIFF_LENGTH)];
+    // The prefix might end in the middle of a character
+    let text = match std::str::from_utf8(prefix) {
+        Ok(_) => true,
+        Err(error) => error.error_len().is_none() && prefix.len() == SNIFF_LENGTH,
+    };
+    match text {
+        true => HeaderValue::from_static("text/plain; charset=utf-8"),
+        false => HeaderValue::from_static("application/octet-stream"),
+    }
+}
+
+/// The bytes of a `Range` header like `bytes=0-499`, `bytes=500-` or `bytes=-500`.
+fn requested_range(headers: &HeaderMap, length: u64) -> Option<Result<Range<u64>, ()>> {
+    let range = headers.get(header::RANGE)?.to_str().ok()?;
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    if start.contains(',') || end.contains(',') {
+        return None;
+    }
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            length.saturating_sub(suffix)..length
+        }
+        (start, "") => start.parse().ok()?..length,
+        (start, end) => start.parse().ok()?..end.parse::<u64>().ok()?.saturating_add(1).min(length),
+    };
+    Some(match range.start < range.end {
+        true => Ok(range),
+        false => Err(()),
+    })
+}
+
+pub(crate) async fn serve_buffer(
+    State(buffer): State<Arc<Buffer>>,
+    headers: HeaderMap,
+) -> Response {
+    let length = buffer.content.len() as u64;
+    // Axum drops the body of responses to HEAD requests
+    ranged_response(&headers, length, &buffer.content_type, |range| {
+        Body::from(
+            buffer
+                .content
+                .slice(range.start as usize..range.end as usize),
+        )
+    })
+}
+
+/// Content in a file that is read through its handle, not opened by its path again.
+pub(crate) struct SpooledFile {
+    file: Arc<File>,
+    length: u64,
+    pub(crate) content_type: HeaderValue,
+}
+
+impl SpooledFile {
+    /// Reads the file once to hash it and to sniff the content type from its start.
+    pub(crate) fn new(file: Arc<File>, hasher: &mut Sha256) -> io::Result<Self> {
+        let mut buffer = vec![0; FILE_CHUNK_SIZE];
+        let mut length = 0;
+        let mut prefix = Vec::new();
+        loop {
+            let read = read_at(&file, &mut buffer, length)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            if prefix.len() < SNIFF_LENGTH {
+                let missing = (SNIFF_LENGTH - prefix.len()).min(read);
+                prefix.extend_from_slice(&buffer[..missing]);
+            }
+            length += read as u64;
+        }
+        Ok(SpooledFile {
+            file,
+            length,
+            content_type: sniff_content_type(&prefix),
+        })
+    }
+}
+
+/// The file is read in chunks of this size.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads at an offset, without moving a position other downloads of the file share.
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    return std::os::unix::fs::FileExt::read_at(file, buffer, offset);
+    #[cfg(windows)]
+    return std::os::windows::fs::FileExt::seek_read(file, buffer, offset);
+}
+
+pub(crate) async fn serve_spooled_file(
+    State(spooled_file): State<Arc<SpooledFile>>,
+    headers: HeaderMap,
+) -> Response {
+    let content_type = spooled_file.content_type.clone();
+    ranged_response(&headers, spooled_file.length, &content_type, |range| {
+        Body::new(FileBody::spawn(spooled_file.file.clone(), range))
+    })
+}
+
+/// The chunks of a file range, read on a blocking thread as the client takes them.
+struct FileBody {
+    receiver: mpsc::Receiver<io::Result<Bytes>>,
+    remaining: u64,
+}
+
+impl FileBody {
+    fn spawn(file: Arc<File>, range: Range<u64>) -> Self {
+        let (sender, receiver) = mpsc::channel(2);
+        let remaining = range.end - range.start;
+        tokio::task::spawn_blocking(move || {
+            let mut offset = range.start;
+            while offset < range.end {
+                let mut chunk = vec![0; FILE_CHUNK_SIZE.min((range.end - offset) as usize)];
+                let chunk = match read_at(&file, &mut chunk, offset) {
+                    Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+                    Ok(read) => {
+                        chunk.truncate(read);
+                        offset += read as u64;
+                        Ok(Bytes::from(chunk))
+                    }
+                    Err(error) => Err(error),
+                };
+                let failed = chunk.is_err();
+                // The client went away
+                if sender.blocking_send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        FileBody {
+            receiver,
+            remaining,
+        }
+    }
+}
+
+impl HttpBody for FileBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = self.get_mut();
+        if this.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        Poll::Ready(match ready!(this.receiver.poll_recv(cx)) {
+            Some(Ok(chunk)) => {
+                this.remaining = this.remaining.saturating_sub(chunk.len() as u64);
+                Some(Ok(Frame::data(chunk)))
+            }
+            Some(Err(error)) => Some(Err(error)),
+            None => Some(Err(io::ErrorKind::UnexpectedEof.into())),
+        })
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+/// Answers with the requested range of the content, `body` only has to deliver it.
+fn ranged_response(
+    headers: &HeaderMap,
+    length: u64,
+    content_type: &HeaderValue,
+    body: impl FnOnce(Range<u64>) -> Body,
+) -> Response {
+    let (status, range) = match requested_range(headers, length) {
+        None => (StatusCode::OK, 0..length),
+        Some(Ok(range)) => (StatusCode::PARTIAL_CONTENT, range),
+        Some(Err(())) => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", length))],
+            )
+                .into_response();
+        }
+    };
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type.clone())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_LENGTH, range.end - range.start);
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", range.start, range.end - 1, length),
+        );
+    }
+    response
+        .body(body(range))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
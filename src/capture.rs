@@ -0,0 +1,213 @@
+//! Recording the requests and responses in a HAR file, to debug why a recipient's client
+//! can't fetch the secret, e.g. behind a corporate proxy.
+//!
+//! The token is redacted from the urls, also those in headers like `Referer`, and only headers
+//! that don't carry credentials are recorded, unless all headers are asked for. Bodies are
+//! never recorded. The file is rewritten after every request, so it is a complete HAR file at
+//! any time.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, header},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::{Value, json};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use crate::content_length;
+use crate::network::Peer;
+use crate::token;
+
+/// Headers that help debugging, but don't identify or authenticate the client.
+const RECORDED_HEADERS: [header::HeaderName; 11] = [
+    header::HOST,
+    header::USER_AGENT,
+    header::ACCEPT,
+    header::ACCEPT_ENCODING,
+    header::RANGE,
+    header::ORIGIN,
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::LOCATION,
+    header::RETRY_AFTER,
+];
+
+/// Headers whose values are redacted even when all headers are recorded.
+const CREDENTIAL_HEADERS: [&str; 6] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    crate::enrollment::NONCE_HEADER,
+    crate::enrollment::HMAC_HEADER,
+];
+
+/// Headers that may carry the url of the share, e.g. the `Referer` of the click-through page.
+const URL_HEADERS: [header::HeaderName; 2] = [header::REFERER, header::LOCATION];
+
+#[derive(Clone)]
+pub(crate) struct Capture {
+    file_path: Arc<Path>,
+    all_headers: bool,
+    tokens: Arc<[Arc<str>]>,
+    entries: Arc<Mutex<Vec<Value>>>,
+}
+
+impl Capture {
+    /// Creates the file right away, so a wrong path fails before the secret is shared.
+    pub(crate) fn create(
+        file_path: PathBuf,
+        all_headers: bool,
+        tokens: Arc<[Arc<str>]>,
+    ) -> io::Result<Self> {
+        let capture = Capture {
+            file_path: file_path.into(),
+            all_headers,
+            tokens,
+            entries: Arc::default(),
+        };
+        capture.write(&[])?;
+        Ok(capture)
+    }
+
+    fn write(&self, entries: &[Value]) -> io::Result<()> {
+        let har = json!({
+            "log": {
+                "version": "1.2",
+                "creator": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "entries": entries,
+            }
+        });
+        std::fs::write(&self.file_path, format!("{:#}\n", har))
+    }
+
+    fn record(&self, entry: Value) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(entry);
+        if let Err(error) = self.write(&entries) {
+            tracing::error!("Can't write the capture file: {}", error);
+        }
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> Vec<Value> {
+        headers
+            .iter()
+            .filter(|(name, _)| self.all_headers || RECORDED_HEADERS.contains(name))
+            .map(|(name, value)| {
+                let value = match CREDENTIAL_HEADERS.contains(&name.as_str()) {
+                    true => "REDACTED".to_string(),
+                    false if URL_HEADERS.contains(name) => {
+                        self.redact_url(value.to_str().unwrap_or("<binary>"))
+                    }
+                    false => value.to_str().unwrap_or("<binary>").to_string(),
+                };
+                json!({"name": name.as_str(), "value": value})
+            })
+            .collect()
+    }
+
+    /// Redacts the token in the path of an absolute or relative url.
+    fn redact_url(&self, url: &str) -> String {
+        let path_start = match url.split_once("://") {
+            Some((scheme, rest)) => rest.find('/').map(|start| scheme.len() + 3 + start),
+            None => url.starts_with('/').then_some(0),
+        };
+        let Some(start) = path_start else {
+            return url.to_string();
+        };
+        let end = url[start..]
+            .find(['?', '#'])
+            .map_or(url.len(), |end| start + end);
+        format!(
+            "{}{}{}",
+            &url[..start],
+            token::redact(&url[start..end], &self.tokens),
+            &url[end..]
+        )
+    }
+}
+
+pub(crate) async fn capture_requests(
+    State(capture): State<Option<Capture>>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(capture) = capture else {
+        return next.run(request).await;
+    };
+    let started = SystemTime::now();
+    let start = Instant::now();
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| {
+            request
+                .uri()
+                .authority()
+                .map(|authority| authority.as_str())
+        })
+        .unwrap_or("localhost")
+        .to_string();
+    let query = request
+        .uri()
+        .query()
+        .map(|query| format!("?{}", query))
+        .unwrap_or_default();
+    let url = format!(
+        "http://{}{}{}",
+        host,
+        token::redact(request.uri().path(), &capture.tokens),
+        query
+    );
+    let method = request.method().to_string();
+    let version = format!("{:?}", request.version());
+    let request_headers = capture.headers(request.headers());
+    let response = next.run(request).await;
+    // The body might still be streaming, so only the time until the response started is known
+    let time = start.elapsed().as_secs_f64() * 1000.0;
+    let size = content_length(&response).map_or(-1, |size| size as i64);
+    let mime_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default();
+    capture.record(json!({
+        "startedDateTime": humantime::format_rfc3339_millis(started).to_string(),
+        "time": time,
+        "request": {
+            "method": method,
+            "url": url,
+            "httpVersion": version,
+            "cookies": [],
+            "headers": request_headers,
+            "queryString": [],
+            "headersSize": -1,
+            "bodySize": -1,
+        },
+        "response": {
+            "status": response.status().as_u16(),
+            "statusText": response.status().canonical_reason().unwrap_or_default(),
+            "httpVersion": format!("{:?}", response.version()),
+            "cookies": [],
+            "headers": capture.headers(response.headers()),
+            "content": {"size": size.max(0), "mimeType": mime_type},
+            "redirectURL": "",
+            "headersSize": -1,
+            "bodySize": size,
+        },
+        "cache": {},
+        "timings": {"send": 0, "wait": time, "receive": 0},
+        "_peer": peer.address().map(|address| address.ip().to_string()),
+    }));
+    response
+}
@@ -0,0 +1,29 @@
+//! Serving user data to cloud-init's NoCloud datasource, for a VM booted with
+//! `ds=nocloud;s=<url>` on the kernel command line or in the SMBIOS serial.
+//!
+//! cloud-init fetches `meta-data` and `user-data` from the seed url and looks for a few
+//! optional files next to them, only the user data consumes a use.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use rand::{Rng, distr::Alphanumeric};
+
+/// Files cloud-init asks for, but which are fine to be missing.
+pub(crate) const OPTIONAL_FILES: [&str; 3] = ["vendor-data", "vendor-data2", "network-config"];
+
+/// Meta data with a new instance id, so cloud-init applies the user data even on a VM it
+/// provisioned before.
+pub(crate) fn meta_data() -> String {
+    let instance_id: String = rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+    format!("instance-id: localsecret-{}\n", instance_id)
+}
+
+pub(crate) async fn missing_file() -> Response {
+    (StatusCode::NOT_FOUND, "404 Not Found").into_response()
+}
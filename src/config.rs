@@ -0,0 +1,81 @@
+//! Defaults for the command line arguments, read from a TOML config file.
+//!
+//! The keys are the long argument names, e.g. `url-prefix-length = 20` or `ipv6 = true`.
+
+use clap::{Arg, ArgMatches, Command, parser::ValueSource};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_dir) if !config_dir.is_empty() => PathBuf::from(config_dir),
+        _ => PathBuf::from(env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?)
+            .join(".config"),
+    };
+    Some(config_dir.join("localsecret").join("config.toml"))
+}
+
+pub fn read_config(config_path: &Path) -> Result<Table, String> {
+    let content = fs::read_to_string(config_path)
+        .map_err(|error| format!("Can't read config file {:?}: {}", config_path, error))?;
+    content
+        .parse::<Table>()
+        .map_err(|error| format!("Can't parse config file {:?}: {}", config_path, error))
+}
+
+/// Turns the config into arguments, skipping everything given on or conflicting with the command line.
+pub fn config_to_args(
+    config: &Table,
+    command: &Command,
+    matches: &ArgMatches,
+) -> Result<Vec<OsString>, String> {
+    let mut args = Vec::new();
+    for (key, value) in config {
+        let long = key.replace('_', "-");
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()))
+        else {
+            return Err(format!("Unknown config key: {}", key));
+        };
+        // Conflicts are only declared on one of the two arguments
+        let conflicting = |a: &Arg, b: &Arg| {
+            command
+                .get_arg_conflicts_with(a)
+                .iter()
+                .any(|conflict| conflict.get_id() == b.get_id())
+        };
+        if command
+            .get_arguments()
+            .filter(|other| {
+                matches.value_source(other.get_id().as_str()) == Some(ValueSource::CommandLine)
+            })
+            .any(|other| {
+                other.get_id() == arg.get_id() || conflicting(arg, other) || conflicting(other, arg)
+            })
+        {
+            continue;
+        }
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Boolean(true) => args.push(format!("--{}", long).into()),
+                Value::Boolean(false) => {}
+                Value::String(value) => args.push(format!("--{}={}", long, value).into()),
+                Value::Integer(value) => args.push(format!("--{}={}", long, value).into()),
+                Value::Float(value) => args.push(format!("--{}={}", long, value).into()),
+                _ => return Err(format!("Unsupported value for config key: {}", key)),
+            }
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,48 @@
+use super::*;
+use clap::ArgAction;
+
+fn command() -> Command {
+    Command::new("localsecret")
+        .arg(Arg::new("uses").long("uses"))
+        .arg(Arg::new("url_prefix_length").long("url-prefix-length"))
+        .arg(Arg::new("ipv6").long("ipv6").action(ArgAction::SetTrue))
+        .arg(
+            Arg::new("dual_stack")
+                .long("dual-stack")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("ipv6"),
+        )
+}
+
+#[test]
+fn test_config_to_args() {
+    let config: Table = "uses = 3\nurl_prefix_length = 20\nipv6 = true\ndual-stack = false"
+        .parse()
+        .unwrap();
+    let matches = command().get_matches_from(["localsecret"]);
+    let args = config_to_args(&config, &command(), &matches).unwrap();
+    assert_eq!(
+        args,
+        ["--ipv6", "--url-prefix-length=20", "--uses=3"].map(OsString::from)
+    );
+}
+
+#[test]
+fn test_config_to_args_skips_command_line_arguments() {
+    let config: Table = "uses = 3\nipv6 = true".parse().unwrap();
+    let matches = command().get_matches_from(["localsecret", "--uses", "5"]);
+    let args = config_to_args(&config, &command(), &matches).unwrap();
+    assert_eq!(args, [OsString::from("--ipv6")]);
+
+    // The conflict is only declared on the command line argument
+    let matches = command().get_matches_from(["localsecret", "--dual-stack"]);
+    let args = config_to_args(&config, &command(), &matches).unwrap();
+    assert_eq!(args, [OsString::from("--uses=3")]);
+}
+
+#[test]
+fn test_config_to_args_rejects_unknown_keys() {
+    let config: Table = "usess = 3".parse().unwrap();
+    let matches = command().get_matches_from(["localsecret"]);
+    assert!(config_to_args(&config, &command(), &matches).is_err());
+}
@@ -0,0 +1,485 @@
+//! Adjusting a running share from another terminal, see `localsecret ctl`.
+//!
+//! The control socket only listens on a loopback address or a unix socket. Every connection
+//! sends a single command line, e.g. `extend-uses 1`, `extend-time 10m`, `revoke` or `status`,
+//! after the token of the control socket, and gets a single reply, errors start with `error: `.
+//! Without the token, other users of the machine could revoke the share or hand out more uses.
+//!
+//! The same state is also served as JSON on `GET /status` of a separate listener, for scripts
+//! polling a long-lived share. It only answers requests for a loopback host, so a web page
+//! can't read it by rebinding its own domain to the loopback address.
+
+use axum::{
+    Json, Router,
+    extract::{Request, State},
+    http::{StatusCode, header, uri::Authority},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::{Deadline, Error, ShutdownTrigger, TokenStyle, UseCount, UseLimit};
+
+/// Commands are short, anything longer isn't meant for the control socket.
+const MAXIMUM_COMMAND_LENGTH: u64 = 1024;
+
+/// Letters and digits in the token of a control socket.
+const CONTROL_TOKEN_LENGTH: u16 = 32;
+
+/// Where the control socket listens, a port on 127.0.0.1, a loopback address or a unix socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for ControlAddress {
+    type Err = String;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let socket_address = match address.parse::<u16>() {
+            Ok(port) => Some(SocketAddr::from((Ipv4Addr::LOCALHOST, port))),
+            Err(_) => address.parse::<SocketAddr>().ok(),
+        };
+        match socket_address {
+            // Anyone who can reach the socket can revoke the share or hand out more uses
+            Some(socket_address) if !socket_address.ip().is_loopback() => Err(format!(
+                "The control socket only listens on loopback addresses, not {}",
+                socket_address.ip()
+            )),
+            Some(socket_address) => Ok(ControlAddress::Tcp(socket_address)),
+            #[cfg(unix)]
+            None => Ok(ControlAddress::Unix(PathBuf::from(address))),
+            #[cfg(not(unix))]
+            None => Err(format!("Not a port or loopback address: {}", address)),
+        }
+    }
+}
+
+impl Display for ControlAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlAddress::Tcp(socket_address) => write!(f, "{}", socket_address),
+            #[cfg(unix)]
+            ControlAddress::Unix(socket_path) => write!(f, "{}", socket_path.display()),
+        }
+    }
+}
+
+/// The state of a running share, see [`Controller::status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub used_uses: u16,
+    /// Uses taken by downloads that are still running.
+    pub downloading: u16,
+    pub maximum_uses: u16,
+    pub failed_attempts: u16,
+    /// Not set if clients are locked out one by one instead of stopping the server.
+    pub maximum_failed_attempts: Option<u16>,
+    pub expires_in: Option<Duration>,
+    /// When the secret was last fetched.
+    pub last_access: Option<SystemTime>,
+    /// The labels attached to the share.
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "uses: {}/{}", self.used_uses, self.maximum_uses)?;
+        if self.downloading > 0 {
+            write!(f, " ({} downloading)", self.downloading)?;
+        }
+        match self.maximum_failed_attempts {
+            Some(maximum) => write!(f, "\nfailed attempts: {}/{}", self.failed_attempts, maximum)?,
+            None => write!(f, "\nfailed attempts: clients are locked out one by one")?,
+        }
+        match self.expires_in {
+            Some(expires_in) => write!(f, "\nexpires in: {}", format_duration(expires_in))?,
+            None => write!(f, "\nexpires in: never")?,
+        }
+        if let Some(last_access) = self.last_access {
+            write!(
+                f,
+                "\nlast access: {}",
+                humantime::format_rfc3339_seconds(last_access)
+            )?;
+        }
+        if !self.labels.is_empty() {
+            let labels: Vec<_> = self
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            write!(f, "\nlabels: {}", labels.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Status {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "uses_left": self.maximum_uses.saturating_sub(self.used_uses),
+            "used_uses": self.used_uses,
+            "downloading": self.downloading,
+            "maximum_uses": self.maximum_uses,
+            "failed_attempts": self.failed_attempts,
+            "maximum_failed_attempts": self.maximum_failed_attempts,
+            "expires_in_seconds": self.expires_in.map(|expires_in| expires_in.as_secs()),
+            "last_access": self
+                .last_access
+                .map(|last_access| humantime::format_rfc3339_seconds(last_access).to_string()),
+            "labels": self.labels,
+        })
+    }
+}
+
+fn format_duration(duration: Duration) -> humantime::FormattedDuration {
+    humantime::format_duration(Duration::from_secs(duration.as_secs()))
+}
+
+/// Adjusts a running share, can be cloned and moved into other tasks.
+#[derive(Clone)]
+pub struct Controller {
+    pub(crate) use_count: Arc<std::sync::Mutex<UseCount>>,
+    pub(crate) use_limit: UseLimit,
+    pub(crate) failed_attempts: Arc<Mutex<u16>>,
+    pub(crate) maximum_failed_attempts: Option<u16>,
+    pub(crate) deadline: Deadline,
+    pub(crate) shutdown_trigger: ShutdownTrigger,
+    pub(crate) labels: Vec<(String, String)>,
+}
+
+impl Controller {
+    pub async fn status(&self) -> Status {
+        let (used_uses, downloading, last_access, links_expire_at) = {
+            let use_count = self.use_count.lock().unwrap();
+            // The share stops once the last recipient link expired, if they all do
+            let links_expire_at = match use_count.recipients.is_empty() {
+                true => None,
+                false => use_count
+                    .recipients
+                    .iter()
+                    .map(|recipient| recipient.expires_at)
+                    .collect::<Option<Vec<_>>>()
+                    .and_then(|expires_at| expires_at.into_iter().max()),
+            };
+            (
+                use_count.used,
+                use_count.in_flight,
+                use_count.last_access,
+                links_expire_at,
+            )
+        };
+        let links_expire_in =
+            links_expire_at.map(|expires_at| expires_at.saturating_duration_since(Instant::now()));
+        let expires_in = match (self.deadline.remaining(), links_expire_in) {
+            (Some(expires_in), Some(links_expire_in)) => Some(expires_in.min(links_expire_in)),
+            (expires_in, links_expire_in) => expires_in.or(links_expire_in),
+        };
+        Status {
+            used_uses,
+            downloading,
+            maximum_uses: self.use_limit.maximum(),
+            failed_attempts: *self.failed_attempts.lock().await,
+            maximum_failed_attempts: self.maximum_failed_attempts,
+            expires_in,
+            last_access,
+            labels: self.labels.iter().cloned().collect(),
+        }
+    }
+
+    /// Runs a command of the control socket and returns the reply.
+    pub async fn execute(&self, command: &str) -> Result<String, String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let reply = match words[..] {
+            ["extend-uses", uses] => {
+                let uses = uses
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|uses| *uses > 0)
+                    .ok_or_else(|| format!("Not a number of uses: {}", uses))?;
+                // Every recipient link has uses of its own, more uses for the share change nothing
+                if !self.use_count.lock().unwrap().recipients.is_empty() {
+                    return Err(
+                        "The share has recipients, the uses of their links can't be extended"
+                            .to_string(),
+                    );
+                }
+                self.use_limit.add(uses);
+                let status = self.status().await;
+                format!("uses: {}/{}", status.used_uses, status.maximum_uses)
+            }
+            ["extend-time", duration] => {
+                let duration = humantime::parse_duration(duration)
+                    .map_err(|error| format!("Not a duration: {}: {}", duration, error))?;
+                let links_expire = self
+                    .use_count
+                    .lock()
+                    .unwrap()
+                    .recipients
+                    .iter()
+                    .any(|recipient| recipient.expires_at.is_some());
+                if links_expire {
+                    return Err(
+                        "Recipient links of the share expire, their time can't be extended"
+                            .to_string(),
+                    );
+                }
+                let expires_in = self
+                    .deadline
+                    .extend(duration)
+                    .ok_or("The share doesn't expire, there is no time to extend")?;
+                format!("expires in: {}", format_duration(expires_in))
+            }
+            ["revoke"] => {
+                self.shutdown_trigger.trigger();
+                "revoked".to_string()
+            }
+            ["status"] => self.status().await.to_string(),
+            _ => {
+                return Err(format!(
+                    "Unknown command: {:?}, try extend-uses <USES>, extend-time <DURATION>, revoke or status",
+                    command.trim()
+                ));
+            }
+        };
+        Ok(reply)
+    }
+
+    /// Answers the commands sent to the control socket until it is closed, only those that
+    /// come with its [`ControlSocket::token`].
+    pub fn listen(&self, address: &ControlAddress) -> Result<ControlSocket, Error> {
+        let token = TokenStyle::Alphanumeric.generate_exactly(CONTROL_TOKEN_LENGTH, false);
+        self.listen_with_token(address, token)
+    }
+
+    /// Like [`listen`](Self::listen), with a token chosen by the caller instead of a random one.
+    pub fn listen_with_token(
+        &self,
+        address: &ControlAddress,
+        token: String,
+    ) -> Result<ControlSocket, Error> {
+        let controller = self.clone();
+        let expected_token: Arc<str> = Arc::from(token.as_str());
+        let (listener, address) = bind(address)?;
+        let task = match listener {
+            BoundListener::Tcp(listener) => tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(controller.clone().answer(stream, expected_token.clone()));
+                }
+            }),
+            #[cfg(unix)]
+            BoundListener::Unix(listener) => tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(controller.clone().answer(stream, expected_token.clone()));
+                }
+            }),
+        };
+        Ok(ControlSocket {
+            address,
+            token: Some(token),
+            task,
+        })
+    }
+
+    /// Serves the status as JSON on `GET /status` until it is closed, e.g. for monitoring.
+    pub fn serve_status(&self, address: &ControlAddress) -> Result<ControlSocket, Error> {
+        let router = Router::new()
+            .route("/status", get(status_json))
+            .with_state(self.clone());
+        let (listener, address) = bind(address)?;
+        let task = match listener {
+            BoundListener::Tcp(listener) => tokio::spawn(async move {
+                let router = router.layer(middleware::from_fn(check_loopback_host));
+                axum::serve(listener, router).await.ok();
+            }),
+            #[cfg(unix)]
+            BoundListener::Unix(listener) => tokio::spawn(async move {
+                axum::serve(listener, router).await.ok();
+            }),
+        };
+        Ok(ControlSocket {
+            address,
+            token: None,
+            task,
+        })
+    }
+
+    async fn answer(self, stream: impl AsyncRead + AsyncWrite + Unpin, expected_token: Arc<str>) {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut line = String::new();
+        let read = BufReader::new(reader.take(MAXIMUM_COMMAND_LENGTH))
+            .read_line(&mut line)
+            .await;
+        let (token, command) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let reply = match read {
+            Ok(_) if bool::from(token.as_bytes().ct_eq(expected_token.as_bytes())) => {
+                self.execute(command).await
+            }
+            Ok(_) => {
+                tracing::warn!("Control socket: refused a command with a wrong token");
+                Err("Wrong control token".to_string())
+            }
+            Err(error) => Err(format!("Can't read the command: {}", error)),
+        };
+        let reply = match reply {
+            Ok(reply) => {
+                tracing::info!("Control socket: {}", command.trim());
+                reply
+            }
+            Err(error) => format!("error: {}", error),
+        };
+        writer
+            .write_all(format!("{}\n", reply).as_bytes())
+            .await
+            .ok();
+        writer.shutdown().await.ok();
+    }
+}
+
+enum BoundListener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+/// Returns the address with the actual port, if port 0 was given.
+fn bind(address: &ControlAddress) -> Result<(BoundListener, ControlAddress), Error> {
+    match address {
+        ControlAddress::Tcp(socket_address) => {
+            let bind_error = |error| Error::ControlSocket(address.to_string(), error);
+            let listener = std::net::TcpListener::bind(socket_address)
+                .and_then(|listener| {
+                    listener.set_nonblocking(true)?;
+                    tokio::net::TcpListener::from_std(listener)
+                })
+                .map_err(bind_error)?;
+            let local_address = listener.local_addr().map_err(bind_error)?;
+            Ok((
+                BoundListener::Tcp(listener),
+                ControlAddress::Tcp(local_address),
+            ))
+        }
+        #[cfg(unix)]
+        ControlAddress::Unix(socket_path) => {
+            let listener = crate::network::create_unix_listener(socket_path)?;
+            Ok((BoundListener::Unix(listener), address.clone()))
+        }
+    }
+}
+
+async fn status_json(State(controller): State<Controller>) -> Json<Value> {
+    Json(controller.status().await.to_json())
+}
+
+/// Like the host check of the share, but the status endpoint only listens on loopback addresses.
+async fn check_loopback_host(request: Request, next: Next) -> Response {
+    let host = match request.headers().get(header::HOST) {
+        Some(host) => Some(host.to_str().unwrap_or_default()),
+        None => request
+            .uri()
+            .authority()
+            .map(|authority| authority.as_str()),
+    };
+    // Clients without a host aren't browsers
+    let allowed = host.is_none_or(|host| {
+        host.parse::<Authority>().is_ok_and(|authority| {
+            let host = authority.host();
+            host.eq_ignore_ascii_case("localhost")
+                || host
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse::<IpAddr>()
+                    .is_ok_and(|ip| ip.is_loopback())
+        })
+    });
+    match allowed {
+        true => next.run(request).await,
+        false => (StatusCode::MISDIRECTED_REQUEST, "421 Misdirected Request").into_response(),
+    }
+}
+
+/// A listening control socket or status endpoint, see [`Controller::listen`].
+pub struct ControlSocket {
+    address: ControlAddress,
+    token: Option<String>,
+    task: JoinHandle<()>,
+}
+
+impl ControlSocket {
+    /// The address `localsecret ctl` connects to, with the actual port if port 0 was given.
+    pub fn address(&self) -> &ControlAddress {
+        &self.address
+    }
+
+    /// The random token commands must be sent with, drawn anew for every control socket. The
+    /// status endpoint has none.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Stops listening, and removes the unix socket.
+    pub fn close(self) {
+        self.task.abort();
+        #[cfg(unix)]
+        if let ControlAddress::Unix(socket_path) = &self.address {
+            std::fs::remove_file(socket_path).ok();
+        }
+    }
+}
+
+/// Sends a command with the token of the control socket of a running share and returns the
+/// reply.
+pub async fn send(address: &ControlAddress, token: &str, command: &str) -> Result<String, Error> {
+    let connection_error = |error: io::Error| Error::ControlSocket(address.to_string(), error);
+    let reply = match address {
+        ControlAddress::Tcp(socket_address) => {
+            let stream = tokio::net::TcpStream::connect(socket_address)
+                .await
+                .map_err(connection_error)?;
+            exchange(stream, token, command).await
+        }
+        #[cfg(unix)]
+        ControlAddress::Unix(socket_path) => {
+            let stream = tokio::net::UnixStream::connect(socket_path)
+                .await
+                .map_err(connection_error)?;
+            exchange(stream, token, command).await
+        }
+    }
+    .map_err(connection_error)?;
+    let reply = reply.trim_end().to_string();
+    match reply.strip_prefix("error: ") {
+        Some(error) => Err(Error::Control(error.to_string())),
+        None => Ok(reply),
+    }
+}
+
+async fn exchange(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    token: &str,
+    command: &str,
+) -> io::Result<String> {
+    stream
+        .write_all(format!("{} {}\n", token, command.trim()).as_bytes())
+        .await?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await?;
+    Ok(reply)
+}
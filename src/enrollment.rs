@@ -0,0 +1,188 @@
+//! Enrollment for unattended clients: a fetch must prove knowledge of a shared key.
+//!
+//! A request without proof gets a 401 with a fresh nonce in the `Localsecret-Nonce` header.
+//! The client repeats the request with that nonce and the hex encoded HMAC-SHA256 of it
+//! in the `Localsecret-Hmac` header, e.g. in a shell script:
+//!
+//! ```sh
+//! nonce=$(curl -sI "$url" | tr -d '\r' | sed -n 's/^localsecret-nonce: //ip')
+//! hmac=$(printf %s "$nonce" | openssl dgst -sha256 -hmac "$key" -r | cut -d' ' -f1)
+//! curl -H "Localsecret-Nonce: $nonce" -H "Localsecret-Hmac: $hmac" "$url"
+//! ```
+//!
+//! Every nonce can only be used once, and only by the client it was issued to.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use rand::{Rng, distr::Alphanumeric};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::events::EventHooks;
+use crate::network::Peer;
+use crate::{Event, user_agent};
+
+pub const NONCE_HEADER: &str = "localsecret-nonce";
+pub const HMAC_HEADER: &str = "localsecret-hmac";
+
+/// Older nonces of a client are forgotten, so clients asking for nonces can't fill up the
+/// memory. Each client only pushes out its own nonces.
+const MAXIMUM_PENDING_NONCES: usize = 64;
+
+/// The clients that asked for a nonce longest ago are forgotten beyond this many.
+const MAXIMUM_PENDING_CLIENTS: usize = 1024;
+
+/// The hex encoded HMAC-SHA256 of `nonce`, as expected in the `Localsecret-Hmac` header.
+pub fn enrollment_hmac(key: &[u8], nonce: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(nonce.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[derive(Clone)]
+pub(crate) struct EnrollmentKey(pub(crate) Arc<[u8]>);
+
+impl Debug for EnrollmentKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EnrollmentKey")
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct EnrollmentState {
+    pub(crate) key: Option<EnrollmentKey>,
+    pub(crate) nonces: Arc<Mutex<PendingNonces>>,
+    pub(crate) event_hooks: EventHooks,
+}
+
+/// The nonces issued to each client, the client that asked most recently last.
+#[derive(Default)]
+pub(crate) struct PendingNonces(VecDeque<(Option<IpAddr>, VecDeque<String>)>);
+
+impl PendingNonces {
+    fn push(&mut self, client: Option<IpAddr>, nonce: String) {
+        let mut nonces = match self
+            .0
+            .iter()
+            .position(|(issued_to, _)| *issued_to == client)
+        {
+            Some(index) => self
+                .0
+                .remove(index)
+                .map(|(_, nonces)| nonces)
+                .unwrap_or_default(),
+            None => VecDeque::new(),
+        };
+        if nonces.len() >= MAXIMUM_PENDING_NONCES {
+            nonces.pop_front();
+        }
+        nonces.push_back(nonce);
+        self.0.push_back((client, nonces));
+        if self.0.len() > MAXIMUM_PENDING_CLIENTS {
+            self.0.pop_front();
+        }
+    }
+
+    /// Uses up the nonce if it was issued to the client, returns whether it was.
+    fn take(&mut self, client: Option<IpAddr>, nonce: &str) -> bool {
+        let Some(index) = self
+            .0
+            .iter()
+            .position(|(issued_to, _)| *issued_to == client)
+        else {
+            return false;
+        };
+        let nonces = &mut self.0[index].1;
+        let Some(position) = nonces.iter().position(|pending| pending == nonce) else {
+            return false;
+        };
+        nonces.remove(position);
+        if nonces.is_empty() {
+            self.0.remove(index);
+        }
+        true
+    }
+}
+
+impl EnrollmentState {
+    fn issue_nonce(&self, client: Option<IpAddr>) -> String {
+        let nonce: String = rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        self.nonces.lock().unwrap().push(client, nonce.clone());
+        nonce
+    }
+
+    /// Checks the proof and uses up the nonce, even if the HMAC is wrong.
+    fn verify(&self, client: Option<IpAddr>, key: &[u8], nonce: &str, hmac: &str) -> bool {
+        if !self.nonces.lock().unwrap().take(client, nonce) {
+            return false;
+        }
+        let Some(hmac) = decode_hex(hmac) else {
+            return false;
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(nonce.as_bytes());
+        mac.verify_slice(&hmac).is_ok()
+    }
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    let value = headers.get(name)?.to_str().ok()?;
+    Some(value.trim().to_string())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(hex.get(start..start + 2)?, 16).ok())
+        .collect()
+}
+
+pub(crate) async fn require_enrollment(
+    State(state): State<EnrollmentState>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(EnrollmentKey(key)) = &state.key else {
+        return next.run(request).await;
+    };
+    let client = peer.address().map(|address| address.ip());
+    let headers = request.headers();
+    match (header(headers, NONCE_HEADER), header(headers, HMAC_HEADER)) {
+        (Some(nonce), Some(hmac)) if state.verify(client, key, &nonce, &hmac) => {
+            next.run(request).await
+        }
+        (None, None) => (
+            StatusCode::UNAUTHORIZED,
+            [(NONCE_HEADER, state.issue_nonce(client))],
+            "401 Unauthorized",
+        )
+            .into_response(),
+        _ => {
+            state.event_hooks.emit(Event::Denied {
+                peer: peer.address(),
+                user_agent: user_agent(request.headers()),
+            });
+            (StatusCode::FORBIDDEN, "403 Forbidden").into_response()
+        }
+    }
+}
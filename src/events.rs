@@ -0,0 +1,105 @@
+//! Events of a running share, for embedders that want to show progress.
+
+use std::fmt::{self, Debug};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// An url the secret can be fetched from.
+    UrlGenerated { url: String },
+    /// The secret was fetched, `bytes` is the announced content length and `recipient` the
+    /// name of the recipient whose url was used.
+    Accessed {
+        peer: Option<SocketAddr>,
+        user_agent: Option<String>,
+        bytes: Option<u64>,
+        recipient: Option<String>,
+    },
+    /// A request for the secret wasn't approved.
+    Denied {
+        peer: Option<SocketAddr>,
+        user_agent: Option<String>,
+    },
+    /// An invalid path was requested. A token in the path, even a mangled one, is redacted.
+    Failed {
+        path: String,
+        peer: Option<SocketAddr>,
+        user_agent: Option<String>,
+    },
+    /// The share is stopping, no further events follow except late attempts.
+    Shutdown(ShutdownReason),
+    /// Someone tried to fetch the secret after the share ended, see
+    /// [`ShareBuilder::afterlife`](crate::ShareBuilder::afterlife). The path is redacted like
+    /// that of [`Event::Failed`].
+    LateAttempt {
+        path: String,
+        peer: Option<SocketAddr>,
+        user_agent: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// Every use was consumed.
+    UsesExhausted,
+    FailedAttemptsExhausted,
+    /// The share expired, or every recipient link that still had uses left.
+    Expired,
+    Triggered,
+    /// A request handler panicked.
+    Panicked,
+    /// The shared file changed on disk, see
+    /// [`ShareBuilder::abort_on_change`](crate::ShareBuilder::abort_on_change).
+    SecretChanged,
+}
+
+/// How much of the secret was sent to a client, see
+/// [`ShareBuilder::on_progress`](crate::ShareBuilder::on_progress).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub peer: Option<SocketAddr>,
+    pub sent: u64,
+    /// The announced content length.
+    pub total: Option<u64>,
+    /// The download ended, completely or not.
+    pub done: bool,
+}
+
+type Hook = Arc<dyn Fn(&Event) + Send + Sync>;
+
+type ProgressHook = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub(crate) struct EventHooks {
+    hooks: Vec<Hook>,
+    progress_hooks: Vec<ProgressHook>,
+}
+
+impl EventHooks {
+    pub(crate) fn push(&mut self, hook: impl Fn(&Event) + Send + Sync + 'static) {
+        self.hooks.push(Arc::new(hook));
+    }
+
+    pub(crate) fn push_progress(&mut self, hook: impl Fn(&Progress) + Send + Sync + 'static) {
+        self.progress_hooks.push(Arc::new(hook));
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        for hook in &self.hooks {
+            hook(&event);
+        }
+    }
+
+    pub(crate) fn emit_progress(&self, progress: &Progress) {
+        for hook in &self.progress_hooks {
+            hook(progress);
+        }
+    }
+}
+
+impl Debug for EventHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EventHooks({})", self.hooks.len())
+    }
+}
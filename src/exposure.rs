@@ -0,0 +1,261 @@
+//! Optional ways to make a running share reachable under another address.
+
+use crate::Error;
+use crate::network::format_url;
+use gethostname::gethostname;
+use igd_next::{
+    PortMappingProtocol, SearchOptions,
+    aio::{
+        Gateway,
+        tokio::{Tokio, search_gateway},
+    },
+};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use rand::Rng;
+use std::fs;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, Command},
+};
+
+const MDNS_SERVICE_TYPE: &str = "_http._tcp.local.";
+// The mapping expires on its own, in case the server isn't able to remove it
+const UPNP_LEASE_DURATION: u32 = 60 * 60;
+
+/// A `_http._tcp` service announced via mDNS under the `.local` host name.
+pub struct MdnsAdvertisement {
+    daemon: ServiceDaemon,
+    host_name: String,
+    port: u16,
+}
+
+impl MdnsAdvertisement {
+    pub fn start(addresses: &[SocketAddr]) -> Result<Self, Error> {
+        let daemon = ServiceDaemon::new().map_err(Error::Mdns)?;
+        let host_name = gethostname().into_string().map_err(Error::HostName)?;
+        // All listeners share the same port, unless it was already taken for one of the address families
+        let port = addresses[0].port();
+        let ips: Vec<IpAddr> = addresses.iter().map(SocketAddr::ip).collect();
+        // Only the host and port are announced, the url path must never end up in the TXT records
+        let service = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &format!("localsecret-{}", std::process::id()),
+            &format!("{}.local.", host_name),
+            &ips[..],
+            port,
+            None,
+        )
+        .map_err(Error::Mdns)?;
+        daemon.register(service).map_err(Error::Mdns)?;
+        Ok(MdnsAdvertisement {
+            daemon,
+            host_name,
+            port,
+        })
+    }
+
+    pub fn url(&self, url_path: &str) -> String {
+        format!("http://{}.local:{}{}", self.host_name, self.port, url_path)
+    }
+
+    pub fn stop(self) {
+        // Give the daemon a moment to send the goodbye packets, but don't block the shutdown on it
+        if let Ok(status) = self.daemon.shutdown() {
+            status.recv_timeout(Duration::from_secs(1)).ok();
+        }
+    }
+}
+
+/// A SSH reverse port forward from a remote host to the share.
+pub struct SshTunnel {
+    child: Child,
+    remote_address: SocketAddr,
+    remote_host: String,
+}
+
+impl SshTunnel {
+    pub async fn open(destination: &str, address: &SocketAddr) -> Result<Self, Error> {
+        // Let the SSH server pick the remote port, it is reported back on stderr
+        let mut child = Command::new("ssh")
+            .arg("-N")
+            .arg("-o")
+            .arg("ExitOnForwardFailure=yes")
+            .arg("-R")
+            .arg(format!("0:{}", address))
+            .arg(destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(Error::SshSpawn)?;
+
+        let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+        let remote_port = loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => match parse_allocated_port(&line) {
+                    Some(port) => break port,
+                    // Pass through anything else, e.g. host key warnings or errors
+                    None => tracing::info!("ssh: {}", line),
+                },
+                Ok(None) | Err(_) => return Err(Error::SshTunnel(destination.to_string())),
+            }
+        };
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::info!("ssh: {}", line);
+            }
+        });
+
+        let remote_host = destination.rsplit('@').next().unwrap_or(destination);
+        Ok(SshTunnel {
+            child,
+            remote_address: SocketAddr::new(address.ip(), remote_port),
+            remote_host: remote_host.to_string(),
+        })
+    }
+
+    pub fn url(&self, url_path: &str) -> String {
+        format_url(&self.remote_address, Some(&self.remote_host), url_path)
+    }
+
+    pub async fn close(mut self) {
+        self.child.kill().await.ok();
+    }
+}
+
+pub(crate) fn parse_allocated_port(line: &str) -> Option<u16> {
+    // e.g. "Allocated port 43210 for remote forward to 10.11.12.13:46497"
+    line.strip_prefix("Allocated port ")?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// A temporary port mapping on the router, requested via UPnP.
+pub struct UpnpMapping {
+    gateway: Gateway<Tokio>,
+    external_address: SocketAddr,
+}
+
+impl UpnpMapping {
+    pub async fn add(addresses: &[SocketAddr]) -> Result<Self, Error> {
+        let local_address = addresses
+            .iter()
+            .find(|address| address.is_ipv4())
+            .ok_or(Error::UpnpIpv4Only)?;
+        let gateway = search_gateway(SearchOptions::default())
+            .await
+            .map_err(Error::UpnpGateway)?;
+        let external_address = gateway
+            .get_any_address(
+                PortMappingProtocol::TCP,
+                *local_address,
+                UPNP_LEASE_DURATION,
+                "localsecret",
+            )
+            .await
+            .map_err(Error::UpnpMapping)?;
+        Ok(UpnpMapping {
+            gateway,
+            external_address,
+        })
+    }
+
+    pub fn external_address(&self) -> SocketAddr {
+        self.external_address
+    }
+
+    pub fn url(&self, url_path: &str) -> String {
+        format_url(&self.external_address, None, url_path)
+    }
+
+    pub async fn remove(self) -> Result<(), Error> {
+        self.gateway
+            .remove_port(PortMappingProtocol::TCP, self.external_address.port())
+            .await
+            .map_err(|error| Error::UpnpRemove(self.external_address, error))
+    }
+}
+
+/// A temporary host name like `secret-7f3a.lan`, added to a hosts file.
+///
+/// This is meant for files a local DNS server serves from, e.g. a dnsmasq `hostsdir`,
+/// which is watched for changes, or `/etc/hosts` on the DNS server itself. The entry is
+/// removed when dropped, unless it was removed before.
+pub struct HostsEntry {
+    hosts_file: PathBuf,
+    host_name: String,
+    address: SocketAddr,
+    marker: String,
+    removed: bool,
+}
+
+impl HostsEntry {
+    pub fn add(hosts_file: &Path, domain: &str, addresses: &[SocketAddr]) -> Result<Self, Error> {
+        let host_name = format!("secret-{:04x}.{}", rand::rng().random::<u16>(), domain);
+        // The marker identifies the lines of this share when removing them again
+        let marker = format!("# localsecret {}", std::process::id());
+        let lines: String = addresses
+            .iter()
+            .filter(|address| !address.ip().is_unspecified())
+            .map(|address| format!("{} {} {}\n", address.ip(), host_name, marker))
+            .collect();
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(hosts_file)
+            .and_then(|mut file| file.write_all(lines.as_bytes()))
+            .map_err(|error| Error::HostsFile(hosts_file.to_path_buf(), error))?;
+        Ok(HostsEntry {
+            hosts_file: hosts_file.to_path_buf(),
+            host_name,
+            address: addresses[0],
+            marker,
+            removed: false,
+        })
+    }
+
+    pub fn host_name(&self) -> &str {
+        &self.host_name
+    }
+
+    pub fn url(&self, url_path: &str) -> String {
+        format_url(&self.address, Some(&self.host_name), url_path)
+    }
+
+    pub fn remove(mut self) -> Result<(), Error> {
+        self.removed = true;
+        self.remove_lines()
+    }
+
+    fn remove_lines(&self) -> Result<(), Error> {
+        let entry = format!(" {} {}", self.host_name, self.marker);
+        fs::read_to_string(&self.hosts_file)
+            .and_then(|content| {
+                let remaining: String = content
+                    .split_inclusive('\n')
+                    .filter(|line| !line.trim_end().ends_with(&entry))
+                    .collect();
+                fs::write(&self.hosts_file, remaining)
+            })
+            .map_err(|error| Error::HostsFile(self.hosts_file.clone(), error))
+    }
+}
+
+impl Drop for HostsEntry {
+    fn drop(&mut self) {
+        // e.g. when an error ends the program early
+        if !self.removed
+            && let Err(error) = self.remove_lines()
+        {
+            tracing::error!("{}", error);
+        }
+    }
+}
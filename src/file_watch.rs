@@ -0,0 +1,119 @@
+//! Stopping the share when the shared file changes on disk, so a modified or truncated secret
+//! is never delivered.
+//!
+//! The file is checked before every delivery and polled in between, so the share also stops
+//! when nobody asks for it. A download that is already running isn't interrupted.
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, watch};
+
+use crate::ShutdownReason;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What changes when the file is written to or replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fingerprint {
+    length: u64,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    inode: u64,
+}
+
+impl Fingerprint {
+    fn of(file_path: &Path) -> io::Result<Self> {
+        let metadata = std::fs::metadata(file_path)?;
+        Ok(Fingerprint {
+            length: metadata.len(),
+            modified: metadata.modified().ok(),
+            #[cfg(unix)]
+            inode: std::os::unix::fs::MetadataExt::ino(&metadata),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct FileWatch {
+    file_path: Arc<Path>,
+    fingerprint: Fingerprint,
+    changed: Arc<AtomicBool>,
+    shutdown_channel: mpsc::Sender<ShutdownReason>,
+}
+
+impl FileWatch {
+    pub(crate) fn new(
+        file_path: &Path,
+        shutdown_channel: mpsc::Sender<ShutdownReason>,
+    ) -> io::Result<Self> {
+        Ok(FileWatch {
+            file_path: file_path.into(),
+            fingerprint: Fingerprint::of(file_path)?,
+            changed: Arc::default(),
+            shutdown_channel,
+        })
+    }
+
+    pub(crate) fn file_path(&self) -> PathBuf {
+        self.file_path.to_path_buf()
+    }
+
+    pub(crate) fn changed(&self) -> bool {
+        self.changed.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether the file is still as it was on start, stops the share otherwise.
+    fn check(&self) -> bool {
+        if self.changed() {
+            return false;
+        }
+        if Fingerprint::of(&self.file_path).ok().as_ref() == Some(&self.fingerprint) {
+            return true;
+        }
+        if !self.changed.swap(true, Ordering::SeqCst) {
+            tracing::error!(
+                "The secret file {:?} changed, stopping the share",
+                self.file_path
+            );
+            self.shutdown_channel
+                .try_send(ShutdownReason::SecretChanged)
+                .ok();
+        }
+        false
+    }
+
+    /// Checks the file until it changed or the share stopped.
+    pub(crate) fn spawn_polling(&self, mut stop_receiver: watch::Receiver<()>) {
+        let file_watch = self.clone();
+        tokio::spawn(async move {
+            while file_watch.check() {
+                tokio::select! {
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                    _ = stop_receiver.changed() => break,
+                }
+            }
+        });
+    }
+}
+
+pub(crate) async fn refuse_changed_secret(
+    State(file_watch): State<Option<FileWatch>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match file_watch {
+        Some(file_watch) if !file_watch.check() => {
+            (StatusCode::SERVICE_UNAVAILABLE, "503 Service Unavailable").into_response()
+        }
+        _ => next.run(request).await,
+    }
+}
@@ -0,0 +1,173 @@
+//! Serving a git repository read-only via the smart HTTP protocol, so it can be cloned.
+//!
+//! `git upload-pack` does the actual work, it runs once per request in stateless mode.
+//! A clone lists the refs with a GET request and fetches the pack with a POST request,
+//! only the latter consumes a use.
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::State,
+    http::{HeaderMap, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use flate2::read::GzDecoder;
+use futures_util::{StreamExt, future, stream};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio_util::io::ReaderStream;
+
+use crate::Error;
+
+/// Upper limit for the size of a fetch request, it only lists the wanted and present commits.
+pub(crate) const MAX_REQUEST_SIZE: u64 = 1024 * 1024;
+
+/// The absolute path of the repository, as long as git recognizes it as one.
+pub(crate) fn validate_repository(repository: &Path) -> Result<PathBuf, Error> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(repository)
+        .args(["rev-parse", "--absolute-git-dir"])
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|error| Error::GitRepository(repository.to_path_buf(), error.to_string()))?;
+    if !output.status.success() {
+        return Err(Error::GitRepository(
+            repository.to_path_buf(),
+            "not a git repository".to_string(),
+        ));
+    }
+    let git_dir = String::from_utf8_lossy(&output.stdout);
+    Ok(PathBuf::from(git_dir.trim_end()))
+}
+
+/// The last url segment, e.g. `project.git`, which `git clone` also uses as directory name.
+pub(crate) fn clone_name(git_dir: &Path) -> PathBuf {
+    // The git dir of a work tree is called .git, the name of the project is its parent's
+    let name = match git_dir.file_name() {
+        Some(name) if name == ".git" => git_dir.parent().and_then(Path::file_name),
+        name => name,
+    };
+    let name = name.map_or("repository".into(), |name| name.to_string_lossy());
+    PathBuf::from(format!("{}.git", name.trim_end_matches(".git")))
+}
+
+pub(crate) async fn advertise_refs(git_dir: &Path) -> io::Result<Vec<u8>> {
+    let output = Command::new("git")
+        .args(["upload-pack", "--stateless-rpc", "--advertise-refs"])
+        .arg(git_dir)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+pub(crate) async fn info_refs(State(git_dir): State<Arc<Path>>, uri: Uri) -> Response {
+    // Pushing would need git-receive-pack, and dumb http clients don't ask for a service
+    let upload_pack = uri.query().is_some_and(|query| {
+        query
+            .split('&')
+            .any(|pair| pair == "service=git-upload-pack")
+    });
+    if !upload_pack {
+        return (StatusCode::FORBIDDEN, "403 Forbidden").into_response();
+    }
+    match advertise_refs(&git_dir).await {
+        Ok(refs) => {
+            let mut body = b"001e# service=git-upload-pack\n0000".to_vec();
+            body.extend(refs);
+            (
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        "application/x-git-upload-pack-advertisement",
+                    ),
+                    (header::CACHE_CONTROL, "no-cache"),
+                ],
+                body,
+            )
+                .into_response()
+        }
+        Err(error) => {
+            tracing::error!("Can't list the refs of the repository: {}", error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub(crate) async fn upload_pack(
+    State(git_dir): State<Arc<Path>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let Ok(request) = to_bytes(body, MAX_REQUEST_SIZE as usize).await else {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "413 Payload Too Large").into_response();
+    };
+    // Git compresses larger requests
+    let gzipped = headers
+        .get(header::CONTENT_ENCODING)
+        .is_some_and(|encoding| encoding == "gzip");
+    let request = match gzipped {
+        true => {
+            let mut decompressed = Vec::new();
+            let mut decoder = GzDecoder::new(&request[..]).take(MAX_REQUEST_SIZE + 1);
+            if decoder.read_to_end(&mut decompressed).is_err() {
+                return (StatusCode::BAD_REQUEST, "400 Bad Request").into_response();
+            }
+            if decompressed.len() as u64 > MAX_REQUEST_SIZE {
+                return (StatusCode::PAYLOAD_TOO_LARGE, "413 Payload Too Large").into_response();
+            }
+            decompressed
+        }
+        false => request.to_vec(),
+    };
+
+    let mut child = match Command::new("git")
+        .args(["upload-pack", "--stateless-rpc"])
+        .arg(&*git_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(error) => {
+            tracing::error!("Can't start git upload-pack: {}", error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    // Writing the request while the pack is read keeps both pipes from filling up
+    tokio::spawn(async move {
+        stdin.write_all(&request).await.ok();
+    });
+    // A failed pack ends the body with an error, so it isn't mistaken for a delivery. If the
+    // client goes away, git gets a broken pipe and exits on its own
+    let exit_status = async move {
+        match child.wait().await {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(Err(io::Error::other(format!(
+                "git upload-pack failed: {}",
+                status
+            )))),
+            Err(error) => Some(Err(error)),
+        }
+    };
+    let pack = ReaderStream::new(stdout).chain(stream::once(exit_status).filter_map(future::ready));
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-git-upload-pack-result"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        Body::from_stream(pack),
+    )
+        .into_response()
+}
@@ -0,0 +1,145 @@
+//! Templated HTTP calls fired on events, e.g. to update a ticket when the secret was fetched.
+//!
+//! Templates can contain `{name}` placeholders for the values passed when firing a hook,
+//! and `{env:NAME}` placeholders for environment variables, e.g. to pass credentials.
+
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+#[derive(Debug, Clone)]
+pub struct HttpHook {
+    url: String,
+    body: Option<String>,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpHook {
+    pub fn new(url: impl Into<String>) -> Self {
+        HttpHook {
+            url: url.into(),
+            body: None,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Send this body with a POST request, otherwise a GET request is sent.
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Add a header, given as `Name: value`.
+    pub fn header(mut self, header: &str) -> Result<Self, String> {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| format!("Header must look like 'Name: value': {}", header))?;
+        self.headers
+            .push((name.trim().to_string(), value.trim().to_string()));
+        Ok(self)
+    }
+
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub(crate) fn request(
+        &self,
+        client: &reqwest::Client,
+        values: &[(&str, String)],
+    ) -> reqwest::RequestBuilder {
+        let url = render_template(&self.url, values);
+        let mut request = match &self.body {
+            Some(body) => client.post(url).body(render_template(body, values)),
+            None => client.get(url),
+        };
+        for (name, value) in &self.headers {
+            request = request.header(name, render_template(value, values));
+        }
+        request
+    }
+}
+
+pub(crate) fn render_template(template: &str, values: &[(&str, String)]) -> String {
+    let lookup = |name: &str| match name.strip_prefix("env:") {
+        Some(variable) => Some(env::var(variable).unwrap_or_default()),
+        None => values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.clone()),
+    };
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let name = rest[1..]
+            .find('}')
+            .map(|end| &rest[1..=end])
+            .filter(|name| {
+                name.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-.:".contains(c))
+            });
+        match name.and_then(|name| Some((name, lookup(name)?))) {
+            Some((name, value)) => {
+                rendered.push_str(&value);
+                rest = &rest[name.len() + 2..];
+            }
+            // Leave anything else alone, e.g. the braces of a JSON body
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Sends hook requests in the background and keeps track of them until they are finished.
+#[derive(Clone, Default)]
+pub struct HookRunner {
+    client: reqwest::Client,
+    requests: Arc<Mutex<JoinSet<()>>>,
+}
+
+impl HookRunner {
+    pub fn fire(&self, name: &'static str, hook: &HttpHook, values: &[(&str, String)]) {
+        self.send(name, hook.request(&self.client, values));
+    }
+
+    /// POST a JSON body as is, without rendering any placeholders.
+    pub fn post_json(&self, name: &'static str, url: &str, body: String) {
+        let request = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        self.send(name, request);
+    }
+
+    fn send(&self, name: &'static str, request: reqwest::RequestBuilder) {
+        self.requests.lock().unwrap().spawn(async move {
+            match request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+            {
+                Ok(_) => {}
+                Err(error) => tracing::warn!("The {} failed: {}", name, error),
+            }
+        });
+    }
+
+    /// Waits for all requests fired so far, but at most for `timeout`.
+    pub async fn finish(&self, timeout: Duration) {
+        let requests = std::mem::take(&mut *self.requests.lock().unwrap());
+        if tokio::time::timeout(timeout, requests.join_all())
+            .await
+            .is_err()
+        {
+            tracing::warn!("Not all hooks finished in time");
+        }
+    }
+}
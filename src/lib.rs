@@ -0,0 +1,3169 @@
+//! Share a secret a limited number of times via a local http server.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), localsecret::Error> {
+//! let handle = localsecret::Share::builder()
+//!     .file("secret.txt")
+//!     .uses(1)
+//!     .expire_after(std::time::Duration::from_secs(600))
+//!     .serve()
+//!     .await?;
+//! println!("{}", handle.urls()[0]);
+//! handle.wait().await
+//! # }
+//! ```
+
+use axum::{
+    Router,
+    body::{Body, Bytes, HttpBody},
+    extract::{ConnectInfo, OriginalUri, Request, State, connect_info::Connected},
+    http::{Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    serve::{IncomingStream, Listener},
+};
+use http::{HeaderMap, HeaderName, HeaderValue, header};
+use http_body::{Frame, SizeHint};
+use ipnet::IpNet;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::{self, Debug, Display};
+use std::fs::File;
+use std::future::IntoFuture;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::task::{Context, Poll, ready};
+use std::time::{Duration, Instant, SystemTime};
+use subtle::{Choice, ConstantTimeEq};
+use tokio::{
+    sync::{Mutex, mpsc, watch},
+    task::JoinSet,
+};
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::services::ServeFile;
+use tower_http::set_header::SetResponseHeaderLayer;
+
+mod buffer;
+mod capture;
+mod cloud_init;
+pub mod control;
+mod cors;
+pub mod enrollment;
+mod events;
+pub mod exposure;
+mod file_watch;
+mod git;
+pub mod hooks;
+pub mod network;
+mod package;
+mod proxy;
+mod rate_limit;
+pub mod simulate;
+mod token;
+
+use buffer::{Buffer, SpooledFile};
+use capture::Capture;
+use enrollment::{EnrollmentKey, EnrollmentState, require_enrollment};
+use events::EventHooks;
+pub use events::{Event, Progress, ShutdownReason};
+use file_watch::FileWatch;
+use hooks::HttpHook;
+use network::{Peer, create_listener, format_url, is_publicly_routable};
+use package::{Package, PackageIndex};
+use proxy::Upstream;
+use rate_limit::{RateLimit, RateLimiter, limit_rate};
+pub use token::TokenStyle;
+
+#[derive(Debug)]
+pub enum Error {
+    NoSecret,
+    SecretFileNotFound(PathBuf),
+    AbsolutePath(PathBuf, io::Error),
+    FileName(PathBuf),
+    ReadSecret(PathBuf, io::Error),
+    PublicAddress(IpAddr),
+    LocalIp(local_ip_address::Error),
+    NoTailnetAddress,
+    Bind(io::Error),
+    UnixSocketExists(PathBuf),
+    UnixSocketBind(PathBuf, io::Error),
+    Mdns(mdns_sd::Error),
+    HostName(OsString),
+    SshSpawn(io::Error),
+    SshTunnel(String),
+    UpnpIpv4Only,
+    UpnpGateway(igd_next::SearchError),
+    UpnpMapping(igd_next::AddAnyPortError),
+    UpnpRemove(SocketAddr, igd_next::RemovePortError),
+    HostsFile(PathBuf, io::Error),
+    GitRepository(PathBuf, String),
+    Package(PathBuf, String),
+    Upstream(String, String),
+    Capture(PathBuf, io::Error),
+    SecretChanged(PathBuf),
+    ControlSocket(String, io::Error),
+    Control(String),
+    Server(io::Error),
+    Panicked,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoSecret => write!(f, "No secret file or content to share"),
+            Error::SecretFileNotFound(file_path) => write!(
+                f,
+                "The provided secret file doesn't exist or is not a file: {:?}",
+                file_path
+            ),
+            Error::AbsolutePath(file_path, error) => write!(
+                f,
+                "Can't determine absolute path of '{:?}': {:#?}",
+                file_path, error
+            ),
+            Error::FileName(file_path) => {
+                write!(f, "Can't determine file name from: {:#?}", file_path)
+            }
+            Error::ReadSecret(file_path, error) => {
+                write!(f, "Can't read secret file {:?}: {:#?}", file_path, error)
+            }
+            Error::PublicAddress(ip) => write!(
+                f,
+                "Refusing to bind to {}, as it is a publicly routable address and the secret could be fetched from outside the local network. Pass --allow-public if this is intended",
+                ip
+            ),
+            Error::LocalIp(error) => write!(f, "Can't determine local ip: {:#?}", error),
+            Error::NoTailnetAddress => {
+                write!(f, "Can't find a tailnet address, is tailscale running?")
+            }
+            Error::Bind(error) => write!(f, "Can't bind to local address: {:#?}", error),
+            Error::UnixSocketExists(socket_path) => {
+                write!(f, "The unix socket path already exists: {:?}", socket_path)
+            }
+            Error::UnixSocketBind(socket_path, error) => write!(
+                f,
+                "Can't bind to unix socket {:?}: {:#?}",
+                socket_path, error
+            ),
+            Error::Mdns(error) => write!(f, "Can't advertise via mDNS: {:#?}", error),
+            Error::HostName(host_name) => write!(f, "Can't decode host name: {:#?}", host_name),
+            Error::SshSpawn(error) => write!(f, "Can't start ssh: {:#?}", error),
+            Error::SshTunnel(destination) => write!(
+                f,
+                "The SSH tunnel to {} couldn't be established",
+                destination
+            ),
+            Error::UpnpIpv4Only => write!(
+                f,
+                "UPnP port mappings are only supported for IPv4 addresses"
+            ),
+            Error::UpnpGateway(error) => write!(f, "Can't find a UPnP gateway: {:#?}", error),
+            Error::UpnpMapping(error) => {
+                write!(f, "Can't add a UPnP port mapping: {:#?}", error)
+            }
+            Error::UpnpRemove(external_address, error) => write!(
+                f,
+                "Can't remove the UPnP port mapping for {}, please remove it on the router: {:#?}",
+                external_address, error
+            ),
+            Error::HostsFile(hosts_file, error) => {
+                write!(f, "Can't update hosts file {:?}: {:#?}", hosts_file, error)
+            }
+            Error::GitRepository(repository, error) => {
+                write!(f, "Can't share git repository {:?}: {}", repository, error)
+            }
+            Error::Package(file_path, error) => {
+                write!(f, "Can't share package {:?}: {}", file_path, error)
+            }
+            Error::Upstream(url, error) => {
+                write!(f, "Can't proxy upstream url {}: {}", url, error)
+            }
+            Error::Capture(file_path, error) => {
+                write!(f, "Can't create capture file {:?}: {:#?}", file_path, error)
+            }
+            Error::SecretChanged(file_path) => write!(
+                f,
+                "The secret file {:?} changed while it was shared, it was no longer served",
+                file_path
+            ),
+            Error::ControlSocket(address, error) => {
+                write!(f, "Can't use control socket {}: {:#?}", address, error)
+            }
+            Error::Control(error) => write!(f, "The share refused the command: {}", error),
+            Error::Server(error) => write!(f, "The server failed: {:#?}", error),
+            Error::Panicked => write!(f, "Something panicked, the share was stopped"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// What is being shared.
+#[derive(Debug, Clone)]
+pub enum Secret {
+    File(PathBuf),
+    Content(Bytes),
+    /// Content that was too large for memory and was written to this file, it is served like
+    /// content, without the file name in the url. The file is only read through this handle,
+    /// so it can be deleted right after it was created.
+    SpooledContent(Arc<File>),
+    /// A git repository, served read-only for `git clone`.
+    GitRepository(PathBuf),
+    /// A Python wheel or source distribution, served as package index for `pip install`.
+    PythonPackage(PathBuf),
+    /// A user data file, served with generated meta data for cloud-init's NoCloud datasource.
+    CloudInit(PathBuf),
+    /// Fetched with this request whenever the secret is requested, and passed on as is.
+    Upstream(HttpHook),
+}
+
+/// Appended to the url of the secret for its checksum, see [`ShareBuilder::checksum_url`].
+const CHECKSUM_SUFFIX: &str = ".sha256";
+
+/// Request bodies larger than this get 413, unless configured otherwise.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Headers on the responses with the secret, so neither caches nor search engines keep it.
+const SECURITY_HEADERS: [(HeaderName, &str); 4] = [
+    (header::CACHE_CONTROL, "no-store"),
+    (HeaderName::from_static("x-robots-tag"), "noindex"),
+    (header::REFERRER_POLICY, "no-referrer"),
+    (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+];
+
+/// Paths browsers and crawlers request on their own, which shouldn't count as failed attempts.
+pub const DEFAULT_IGNORED_PATHS: [&str; 4] = [
+    "/favicon.ico",
+    "/apple-touch-icon.png",
+    "/robots.txt",
+    "/.well-known/*",
+];
+
+/// The configuration of a share, create it with [`Share::builder`].
+#[derive(Debug, Clone)]
+pub struct Share {
+    secret: Option<Secret>,
+    url_prefix_length: u16,
+    token_style: TokenStyle,
+    token_length: Option<u16>,
+    case_insensitive: bool,
+    uses: u16,
+    failed_attempts: u16,
+    lock_out_after: Option<u16>,
+    tarpit: Option<Duration>,
+    rate_limit: Option<RateLimit>,
+    max_body_size: usize,
+    ignored_paths: Vec<String>,
+    expire_after: Option<Duration>,
+    retry_window: Option<Duration>,
+    lock_first_client: bool,
+    afterlife: Option<Duration>,
+    recipients: Vec<Recipient>,
+    drain_timeout: Duration,
+    release_at: Option<SystemTime>,
+    bind_ips: Vec<IpAddr>,
+    allowed_networks: Vec<IpNet>,
+    denied_networks: Vec<IpNet>,
+    cors_origins: Vec<String>,
+    capture: Option<(PathBuf, bool)>,
+    allow_public: bool,
+    display_host: Option<String>,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
+    default_charset: String,
+    in_memory: bool,
+    abort_on_change: bool,
+    checksum_url: bool,
+    name: Option<String>,
+    hide_file_name: bool,
+    download: bool,
+    download_name: Option<String>,
+    response_headers: Vec<(HeaderName, HeaderValue)>,
+    content_type: Option<HeaderValue>,
+    security_headers: bool,
+    labels: Vec<(String, String)>,
+    approval: Option<Approval>,
+    enrollment_key: Option<EnrollmentKey>,
+    click_through: bool,
+    instructions_page: bool,
+    check_host: bool,
+    event_hooks: EventHooks,
+}
+
+/// A recipient with a link of their own, see [`ShareBuilder::recipient`].
+#[derive(Debug, Clone)]
+struct Recipient {
+    name: String,
+    uses: u16,
+    expire_after: Option<Duration>,
+}
+
+impl Default for Share {
+    fn default() -> Self {
+        Share {
+            secret: None,
+            url_prefix_length: 42,
+            token_style: TokenStyle::default(),
+            token_length: None,
+            case_insensitive: false,
+            uses: 1,
+            failed_attempts: 3,
+            lock_out_after: None,
+            tarpit: None,
+            rate_limit: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            ignored_paths: DEFAULT_IGNORED_PATHS.map(String::from).to_vec(),
+            expire_after: None,
+            retry_window: None,
+            lock_first_client: false,
+            afterlife: None,
+            recipients: Vec::new(),
+            drain_timeout: Duration::from_secs(30),
+            release_at: None,
+            bind_ips: Vec::new(),
+            allowed_networks: Vec::new(),
+            denied_networks: Vec::new(),
+            cors_origins: Vec::new(),
+            capture: None,
+            allow_public: false,
+            display_host: None,
+            #[cfg(unix)]
+            unix_socket: None,
+            default_charset: "utf-8".to_string(),
+            in_memory: false,
+            abort_on_change: false,
+            checksum_url: false,
+            download: false,
+            name: None,
+            hide_file_name: false,
+            download_name: None,
+            response_headers: Vec::new(),
+            content_type: None,
+            security_headers: true,
+            labels: Vec::new(),
+            approval: None,
+            enrollment_key: None,
+            click_through: false,
+            instructions_page: false,
+            check_host: true,
+            event_hooks: EventHooks::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ShareBuilder {
+    share: Share,
+}
+
+impl ShareBuilder {
+    /// Share the content of a file.
+    pub fn file(mut self, file_path: impl Into<PathBuf>) -> Self {
+        self.share.secret = Some(Secret::File(file_path.into()));
+        self
+    }
+
+    /// Share content that was written to a file, e.g. a large input, like [`ShareBuilder::content`].
+    /// The file is read from its start through the handle only, it might be deleted already.
+    pub fn spooled_content(mut self, file: File) -> Self {
+        self.share.secret = Some(Secret::SpooledContent(Arc::new(file)));
+        self
+    }
+
+    /// Share a git repository, so it can be cloned via the url.
+    pub fn git_repository(mut self, repository: impl Into<PathBuf>) -> Self {
+        self.share.secret = Some(Secret::GitRepository(repository.into()));
+        self
+    }
+
+    /// Share a Python package file, so it can be installed with pip from the index url.
+    pub fn python_package(mut self, file_path: impl Into<PathBuf>) -> Self {
+        self.share.secret = Some(Secret::PythonPackage(file_path.into()));
+        self
+    }
+
+    /// Share a user data file with cloud-init, the url is the seed of the NoCloud datasource.
+    pub fn cloud_init(mut self, file_path: impl Into<PathBuf>) -> Self {
+        self.share.secret = Some(Secret::CloudInit(file_path.into()));
+        self
+    }
+
+    /// Fetch the secret from upstream only when the recipient requests it, e.g. an artifact
+    /// behind credentials that stay on this machine. Placeholders in the request can only
+    /// refer to environment variables.
+    pub fn proxy(mut self, upstream: HttpHook) -> Self {
+        self.share.secret = Some(Secret::Upstream(upstream));
+        self
+    }
+
+    /// Share some content, e.g. read from stdin. It is served as text if it is valid UTF-8.
+    pub fn content(mut self, content: impl Into<Bytes>) -> Self {
+        self.share.secret = Some(Secret::Content(content.into()));
+        self
+    }
+
+    /// Length of the randomly generated url prefix.
+    pub fn url_prefix_length(mut self, url_prefix_length: u16) -> Self {
+        self.share.url_prefix_length = url_prefix_length;
+        self
+    }
+
+    /// How the url prefix is written, its entropy stays that of `url_prefix_length` letters
+    /// and digits.
+    pub fn token_style(mut self, token_style: TokenStyle) -> Self {
+        self.share.token_style = token_style;
+        self
+    }
+
+    /// The number of characters, or words, of the url prefix in its [`TokenStyle`], instead
+    /// of as many as are as hard to guess as `url_prefix_length` letters and digits.
+    pub fn token_length(mut self, token_length: u16) -> Self {
+        self.share.token_length = Some(token_length);
+        self
+    }
+
+    /// Generate the url prefix in lowercase, longer to be as hard to guess, and match the token
+    /// and the file name in the url regardless of case, for recipients typing the url.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.share.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// How often the shared url can be used before the server stops.
+    pub fn uses(mut self, uses: u16) -> Self {
+        self.share.uses = uses;
+        self
+    }
+
+    /// How often an invalid url can be requested before the server stops.
+    pub fn failed_attempts(mut self, failed_attempts: u16) -> Self {
+        self.share.failed_attempts = failed_attempts;
+        self
+    }
+
+    /// Count invalid urls per client IP address and answer a client with 403 after this many,
+    /// instead of stopping the server after [`ShareBuilder::failed_attempts`].
+    ///
+    /// A noisy scanner then only locks itself out, the share stops once the uses are exhausted.
+    pub fn lock_out_after(mut self, failed_attempts: u16) -> Self {
+        self.share.lock_out_after = Some(failed_attempts);
+        self
+    }
+
+    /// Delay the response to an invalid url, by this duration for every failed attempt the
+    /// client made so far. This slows down guessing the url without affecting the recipient.
+    pub fn tarpit(mut self, tarpit: Duration) -> Self {
+        self.share.tarpit = Some(tarpit);
+        self
+    }
+
+    /// Answer this path with 404 without counting a failed attempt, in addition to
+    /// [`DEFAULT_IGNORED_PATHS`]. A trailing `*` matches any rest of the path.
+    pub fn ignore_path(mut self, path: impl Into<String>) -> Self {
+        self.share.ignored_paths.push(path.into());
+        self
+    }
+
+    /// Answer at most `requests` requests `per` duration and client IP address, with bursts
+    /// up to `requests`. Further requests get 429 and don't count as failed attempts.
+    pub fn rate_limit(mut self, requests: u32, per: Duration) -> Self {
+        self.share.rate_limit = Some(RateLimit { requests, per });
+        self
+    }
+
+    /// Turn away requests with bodies larger than this many bytes with 413, before any handler
+    /// reads them. Defaults to [`DEFAULT_MAX_BODY_SIZE`].
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.share.max_body_size = max_body_size;
+        self
+    }
+
+    /// Stop the server after this duration, even if uses are left.
+    pub fn expire_after(mut self, expire_after: Duration) -> Self {
+        self.share.expire_after = Some(expire_after);
+        self
+    }
+
+    /// Let a client fetch the secret again within this duration after it did, without
+    /// consuming another use, e.g. after a flaky connection.
+    ///
+    /// Clients are told apart by their IP address. Once the uses are used up, the server keeps
+    /// running for this duration, only for these retries.
+    pub fn retry_window(mut self, retry_window: Duration) -> Self {
+        self.share.retry_window = Some(retry_window);
+        self
+    }
+
+    /// Only let the client that fetched the secret first use the remaining uses, everyone
+    /// else gets 403. Clients are told apart by their IP address.
+    pub fn lock_first_client(mut self, lock_first_client: bool) -> Self {
+        self.share.lock_first_client = lock_first_client;
+        self
+    }
+
+    /// After the share ended, keep answering on the same addresses for this duration, with a
+    /// page saying when it ended.
+    ///
+    /// Late attempts are reported as [`Event::LateAttempt`]. Nothing of the secret is kept,
+    /// stopping the share once more ends the afterlife early. Not available on unix sockets.
+    pub fn afterlife(mut self, afterlife: Duration) -> Self {
+        self.share.afterlife = Some(afterlife);
+        self
+    }
+
+    /// Give a recipient a link of their own, which can be used `uses` times and expires
+    /// after `expire_after`, if given.
+    ///
+    /// With recipients the shared url itself doesn't work, the server stops once the links of
+    /// all recipients are used up or expired.
+    pub fn recipient(
+        mut self,
+        name: impl Into<String>,
+        uses: u16,
+        expire_after: Option<Duration>,
+    ) -> Self {
+        self.share.recipients.push(Recipient {
+            name: name.into(),
+            uses,
+            expire_after,
+        });
+        self
+    }
+
+    /// How long [`ShareHandle::wait`] waits for running responses to finish after the shutdown.
+    ///
+    /// No new connections are accepted in the meantime. The CLI exits afterwards, which cuts
+    /// off anything still running.
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.share.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Only serve the secret after this time, earlier requests get a countdown page.
+    pub fn release_at(mut self, release_at: SystemTime) -> Self {
+        self.share.release_at = Some(release_at);
+        self
+    }
+
+    /// Add an IP address to listen on. If none is added, the local IP address is used.
+    pub fn bind_ip(mut self, ip: IpAddr) -> Self {
+        self.share.bind_ips.push(ip);
+        self
+    }
+
+    /// Only answer clients from this network, others get 403. Can be called multiple times.
+    ///
+    /// If none is added, clients from anywhere are answered. Clients connecting via a unix
+    /// socket have no address and are always answered.
+    pub fn allow_network(mut self, network: IpNet) -> Self {
+        self.share.allowed_networks.push(network);
+        self
+    }
+
+    /// Let web pages from this origin read the secret, e.g. `https://tools.example.com`. Can
+    /// be called multiple times.
+    pub fn cors_origin(mut self, origin: &str) -> Self {
+        self.share.cors_origins.push(cors::normalize_origin(origin));
+        self
+    }
+
+    /// Turn away clients from this network with 403, even if it is part of an allowed one.
+    /// Can be called multiple times.
+    pub fn deny_network(mut self, network: IpNet) -> Self {
+        self.share.denied_networks.push(network);
+        self
+    }
+
+    /// Allow listening on a publicly routable address.
+    pub fn allow_public(mut self, allow_public: bool) -> Self {
+        self.share.allow_public = allow_public;
+        self
+    }
+
+    /// Host to show in the urls instead of the bound IP address.
+    pub fn display_host(mut self, display_host: impl Into<String>) -> Self {
+        self.share.display_host = Some(display_host.into());
+        self
+    }
+
+    /// Listen on a unix domain socket instead of TCP.
+    #[cfg(unix)]
+    pub fn unix_socket(mut self, socket_path: impl Into<PathBuf>) -> Self {
+        self.share.unix_socket = Some(socket_path.into());
+        self
+    }
+
+    /// Charset added to the content type of text files.
+    pub fn default_charset(mut self, default_charset: impl Into<String>) -> Self {
+        self.share.default_charset = default_charset.into();
+        self
+    }
+
+    /// Read the secret file into memory on start, so it can be deleted or changed meanwhile.
+    pub fn in_memory(mut self, in_memory: bool) -> Self {
+        self.share.in_memory = in_memory;
+        self
+    }
+
+    /// Stop with [`Error::SecretChanged`] if the shared file changes on disk while it is shared,
+    /// instead of serving the changed file. Has no effect on files read into memory.
+    pub fn abort_on_change(mut self, abort_on_change: bool) -> Self {
+        self.share.abort_on_change = abort_on_change;
+        self
+    }
+
+    /// Also serve the [`ShareHandle::checksum`] at the url of a file or content with `.sha256`
+    /// appended, in the format of `sha256sum`. Fetching it is free, but it is held back
+    /// like the secret, e.g. until [`release_at`](Self::release_at).
+    pub fn checksum_url(mut self, checksum_url: bool) -> Self {
+        self.share.checksum_url = checksum_url;
+        self
+    }
+
+    /// Add a header to the responses with the secret, given as `Name: value`.
+    ///
+    /// It replaces a header of the same name the response would have had otherwise, giving the
+    /// same name multiple times sends all values.
+    pub fn response_header(mut self, header: &str) -> Result<Self, String> {
+        let invalid = || format!("Header must look like 'Name: value': {}", header);
+        let (name, value) = header.split_once(':').ok_or_else(invalid)?;
+        let name = HeaderName::try_from(name.trim()).map_err(|_| invalid())?;
+        let value = HeaderValue::try_from(value.trim()).map_err(|_| invalid())?;
+        self.share.response_headers.push((name, value));
+        Ok(self)
+    }
+
+    /// The file name in the url instead of the real one, which might tell more than the secret
+    /// should, e.g. in logs and browser histories. Content gets a file name in the url this way.
+    pub fn name(mut self, name: &str) -> Result<Self, String> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(format!("Name must be a file name without a path: {}", name));
+        }
+        self.share.name = Some(name.to_string());
+        Ok(self)
+    }
+
+    /// Leave the file name out of the url, it is only sent in the `Content-Disposition` header
+    /// then. Browsers still save the secret under its name, but the url tells nothing about it.
+    pub fn hide_file_name(mut self, hide_file_name: bool) -> Self {
+        self.share.hide_file_name = hide_file_name;
+        self
+    }
+
+    /// Ask browsers to save a file or content instead of showing it, named like the file in
+    /// the url. This keeps keys from being copied from the browser incompletely.
+    pub fn download(mut self, download: bool) -> Self {
+        self.share.download = download;
+        self
+    }
+
+    /// The name browsers save the secret under, this implies [`ShareBuilder::download`].
+    pub fn download_name(mut self, file_name: impl Into<String>) -> Self {
+        self.share.download = true;
+        self.share.download_name = Some(file_name.into());
+        self
+    }
+
+    /// Serve the secret with this content type instead of the guessed one, e.g.
+    /// `application/json`. It replaces a `Content-Type` given as response header, whether that
+    /// was added before or after.
+    pub fn content_type(mut self, content_type: &str) -> Result<Self, String> {
+        let value = HeaderValue::try_from(content_type.trim())
+            .ok()
+            .filter(|_| content_type.contains('/'))
+            .ok_or_else(|| {
+                format!(
+                    "Content type must look like 'type/subtype': {}",
+                    content_type
+                )
+            })?;
+        self.share.content_type = Some(value);
+        Ok(self)
+    }
+
+    /// Record the requests and responses in a HAR file, without bodies and with the token
+    /// redacted. Headers that might carry credentials are left out unless `all_headers` is
+    /// set, their values are redacted then.
+    pub fn capture(mut self, file_path: impl Into<PathBuf>, all_headers: bool) -> Self {
+        self.share.capture = Some((file_path.into(), all_headers));
+        self
+    }
+
+    /// Send `Cache-Control: no-store`, `X-Robots-Tag: noindex`, `Referrer-Policy: no-referrer`
+    /// and `X-Content-Type-Options: nosniff` with the secret, which is the default.
+    ///
+    /// Headers added with [`ShareBuilder::response_header`] replace these.
+    pub fn security_headers(mut self, security_headers: bool) -> Self {
+        self.share.security_headers = security_headers;
+        self
+    }
+
+    /// Attach metadata to the share, e.g. a ticket number, to correlate it in other systems.
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.share.labels.push((key.into(), value.into()));
+        self
+    }
+
+    /// Ask `approve` with the peer address and user agent before every fetch, it may block,
+    /// e.g. to ask in the terminal.
+    pub fn approve_with(
+        mut self,
+        approve: impl Fn(Option<SocketAddr>, Option<&str>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.share.approval = Some(Approval(Arc::new(approve)));
+        self
+    }
+
+    /// Only serve clients that prove knowledge of `key`, see [`enrollment`] for the protocol.
+    pub fn enrollment_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.share.enrollment_key = Some(EnrollmentKey(key.into().into()));
+        self
+    }
+
+    /// Answer fetches with a page that reveals the secret with a button, i.e. a POST request.
+    ///
+    /// Link previews and prefetchers only follow links, so they can't consume a use.
+    pub fn click_through(mut self, click_through: bool) -> Self {
+        self.share.click_through = click_through;
+        self
+    }
+
+    /// Serve a page on `/` asking to get the full link from the sender, instead of counting
+    /// it as failed attempt.
+    pub fn instructions_page(mut self, instructions_page: bool) -> Self {
+        self.share.instructions_page = instructions_page;
+        self
+    }
+
+    /// Only answer requests whose `Host` header names one of the urls, which is the default.
+    ///
+    /// This keeps a web page from reading the secret via DNS rebinding. Urls that reach the
+    /// share in other ways, e.g. through a tunnel, can be added with [`ShareHandle::allow_url`].
+    pub fn check_host(mut self, check_host: bool) -> Self {
+        self.share.check_host = check_host;
+        self
+    }
+
+    /// Call `hook` for every [`Event`] of the share, it must not block.
+    pub fn on_event(mut self, hook: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        self.share.event_hooks.push(hook);
+        self
+    }
+
+    /// Call `hook` with the [`Progress`] of every download a few times a second and once more
+    /// when it ended, it must not block.
+    pub fn on_progress(mut self, hook: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        self.share.event_hooks.push_progress(hook);
+        self
+    }
+
+    pub fn build(self) -> Share {
+        self.share
+    }
+
+    pub async fn serve(self) -> Result<ShareHandle, Error> {
+        self.share.serve().await
+    }
+}
+
+/// A running share, returned by [`Share::serve`].
+pub struct ShareHandle {
+    url_path: String,
+    urls: Vec<String>,
+    local_addresses: Vec<SocketAddr>,
+    checksum: Option<String>,
+    labels: Vec<(String, String)>,
+    recipients: Vec<RecipientLink>,
+    maximum_uses: Arc<AtomicU16>,
+    use_count: Arc<std::sync::Mutex<UseCount>>,
+    failed_attempts: Arc<Mutex<u16>>,
+    maximum_failed_attempts: Option<u16>,
+    deadline: Deadline,
+    file_watch: Option<FileWatch>,
+    shutdown_sender: mpsc::Sender<ShutdownReason>,
+    servers: JoinSet<io::Result<()>>,
+    allowed_hosts: AllowedHosts,
+    afterlife: Option<Duration>,
+    afterlife_stop: watch::Receiver<()>,
+    redacted_tokens: Arc<[Arc<str>]>,
+    event_hooks: EventHooks,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
+}
+
+/// Removes the unix socket, also when the handle is dropped while unwinding from a panic.
+impl Drop for ShareHandle {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(socket_path) = &self.unix_socket {
+            std::fs::remove_file(socket_path).ok();
+        }
+    }
+}
+
+/// The own link of a recipient, see [`ShareBuilder::recipient`].
+#[derive(Debug, Clone)]
+pub struct RecipientLink {
+    pub name: String,
+    pub url_path: String,
+    pub urls: Vec<String>,
+}
+
+/// Stops a running share, can be cloned and moved into other tasks.
+#[derive(Clone)]
+pub struct ShutdownTrigger {
+    shutdown_sender: mpsc::Sender<ShutdownReason>,
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        self.send(ShutdownReason::Triggered);
+    }
+
+    /// Stops the share because something panicked that the share doesn't know about, e.g. a
+    /// task of the embedder.
+    pub fn trigger_panicked(&self) {
+        self.send(ShutdownReason::Panicked);
+    }
+
+    fn send(&self, reason: ShutdownReason) {
+        // If the channel is full, the shutdown is already on its way
+        self.shutdown_sender.try_send(reason).ok();
+    }
+}
+
+/// Changes the number of uses of a running share, can be cloned and moved into other tasks.
+#[derive(Clone)]
+pub struct UseLimit {
+    maximum_uses: Arc<AtomicU16>,
+}
+
+impl UseLimit {
+    pub fn maximum(&self) -> u16 {
+        self.maximum_uses.load(Ordering::SeqCst)
+    }
+
+    /// Allow more uses, this has no effect once the uses are used up and the share stopped.
+    pub fn add(&self, uses: u16) {
+        self.maximum_uses
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |maximum| {
+                Some(maximum.saturating_add(uses))
+            })
+            .ok();
+    }
+}
+
+impl ShareHandle {
+    /// The path of the secret, without scheme and host.
+    pub fn url_path(&self) -> &str {
+        &self.url_path
+    }
+
+    /// Also answer requests for the host of this url, e.g. of a tunnel to the share.
+    pub fn allow_url(&self, url: &str) {
+        self.allowed_hosts.allow_url(url);
+    }
+
+    /// The links of the recipients, empty unless the share has recipients.
+    pub fn recipients(&self) -> &[RecipientLink] {
+        &self.recipients
+    }
+
+    /// The urls the secret can be fetched from, with recipients those of all their links.
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    /// The addresses of the TCP listeners.
+    pub fn local_addresses(&self) -> &[SocketAddr] {
+        &self.local_addresses
+    }
+
+    /// The hex encoded SHA-256 checksum of the secret, unless it is only fetched on request.
+    pub fn checksum(&self) -> Option<&str> {
+        self.checksum.as_deref()
+    }
+
+    /// The labels attached to the share.
+    pub fn labels(&self) -> &[(String, String)] {
+        &self.labels
+    }
+
+    pub fn use_limit(&self) -> UseLimit {
+        UseLimit {
+            maximum_uses: self.maximum_uses.clone(),
+        }
+    }
+
+    pub fn shutdown_trigger(&self) -> ShutdownTrigger {
+        ShutdownTrigger {
+            shutdown_sender: self.shutdown_sender.clone(),
+        }
+    }
+
+    /// Commands to adjust the share while it runs, e.g. for a control socket.
+    pub fn controller(&self) -> control::Controller {
+        control::Controller {
+            use_count: self.use_count.clone(),
+            use_limit: self.use_limit(),
+            failed_attempts: self.failed_attempts.clone(),
+            maximum_failed_attempts: self.maximum_failed_attempts,
+            deadline: self.deadline.clone(),
+            shutdown_trigger: self.shutdown_trigger(),
+            labels: self.labels.clone(),
+        }
+    }
+
+    /// Waits until all uses or failed attempts are used up, the share expired or it was stopped.
+    ///
+    /// With an afterlife, it waits for that to end as well.
+    pub async fn wait(mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        while let Some(server_result) = self.servers.join_next().await {
+            match server_result {
+                Ok(Ok(())) => {}
+                Ok(Err(error)) => result = Err(Error::Server(error)),
+                Err(_) => result = Err(Error::Panicked),
+            }
+        }
+        if let Some(file_watch) = &self.file_watch
+            && file_watch.changed()
+        {
+            result = Err(Error::SecretChanged(file_watch.file_path()));
+        }
+        if let Some(afterlife) = self.afterlife
+            && result.is_ok()
+            && !self.local_addresses.is_empty()
+        {
+            self.serve_afterlife(afterlife).await;
+        }
+        result
+    }
+
+    /// The secret is gone with the servers, the new listeners only know about the end.
+    async fn serve_afterlife(&mut self, afterlife: Duration) {
+        let router = Router::new().fallback(late_attempt).with_state(Afterlife {
+            ended_at: SystemTime::now(),
+            redacted_tokens: self.redacted_tokens.clone(),
+            event_hooks: self.event_hooks.clone(),
+        });
+        let (stop_sender, stop_receiver) = watch::channel(());
+        let mut servers = JoinSet::new();
+        for address in &self.local_addresses {
+            match tokio::net::TcpListener::bind(address).await {
+                Ok(listener) => spawn_server(
+                    &mut servers,
+                    listener,
+                    router.clone(),
+                    stop_receiver.clone(),
+                    Duration::ZERO,
+                ),
+                Err(error) => {
+                    tracing::warn!(
+                        "Can't listen on {} after the share ended: {}",
+                        address,
+                        error
+                    )
+                }
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(afterlife) => {}
+            _ = self.afterlife_stop.changed() => {}
+        }
+        stop_sender.send(()).ok();
+        while servers.join_next().await.is_some() {}
+    }
+}
+
+#[derive(Clone)]
+struct Afterlife {
+    ended_at: SystemTime,
+    redacted_tokens: Arc<[Arc<str>]>,
+    event_hooks: EventHooks,
+}
+
+async fn late_attempt(
+    State(afterlife): State<Afterlife>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Response {
+    let ended_at = humantime::format_rfc3339_seconds(afterlife.ended_at);
+    tracing::warn!(
+        "{} tried to fetch the secret after the share ended",
+        peer.address()
+            .map_or("unix socket".to_string(), |address| address.to_string())
+    );
+    afterlife.event_hooks.emit(Event::LateAttempt {
+        path: token::redact(uri.path(), &afterlife.redacted_tokens),
+        peer: peer.address(),
+        user_agent: user_agent(&headers),
+    });
+    html_page(
+        StatusCode::GONE,
+        "Share ended",
+        &format!("<p>This share has ended at {}.</p>", ended_at),
+    )
+}
+
+type Approve = dyn Fn(Option<SocketAddr>, Option<&str>) -> bool + Send + Sync;
+
+#[derive(Clone)]
+struct Approval(Arc<Approve>);
+
+impl Debug for Approval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Approval")
+    }
+}
+
+#[derive(Clone)]
+struct ApprovalState {
+    approval: Option<Approval>,
+    event_hooks: EventHooks,
+}
+
+#[derive(Clone)]
+struct AccessState {
+    uses: Arc<std::sync::Mutex<UseCount>>,
+    maximum_uses: Arc<AtomicU16>,
+    retry_window: Option<Duration>,
+    lock_first_client: bool,
+    /// Indexed like the recipients of the share.
+    recipient_names: Arc<[String]>,
+    shutdown_channel: mpsc::Sender<ShutdownReason>,
+    event_hooks: EventHooks,
+}
+
+impl AccessState {
+    fn recipient_name(&self, recipient: Option<usize>) -> Option<String> {
+        recipient.map(|index| self.recipient_names[index].clone())
+    }
+}
+
+#[derive(Clone)]
+struct FailState {
+    failed_attempts: Arc<tokio::sync::Mutex<u16>>,
+    maximum_failed_attempts: u16,
+    lock_out_after: Option<u16>,
+    tarpit: Option<Duration>,
+    ignored_paths: Arc<[String]>,
+    peer_failed_attempts: Arc<std::sync::Mutex<HashMap<Option<IpAddr>, u16>>>,
+    instructions_page: bool,
+    redacted_tokens: Arc<[Arc<str>]>,
+    shutdown_channel: mpsc::Sender<ShutdownReason>,
+    event_hooks: EventHooks,
+}
+
+impl Share {
+    pub fn builder() -> ShareBuilder {
+        ShareBuilder::default()
+    }
+
+    fn generate_token(&self) -> String {
+        match self.token_length {
+            Some(token_length) => self
+                .token_style
+                .generate_exactly(token_length, self.case_insensitive),
+            None => self
+                .token_style
+                .generate(self.url_prefix_length, self.case_insensitive),
+        }
+    }
+
+    pub async fn serve(self) -> Result<ShareHandle, Error> {
+        let secret = self.secret.clone().ok_or(Error::NoSecret)?;
+        // Git accepts any directory inside the repository, but the url is named after the repository
+        let secret = match secret {
+            Secret::GitRepository(repository) => {
+                Secret::GitRepository(git::validate_repository(&repository)?)
+            }
+            secret => secret,
+        };
+        let file_path = match &secret {
+            Secret::File(file_path) => Some(file_path.clone()),
+            Secret::Content(_) | Secret::SpooledContent(_) => None,
+            Secret::GitRepository(git_dir) => Some(git::clone_name(git_dir)),
+            // pip is pointed at the index, the package file is linked from there
+            Secret::PythonPackage(_) => Some(PathBuf::from("simple")),
+            Secret::CloudInit(_) => None,
+            Secret::Upstream(upstream) => {
+                reqwest::Url::parse(upstream.url()).map_err(|error| {
+                    Error::Upstream(upstream.url().to_string(), error.to_string())
+                })?;
+                proxy::file_name(upstream)
+            }
+        };
+        // The other secrets are read by their clients, e.g. git, not saved by browsers
+        let downloadable = matches!(
+            secret,
+            Secret::File(_) | Secret::Content(_) | Secret::SpooledContent(_) | Secret::Upstream(_)
+        );
+        let file_path = match &self.name {
+            Some(name) if downloadable => Some(PathBuf::from(name)),
+            _ => file_path,
+        };
+        let url_file_path = match self.hide_file_name && downloadable {
+            true => None,
+            false => file_path.clone(),
+        };
+        let mut file_url_path = generate_file_url_path(&url_file_path, self.generate_token())?;
+        // cloud-init appends the file names to the seed url as is
+        if matches!(secret, Secret::CloudInit(_)) {
+            file_url_path.push('/');
+        }
+
+        let token = file_url_path
+            .split('/')
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+        // Recipients get the same url, but with a token of their own
+        let mut recipients: Vec<RecipientLink> = self
+            .recipients
+            .iter()
+            .map(|recipient| RecipientLink {
+                name: recipient.name.clone(),
+                url_path: format!(
+                    "/{}{}",
+                    self.generate_token(),
+                    &file_url_path[1 + token.len()..]
+                ),
+                urls: Vec::new(),
+            })
+            .collect();
+        let url_paths: Arc<[String]> = match recipients.is_empty() {
+            true => vec![file_url_path.clone()].into(),
+            false => recipients
+                .iter()
+                .map(|recipient| recipient.url_path.clone())
+                .collect(),
+        };
+        let tokens = Tokens {
+            file_name: file_url_path[1 + token.len()..].into(),
+            case_insensitive: self.case_insensitive,
+            share: token.into(),
+            recipients: recipients
+                .iter()
+                .map(|recipient| {
+                    recipient
+                        .url_path
+                        .split('/')
+                        .nth(1)
+                        .unwrap_or_default()
+                        .into()
+                })
+                .collect(),
+        };
+
+        // Recorded paths must not give a token away, e.g. one with the wrong file name
+        let redacted_tokens: Arc<[Arc<str>]> = std::iter::once(tokens.share.clone())
+            .chain(tokens.recipients.iter().cloned())
+            .collect();
+        let capture = match &self.capture {
+            Some((file_path, all_headers)) => Some(
+                Capture::create(file_path.clone(), *all_headers, redacted_tokens.clone())
+                    .map_err(|error| Error::Capture(file_path.clone(), error))?,
+            ),
+            None => None,
+        };
+
+        let (shutdown_sender, mut shutdown_receiver) = mpsc::channel(16);
+        let maximum_uses = match self.recipients.is_empty() {
+            true => self.uses,
+            false => self
+                .recipients
+                .iter()
+                .fold(0, |sum: u16, recipient| sum.saturating_add(recipient.uses)),
+        };
+        let maximum_uses = Arc::new(AtomicU16::new(maximum_uses));
+        let started = Instant::now();
+        let use_count = Arc::new(std::sync::Mutex::new(UseCount {
+            recipients: self
+                .recipients
+                .iter()
+                .map(|recipient| RecipientCount {
+                    uses: recipient.uses,
+                    expires_at: recipient
+                        .expire_after
+                        .map(|expire_after| started + expire_after),
+                    used: 0,
+                    in_flight: 0,
+                })
+                .collect(),
+            ..UseCount::default()
+        }));
+        let access_state = AccessState {
+            uses: use_count.clone(),
+            maximum_uses: maximum_uses.clone(),
+            retry_window: self.retry_window,
+            lock_first_client: self.lock_first_client,
+            recipient_names: recipients
+                .iter()
+                .map(|recipient| recipient.name.clone())
+                .collect(),
+            shutdown_channel: shutdown_sender.clone(),
+            event_hooks: self.event_hooks.clone(),
+        };
+        let failed_attempts = Arc::new(Mutex::new(0));
+        // Locked out clients don't count towards stopping the server
+        let maximum_failed_attempts = self
+            .lock_out_after
+            .is_none()
+            .then_some(self.failed_attempts);
+        let fail_state = FailState {
+            failed_attempts: failed_attempts.clone(),
+            maximum_failed_attempts: self.failed_attempts,
+            lock_out_after: self.lock_out_after,
+            tarpit: self
+                .tarpit
+                .or((self.token_style == TokenStyle::Digits).then_some(token::DIGITS_TARPIT)),
+            ignored_paths: self.ignored_paths.clone().into(),
+            peer_failed_attempts: Arc::default(),
+            instructions_page: self.instructions_page,
+            redacted_tokens: redacted_tokens.clone(),
+            shutdown_channel: shutdown_sender.clone(),
+            event_hooks: self.event_hooks.clone(),
+        };
+
+        let approval_state = ApprovalState {
+            approval: self.approval.clone(),
+            event_hooks: self.event_hooks.clone(),
+        };
+
+        let enrollment_state = EnrollmentState {
+            key: self.enrollment_key.clone(),
+            nonces: Arc::default(),
+            event_hooks: self.event_hooks.clone(),
+        };
+
+        let download_name = self.download_name.clone().or_else(|| {
+            file_path
+                .as_ref()
+                .and_then(|file_path| file_path.file_name()?.to_str())
+                .map(String::from)
+        });
+        // A hidden file name is only told by the header, browsers still show the secret inline
+        let disposition = match self.download {
+            true => "attachment",
+            false => "inline",
+        };
+        let content_disposition = (downloadable
+            && (self.download || (self.hide_file_name && download_name.is_some())))
+        .then(|| {
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition(disposition, download_name.as_deref()),
+            )
+        })
+        .filter(|(name, _)| !self.response_headers.iter().any(|(given, _)| given == name));
+        let security_headers = SECURITY_HEADERS
+            .into_iter()
+            .filter(|_| self.security_headers)
+            .filter(|(name, _)| {
+                !self
+                    .response_headers
+                    .iter()
+                    .any(|(replaced, _)| replaced == name)
+            })
+            .map(|(name, value)| (name, HeaderValue::from_static(value)));
+        let given_headers = self
+            .response_headers
+            .iter()
+            .filter(|(name, _)| self.content_type.is_none() || name != header::CONTENT_TYPE)
+            .cloned();
+        let content_type = self
+            .content_type
+            .clone()
+            .map(|content_type| (header::CONTENT_TYPE, content_type));
+        let response_headers: Arc<[_]> = security_headers
+            .chain(content_disposition)
+            .chain(given_headers)
+            .chain(content_type)
+            .collect();
+        let file_watch = match &secret {
+            Secret::File(_) if self.in_memory => None,
+            // A missing file is reported when it is served below
+            Secret::File(file_path)
+            | Secret::PythonPackage(file_path)
+            | Secret::CloudInit(file_path)
+                if self.abort_on_change && file_path.is_file() =>
+            {
+                Some(
+                    FileWatch::new(file_path, shutdown_sender.clone())
+                        .map_err(|error| Error::ReadSecret(file_path.clone(), error))?,
+                )
+            }
+            _ => None,
+        };
+        // Only the responses with the secret itself consume a use and get the extra headers
+        let secret_layers = (
+            middleware::from_fn_with_state(file_watch.clone(), file_watch::refuse_changed_secret),
+            middleware::from_fn_with_state(access_state, limit_uses),
+            middleware::from_fn_with_state(response_headers, add_response_headers),
+        );
+        let fetching = Fetching {
+            git: matches!(secret, Secret::GitRepository(_)),
+        };
+
+        let host_check = HostCheck {
+            allowed: AllowedHosts::default(),
+            enabled: self.check_host,
+            event_hooks: self.event_hooks.clone(),
+        };
+
+        let default_charset = self.default_charset.clone();
+        let mut hasher = Sha256::new();
+        let content_known = !matches!(secret, Secret::Upstream(_));
+        let checksum_url = self.checksum_url
+            && matches!(
+                secret,
+                Secret::File(_) | Secret::Content(_) | Secret::SpooledContent(_)
+            );
+        let router = match secret {
+            Secret::File(file_path) if self.in_memory => {
+                let absolute_path = validate_and_get_absolute_path(&file_path)?;
+                let content = std::fs::read(&absolute_path)
+                    .map_err(|error| Error::ReadSecret(file_path.clone(), error))?;
+                hasher.update(&content);
+                let buffer = Arc::new(Buffer::new(content.into(), &file_path));
+                Router::new()
+                    .route(&file_url_path, get(buffer::serve_buffer).with_state(buffer))
+                    .layer(secret_layers)
+            }
+            Secret::File(file_path) => {
+                let absolute_path = validate_and_get_absolute_path(&file_path)?;
+                let prefix = hash_file(&absolute_path, &mut hasher)
+                    .map_err(|error| Error::ReadSecret(file_path.clone(), error))?;
+                // Without a known extension, the content tells the type better than the default
+                let sniffed_content_type = mime_guess::from_path(&file_path)
+                    .first()
+                    .is_none()
+                    .then(|| buffer::sniff_content_type(&prefix));
+                Router::new()
+                    .route_service(&file_url_path, ServeFile::new(absolute_path))
+                    .layer(SetResponseHeaderLayer::overriding(
+                        header::CONTENT_TYPE,
+                        sniffed_content_type,
+                    ))
+                    .layer(secret_layers)
+            }
+            Secret::Content(content) => {
+                hasher.update(&content);
+                let buffer = Arc::new(Buffer {
+                    content_type: buffer::sniff_content_type(&content),
+                    content,
+                });
+                Router::new()
+                    .route(&file_url_path, get(buffer::serve_buffer).with_state(buffer))
+                    .layer(secret_layers)
+            }
+            Secret::SpooledContent(file) => {
+                // The temporary file has no extension to guess from, so it is served like content
+                let spooled_file =
+                    Arc::new(SpooledFile::new(file, &mut hasher).map_err(|error| {
+                        Error::ReadSecret(PathBuf::from("spooled content"), error)
+                    })?);
+                Router::new()
+                    .route(
+                        &file_url_path,
+                        get(buffer::serve_spooled_file).with_state(spooled_file),
+                    )
+                    .layer(secret_layers)
+            }
+            Secret::GitRepository(git_dir) => {
+                let refs = git::advertise_refs(&git_dir)
+                    .await
+                    .map_err(|error| Error::GitRepository(git_dir.clone(), error.to_string()))?;
+                hasher.update(&refs);
+                let git_dir: Arc<Path> = git_dir.into();
+                // Listing the refs is free, only fetching the pack consumes a use
+                Router::new()
+                    .route(
+                        &format!("{}/info/refs", file_url_path),
+                        get(git::info_refs).with_state(git_dir.clone()),
+                    )
+                    .route(
+                        &format!("{}/git-upload-pack", file_url_path),
+                        post(git::upload_pack)
+                            .with_state(git_dir)
+                            .layer(secret_layers),
+                    )
+            }
+            Secret::PythonPackage(file_path) => {
+                let absolute_path = validate_and_get_absolute_path(&file_path)?;
+                let package = Package::from_file(&file_path)?;
+                File::open(&absolute_path)
+                    .and_then(|mut file| io::copy(&mut file, &mut hasher))
+                    .map_err(|error| Error::ReadSecret(file_path, error))?;
+                let project_url_path = format!("{}/{}", file_url_path, package.project);
+                let package_url_path = format!("{}/{}", project_url_path, package.file_name);
+                let index = Arc::new(PackageIndex {
+                    package,
+                    sha256: format!("{:x}", hasher.clone().finalize()),
+                });
+                // Browsing the index is free, only downloading the package consumes a use
+                Router::new()
+                    .route_service(&package_url_path, ServeFile::new(absolute_path))
+                    .layer(secret_layers)
+                    .route(
+                        &file_url_path,
+                        get(package::project_list).with_state(index.clone()),
+                    )
+                    .route(
+                        &format!("{}/", project_url_path),
+                        get(package::project_page).with_state(index),
+                    )
+                    .route(
+                        &format!("{}/{{project}}/", file_url_path),
+                        get(package::unknown_project),
+                    )
+            }
+            Secret::CloudInit(file_path) => {
+                let absolute_path = validate_and_get_absolute_path(&file_path)?;
+                File::open(&absolute_path)
+                    .and_then(|mut file| io::copy(&mut file, &mut hasher))
+                    .map_err(|error| Error::ReadSecret(file_path, error))?;
+                let meta_data = cloud_init::meta_data();
+                // The meta data is free, only fetching the user data consumes a use
+                let router = Router::new()
+                    .route_service(
+                        &format!("{}user-data", file_url_path),
+                        ServeFile::new(absolute_path),
+                    )
+                    .layer(secret_layers)
+                    .route(
+                        &format!("{}meta-data", file_url_path),
+                        get(|| async { meta_data }),
+                    );
+                cloud_init::OPTIONAL_FILES
+                    .iter()
+                    .fold(router, |router, file_name| {
+                        router.route(
+                            &format!("{}{}", file_url_path, file_name),
+                            get(cloud_init::missing_file),
+                        )
+                    })
+            }
+            Secret::Upstream(request) => {
+                let upstream = Arc::new(Upstream {
+                    client: reqwest::Client::new(),
+                    request,
+                });
+                Router::new()
+                    .route(
+                        &file_url_path,
+                        get(proxy::fetch_upstream).with_state(upstream),
+                    )
+                    .layer(secret_layers)
+            }
+        };
+        let checksum = content_known.then(|| format!("{:x}", hasher.finalize()));
+        // The checksum is free, but it tells a small secret as well as the secret itself
+        let checksum_router = match &checksum {
+            Some(checksum) if checksum_url => {
+                // In the format of sha256sum, so `sha256sum -c` can check the download
+                // Named like the saved file, even if the url doesn't tell the name
+                let file_name = download_name
+                    .as_deref()
+                    .unwrap_or_else(|| file_url_path.rsplit('/').next().unwrap_or_default());
+                let checksum_line = format!("{}  {}\n", checksum, file_name);
+                Router::new().route(
+                    &format!("{}{}", file_url_path, CHECKSUM_SUFFIX),
+                    get(|| async { checksum_line }),
+                )
+            }
+            _ => Router::new(),
+        };
+        // Only the routes of the secret consume uses and only handler_404 counts failed
+        // attempts, so a request any of the layers turns away neither consumes a use nor counts
+        // as failed attempt
+        let router = router
+            .merge(checksum_router)
+            .layer(middleware::from_fn_with_state(
+                approval_state,
+                require_approval,
+            ))
+            .layer(middleware::from_fn_with_state(
+                enrollment_state,
+                require_enrollment,
+            ))
+            .layer(middleware::from_fn_with_state(
+                fetching,
+                allow_only_fetching,
+            ))
+            .layer(middleware::from_fn_with_state(
+                self.click_through,
+                click_through,
+            ))
+            .layer(middleware::from_fn_with_state(
+                self.event_hooks.clone(),
+                turn_away_preview_bots,
+            ))
+            .layer(middleware::from_fn_with_state(self.release_at, embargo))
+            .layer(middleware::from_fn_with_state(
+                fetching,
+                reject_unexpected_input,
+            ))
+            .layer(SetResponseHeaderLayer::overriding(
+                header::CONTENT_TYPE,
+                move |response: &Response| -> Option<HeaderValue> {
+                    add_charset_to_content_type(response, &default_charset)
+                },
+            ))
+            .fallback(handler_404)
+            .layer(middleware::from_fn_with_state(
+                fail_state.clone(),
+                lock_out_peers,
+            ))
+            .layer(middleware::from_fn_with_state(
+                file_url_path.clone(),
+                log_request,
+            ))
+            .layer(CatchPanicLayer::custom({
+                // The uses might not have been counted, so rather stop than keep serving the secret
+                let shutdown_sender = shutdown_sender.clone();
+                move |_| {
+                    shutdown_sender.try_send(ShutdownReason::Panicked).ok();
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }))
+            .with_state(fail_state);
+        // Routing happens inside the router, so the url has to be fixed up before reaching it
+        let router = Router::new()
+            .fallback_service(router)
+            .layer(middleware::from_fn_with_state(
+                self.click_through,
+                accept_reveal,
+            ))
+            .layer(middleware::from_fn_with_state(tokens, compare_token))
+            .layer(middleware::from_fn_with_state(
+                (url_paths, self.case_insensitive),
+                normalize_url,
+            ))
+            .layer(middleware::from_fn_with_state(
+                Arc::<[String]>::from(self.cors_origins.clone()),
+                cors::allow_origins,
+            ))
+            .layer(middleware::from_fn_with_state(
+                RateLimiter::new(self.rate_limit),
+                limit_rate,
+            ))
+            .layer(middleware::from_fn_with_state(
+                host_check.clone(),
+                check_host,
+            ))
+            .layer(middleware::from_fn_with_state(
+                NetworkFilter {
+                    allowed: self.allowed_networks.clone().into(),
+                    denied: self.denied_networks.clone().into(),
+                    event_hooks: self.event_hooks.clone(),
+                },
+                filter_networks,
+            ))
+            .layer(RequestBodyLimitLayer::new(self.max_body_size))
+            .layer(middleware::from_fn_with_state(
+                capture,
+                capture::capture_requests,
+            ));
+
+        // Every listener gets its own server, so the shutdown signal has to be fanned out to all of them
+        let (stop_sender, stop_receiver) = watch::channel(());
+        let (afterlife_stop_sender, afterlife_stop) = watch::channel(());
+        if let Some(file_watch) = &file_watch {
+            file_watch.spawn_polling(stop_receiver.clone());
+        }
+        let event_hooks = self.event_hooks.clone();
+        tokio::spawn(async move {
+            // Only the first reason is reported, later ones are just echoes of the same shutdown
+            if let Some(reason) = shutdown_receiver.recv().await {
+                event_hooks.emit(Event::Shutdown(reason));
+            }
+            stop_sender.send(()).ok();
+            // Stopping the share once more ends the afterlife too
+            while let Some(reason) = shutdown_receiver.recv().await {
+                if reason == ShutdownReason::Triggered {
+                    afterlife_stop_sender.send(()).ok();
+                }
+            }
+        });
+
+        let deadline = Deadline::new(self.expire_after.map(|expire_after| started + expire_after));
+        if let Some(mut expires_at) = deadline.get() {
+            let shutdown_sender = shutdown_sender.clone();
+            let deadline = deadline.clone();
+            tokio::spawn(async move {
+                // The share might have been extended in the meantime
+                loop {
+                    tokio::time::sleep_until(expires_at.into()).await;
+                    match deadline.get() {
+                        Some(extended) if extended > expires_at => expires_at = extended,
+                        _ => break,
+                    }
+                }
+                shutdown_sender.send(ShutdownReason::Expired).await.ok();
+            });
+        }
+        for expire_after in self
+            .recipients
+            .iter()
+            .filter_map(|recipient| recipient.expire_after)
+        {
+            let shutdown_sender = shutdown_sender.clone();
+            let use_count = use_count.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(expire_after).await;
+                // The other recipients might still be able to fetch the secret
+                let closed = use_count.lock().unwrap().all_recipients_closed();
+                if closed {
+                    shutdown_sender.send(ShutdownReason::Expired).await.ok();
+                }
+            });
+        }
+
+        let mut servers = JoinSet::new();
+        let mut urls = Vec::new();
+        let mut local_addresses = Vec::new();
+
+        #[cfg(unix)]
+        if let Some(socket_path) = &self.unix_socket {
+            let listener = network::create_unix_listener(socket_path)?;
+            spawn_server(
+                &mut servers,
+                listener,
+                router,
+                stop_receiver,
+                self.drain_timeout,
+            );
+            // There is no host for a unix socket, clients like curl expect one anyway
+            if recipients.is_empty() {
+                urls.push(format!("http://localhost{}", file_url_path));
+            }
+            for recipient in &mut recipients {
+                let url = format!("http://localhost{}", recipient.url_path);
+                recipient.urls.push(url.clone());
+                urls.push(url);
+            }
+            self.emit_urls(&urls);
+            return Ok(ShareHandle {
+                url_path: file_url_path,
+                urls,
+                local_addresses,
+                checksum,
+                labels: self.labels,
+                recipients,
+                maximum_uses,
+                use_count,
+                failed_attempts,
+                maximum_failed_attempts,
+                deadline,
+                file_watch,
+                shutdown_sender,
+                servers,
+                allowed_hosts: host_check.allowed,
+                afterlife: self.afterlife,
+                afterlife_stop,
+                redacted_tokens,
+                event_hooks: self.event_hooks.clone(),
+                unix_socket: self.unix_socket,
+            });
+        }
+
+        let bind_ips = match self.bind_ips.is_empty() {
+            true => vec![network::get_local_ip(None, false)?],
+            false => self.bind_ips.clone(),
+        };
+        let mut port = 0;
+        for local_address in bind_ips {
+            if is_publicly_routable(&local_address) && !self.allow_public {
+                return Err(Error::PublicAddress(local_address));
+            }
+            let listener = create_listener(local_address, port).await?;
+            let address = listener.local_addr().map_err(Error::Bind)?;
+            // Try to use the same port for all address families, to make the urls easier to compare
+            port = address.port();
+            let url = format_url(&address, self.display_host.as_deref(), &file_url_path);
+            host_check.allowed.allow_url(&url);
+            host_check
+                .allowed
+                .allow_url(&format_url(&address, None, "/"));
+            // With a display host, all listeners might end up with the same url
+            if recipients.is_empty() && !urls.contains(&url) {
+                urls.push(url);
+            }
+            for recipient in &mut recipients {
+                let url = format_url(&address, self.display_host.as_deref(), &recipient.url_path);
+                if !recipient.urls.contains(&url) {
+                    recipient.urls.push(url.clone());
+                    urls.push(url);
+                }
+            }
+            spawn_server(
+                &mut servers,
+                listener,
+                router.clone(),
+                stop_receiver.clone(),
+                self.drain_timeout,
+            );
+            local_addresses.push(address);
+        }
+        self.emit_urls(&urls);
+
+        Ok(ShareHandle {
+            url_path: file_url_path,
+            urls,
+            local_addresses,
+            checksum,
+            labels: self.labels,
+            recipients,
+            maximum_uses,
+            use_count,
+            failed_attempts,
+            maximum_failed_attempts,
+            deadline,
+            file_watch,
+            shutdown_sender,
+            servers,
+            allowed_hosts: host_check.allowed,
+            afterlife: self.afterlife,
+            afterlife_stop,
+            redacted_tokens,
+            event_hooks: self.event_hooks.clone(),
+            #[cfg(unix)]
+            unix_socket: None,
+        })
+    }
+
+    fn emit_urls(&self, urls: &[String]) {
+        for url in urls {
+            self.event_hooks
+                .emit(Event::UrlGenerated { url: url.clone() });
+        }
+    }
+}
+
+fn spawn_server<L>(
+    servers: &mut JoinSet<io::Result<()>>,
+    listener: L,
+    router: Router,
+    stop_receiver: watch::Receiver<()>,
+    drain_timeout: Duration,
+) where
+    L: Listener,
+    L::Addr: Debug,
+    for<'a> Peer: Connected<IncomingStream<'a, L>>,
+{
+    let service = router.into_make_service_with_connect_info::<Peer>();
+    let stopped = |mut stop_receiver: watch::Receiver<()>| async move {
+        stop_receiver.changed().await.ok();
+    };
+    // The server stops accepting connections on shutdown, but waits for running responses
+    let server = axum::serve(listener, service)
+        .with_graceful_shutdown(stopped(stop_receiver.clone()))
+        .into_future();
+    servers.spawn(async move {
+        tokio::select! {
+            result = server => result,
+            _ = async {
+                stopped(stop_receiver).await;
+                tokio::time::sleep(drain_timeout).await;
+            } => {
+                tracing::warn!(
+                    "Stopped waiting for responses that didn't finish within {:?}",
+                    drain_timeout
+                );
+                Ok(())
+            }
+        }
+    });
+}
+
+fn add_charset_to_content_type(response: &Response, default_charset: &str) -> Option<HeaderValue> {
+    let content_type = response.headers().get(header::CONTENT_TYPE);
+    match content_type {
+        Some(content_type_opt) => match content_type_opt.to_str() {
+            Ok(content_type_str) => {
+                if content_type_str.starts_with("text/") && !content_type_str.contains("charset") {
+                    let new_content_type =
+                        content_type_str.to_string() + "; charset=" + default_charset;
+                    match HeaderValue::from_str(&new_content_type) {
+                        Ok(new_content_type) => Some(new_content_type),
+                        Err(_) => {
+                            tracing::warn!(
+                                "Failed to build new content type: {}",
+                                new_content_type
+                            );
+                            content_type.cloned()
+                        }
+                    }
+                } else {
+                    content_type.cloned()
+                }
+            }
+            Err(_) => None,
+        },
+        None => None,
+    }
+}
+
+#[derive(Clone)]
+struct NetworkFilter {
+    allowed: Arc<[IpNet]>,
+    denied: Arc<[IpNet]>,
+    event_hooks: EventHooks,
+}
+
+impl NetworkFilter {
+    fn allows(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of an IPv6 listener show up with IPv4-mapped addresses
+        let ip = ip.to_canonical();
+        let contains_ip = |network: &IpNet| network.contains(&ip);
+        (self.allowed.is_empty() || self.allowed.iter().any(contains_ip))
+            && !self.denied.iter().any(contains_ip)
+    }
+}
+
+async fn filter_networks(
+    State(filter): State<NetworkFilter>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let allowed = peer
+        .address()
+        .is_none_or(|address| filter.allows(address.ip()));
+    if allowed {
+        return next.run(request).await;
+    }
+    filter.event_hooks.emit(Event::Denied {
+        peer: peer.address(),
+        user_agent: user_agent(request.headers()),
+    });
+    (StatusCode::FORBIDDEN, "403 Forbidden").into_response()
+}
+
+#[derive(Clone)]
+struct Tokens {
+    share: Arc<str>,
+    /// With recipients, only their tokens are valid.
+    recipients: Arc<[Arc<str>]>,
+    /// What follows the token in the url, e.g. `/secret.txt`.
+    file_name: Arc<str>,
+    /// Match the token and the file name regardless of case, the tokens are lowercase then.
+    case_insensitive: bool,
+}
+
+/// Which recipient's link a request came through, the routes only know the token of the share.
+#[derive(Clone, Copy)]
+struct RecipientIndex(usize);
+
+/// The router compares paths byte by byte, which could reveal how much of a guessed token is
+/// right. Wrong tokens are therefore sent to the fallback before reaching it.
+async fn compare_token(State(tokens): State<Tokens>, mut request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    let requested = path.split('/').nth(1).unwrap_or_default();
+    // Without a file name, the url of the checksum only appends to the token
+    let requested = requested.strip_suffix(CHECKSUM_SUFFIX).unwrap_or(requested);
+    let requested = match tokens.case_insensitive {
+        true => requested.to_ascii_lowercase(),
+        false => requested.to_string(),
+    };
+    if tokens.recipients.is_empty() && !tokens.case_insensitive {
+        if !bool::from(requested.as_bytes().ct_eq(tokens.share.as_bytes())) {
+            *request.uri_mut() = Uri::from_static("/");
+        }
+        return next.run(request).await;
+    }
+    // All tokens are compared, so the time doesn't tell which one came close either
+    let mut recipient = None;
+    let mut matched = tokens.recipients.is_empty()
+        && bool::from(requested.as_bytes().ct_eq(tokens.share.as_bytes()));
+    for (index, token) in tokens.recipients.iter().enumerate() {
+        if bool::from(requested.as_bytes().ct_eq(token.as_bytes())) {
+            recipient = Some(index);
+            matched = true;
+        }
+    }
+    // The routes only know the token of the share and the file name as it is
+    let rewritten = matched.then(|| {
+        let mut rest = path[1 + requested.len()..].to_string();
+        let file_name = rest.get(..tokens.file_name.len());
+        if tokens.case_insensitive
+            && file_name.is_some_and(|file_name| file_name.eq_ignore_ascii_case(&tokens.file_name))
+        {
+            rest.replace_range(..tokens.file_name.len(), &tokens.file_name);
+        }
+        let query = request
+            .uri()
+            .query()
+            .map(|query| format!("?{}", query))
+            .unwrap_or_default();
+        Uri::builder()
+            .path_and_query(format!("/{}{}{}", tokens.share, rest, query))
+            .build()
+            .ok()
+    });
+    match rewritten.flatten() {
+        Some(uri) => {
+            *request.uri_mut() = uri;
+            if let Some(index) = recipient {
+                request.extensions_mut().insert(RecipientIndex(index));
+            }
+        }
+        None => *request.uri_mut() = Uri::from_static("/"),
+    }
+    next.run(request).await
+}
+
+/// The hosts and ports of the urls the share is reachable under.
+#[derive(Clone, Default)]
+struct AllowedHosts(Arc<std::sync::Mutex<Vec<(String, u16)>>>);
+
+impl AllowedHosts {
+    fn allow_url(&self, url: &str) {
+        let authority = url.parse::<Uri>().ok().and_then(|uri| {
+            let port = uri.port_u16().unwrap_or(80);
+            Some((uri.host()?.to_ascii_lowercase(), port))
+        });
+        if let Some(authority) = authority {
+            self.0.lock().unwrap().push(authority);
+        }
+    }
+
+    fn allows(&self, host: &str) -> bool {
+        let Ok(authority) = host.parse::<http::uri::Authority>() else {
+            return false;
+        };
+        let host = authority.host().to_ascii_lowercase();
+        let port = authority.port_u16().unwrap_or(80);
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(allowed, allowed_port)| {
+                // A listener on all interfaces doesn't know which address the client used
+                let any_host = matches!(allowed.as_str(), "0.0.0.0" | "[::]");
+                *allowed_port == port && (any_host || *allowed == host)
+            })
+    }
+}
+
+#[derive(Clone)]
+struct HostCheck {
+    allowed: AllowedHosts,
+    enabled: bool,
+    event_hooks: EventHooks,
+}
+
+/// A web page can rebind its own domain to the address of the share, the browser then still
+/// sends that domain as host.
+async fn check_host(
+    State(host_check): State<HostCheck>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // HTTP/2 clients send the host as part of the uri instead
+    let host = match request.headers().get(header::HOST) {
+        Some(host) => Some(host.to_str().unwrap_or_default()),
+        None => request
+            .uri()
+            .authority()
+            .map(|authority| authority.as_str()),
+    };
+    // Unix sockets have no host, and clients without a host aren't browsers
+    let allowed = match host {
+        Some(host) if host_check.enabled && peer.address().is_some() => {
+            host_check.allowed.allows(host)
+        }
+        _ => true,
+    };
+    if allowed {
+        return next.run(request).await;
+    }
+    host_check.event_hooks.emit(Event::Denied {
+        peer: peer.address(),
+        user_agent: user_agent(request.headers()),
+    });
+    (StatusCode::MISDIRECTED_REQUEST, "421 Misdirected Request").into_response()
+}
+
+async fn normalize_url(
+    State((url_paths, case_insensitive)): State<(Arc<[String]>, bool)>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let fold = |path: &str| match case_insensitive {
+        true => path.to_ascii_lowercase(),
+        false => path.to_string(),
+    };
+    let path = fold(request.uri().path());
+    let normalized = fold(&normalize_url_path(request.uri().path()));
+    // The paths carry the token, so they are compared in constant time like in compare_token
+    let mut exact = Choice::from(0);
+    let mut fixed = None;
+    for url_path in url_paths.iter() {
+        let folded = fold(url_path);
+        exact |= folded.as_bytes().ct_eq(path.as_bytes());
+        if bool::from(folded.as_bytes().ct_eq(normalized.as_bytes())) {
+            fixed = Some(url_path);
+        }
+    }
+    let query = request
+        .uri()
+        .query()
+        .map(|query| format!("?{}", query))
+        .unwrap_or_default();
+    // Only rewrite urls that match afterwards, everything else is a failed attempt as requested
+    if !bool::from(exact)
+        && let Some(url_path) = fixed
+        && let Ok(uri) = Uri::builder()
+            .path_and_query(format!("{}{}", url_path, query))
+            .build()
+    {
+        *request.uri_mut() = uri;
+    }
+    next.run(request).await
+}
+
+/// Undoes common copy and paste artifacts, e.g. trailing punctuation from chat apps.
+fn normalize_url_path(path: &str) -> String {
+    let decoded = percent_decode_str(path).decode_utf8_lossy();
+    let without_whitespace: String = decoded.chars().filter(|c| !c.is_whitespace()).collect();
+    without_whitespace
+        .trim_end_matches(|c| ".,;:!?)]}>'\"/".contains(c))
+        .to_string()
+}
+
+async fn log_request(
+    State(file_url_path): State<String>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    OriginalUri(original_uri): OriginalUri,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    // Log the path as requested, before fixing it up or turning away a wrong token
+    let path = original_uri.path().to_string();
+    let matched = request.uri().path() == file_url_path;
+    let start = Instant::now();
+    let response = next.run(request).await;
+    tracing::debug!(
+        %method,
+        path,
+        matched,
+        peer = peer
+            .address()
+            .map_or("unix socket".to_string(), |address| address.to_string()),
+        status = response.status().as_u16(),
+        bytes = content_length(&response),
+        duration = ?start.elapsed(),
+        "request"
+    );
+    response
+}
+
+/// Upper limit for the size of all request headers together.
+const MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+/// What clients are expected to send to fetch the secret.
+#[derive(Clone, Copy)]
+struct Fetching {
+    /// Git sends its fetch requests via POST, with a body listing the wanted commits.
+    git: bool,
+}
+
+async fn reject_unexpected_input(
+    State(fetching): State<Fetching>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // The secret is only ever fetched, so there is no reason to accept a body
+    let size_hint = request.body().size_hint();
+    let too_large = match fetching.git {
+        // Chunked bodies don't announce their size, the handler stops reading them at the limit
+        true => size_hint.lower() > git::MAX_REQUEST_SIZE,
+        false => size_hint.upper() != Some(0),
+    };
+    if too_large {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "413 Payload Too Large").into_response();
+    }
+    let headers_size: usize = request
+        .headers()
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum();
+    if headers_size > MAX_HEADERS_SIZE {
+        return (
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            "431 Request Header Fields Too Large",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+async fn allow_only_fetching(
+    State(fetching): State<Fetching>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match *request.method() {
+        Method::GET | Method::HEAD => next.run(request).await,
+        Method::POST if fetching.git => next.run(request).await,
+        _ => (
+            StatusCode::METHOD_NOT_ALLOWED,
+            [(header::ALLOW, "GET, HEAD")],
+            "405 Method Not Allowed",
+        )
+            .into_response(),
+    }
+}
+
+/// User agents of chat apps and social networks, which fetch pasted links to show a preview.
+const PREVIEW_BOTS: &[&str] = &[
+    "slackbot",
+    "slack-imgproxy",
+    "skypeuripreview",
+    "microsoftpreview",
+    "discordbot",
+    "telegrambot",
+    "whatsapp",
+    "facebookexternalhit",
+    "twitterbot",
+    "linkedinbot",
+    "mattermost-bot",
+    "rocket.chat",
+    "zulip",
+    "embedly",
+    "iframely",
+    "redditbot",
+    "pinterestbot",
+    "viber",
+];
+
+fn is_preview_bot(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_lowercase();
+    PREVIEW_BOTS.iter().any(|bot| user_agent.contains(bot))
+}
+
+async fn turn_away_preview_bots(
+    State(event_hooks): State<EventHooks>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let user_agent = user_agent(request.headers());
+    if !user_agent.as_deref().is_some_and(is_preview_bot) {
+        return next.run(request).await;
+    }
+    // Previews get a neutral page
+    event_hooks.emit(Event::Denied {
+        peer: peer.address(),
+        user_agent,
+    });
+    html_page(
+        StatusCode::OK,
+        "Shared secret",
+        "<p>A secret was shared with you, open the link in a browser to see it.</p>",
+    )
+}
+
+/// Marks a fetch that came from the button of the click through page.
+#[derive(Clone)]
+struct Reveal;
+
+async fn accept_reveal(
+    State(click_through): State<bool>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    // The routes only know how to fetch, and the method is matched before their middlewares run
+    if click_through && request.method() == Method::POST {
+        *request.method_mut() = Method::GET;
+        request.extensions_mut().insert(Reveal);
+    }
+    next.run(request).await
+}
+
+async fn click_through(
+    State(click_through): State<bool>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let fetching = matches!(*request.method(), Method::GET | Method::HEAD);
+    if !click_through || !fetching || request.extensions().get::<Reveal>().is_some() {
+        return next.run(request).await;
+    }
+    html_page(
+        StatusCode::OK,
+        "Shared secret",
+        "<p>A secret was shared with you, it might only be revealed once.</p>\n<form method=\"post\"><button type=\"submit\">Reveal the secret</button></form>",
+    )
+}
+
+fn html_page(status: StatusCode, title: &str, content: &str) -> Response {
+    let body = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"robots\" content=\"noindex\">\n<title>{}</title>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        title, content
+    );
+    (
+        status,
+        [
+            (header::CONTENT_TYPE, "text/html"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+async fn embargo(
+    State(release_at): State<Option<SystemTime>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match release_at.and_then(|release_at| release_at.duration_since(SystemTime::now()).ok()) {
+        Some(remaining) => countdown_page(remaining),
+        None => next.run(request).await,
+    }
+}
+
+fn countdown_page(remaining: Duration) -> Response {
+    // Round up, so the page doesn't reload right before the release
+    let remaining = Duration::from_secs(remaining.as_secs() + 1);
+    let body = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta http-equiv=\"refresh\" content=\"{}\">\n<title>Not released yet</title>\n</head>\n<body>\n<p>This secret will be released in {}.</p>\n</body>\n</html>\n",
+        remaining.as_secs(),
+        humantime::format_duration(remaining)
+    );
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [
+            (header::RETRY_AFTER, remaining.as_secs().to_string()),
+            (header::CONTENT_TYPE, "text/html".to_string()),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+async fn require_approval(
+    State(state): State<ApprovalState>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(Approval(approve)) = state.approval else {
+        return next.run(request).await;
+    };
+    // A HEAD only reveals the headers, that isn't worth asking for
+    if request.method() == Method::HEAD {
+        return next.run(request).await;
+    }
+    let user_agent = user_agent(request.headers());
+    let approved = {
+        let user_agent = user_agent.clone();
+        tokio::task::spawn_blocking(move || approve(peer.address(), user_agent.as_deref()))
+            .await
+            .unwrap_or(false)
+    };
+    if approved {
+        return next.run(request).await;
+    }
+    state.event_hooks.emit(Event::Denied {
+        peer: peer.address(),
+        user_agent,
+    });
+    (StatusCode::FORBIDDEN, "403 Forbidden").into_response()
+}
+
+async fn add_response_headers(
+    State(response_headers): State<Arc<[(HeaderName, HeaderValue)]>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    for (name, _) in response_headers.iter() {
+        headers.remove(name);
+    }
+    for (name, value) in response_headers.iter() {
+        headers.append(name, value.clone());
+    }
+    response
+}
+
+async fn limit_uses(
+    State(state): State<AccessState>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = peer.address().map(|address| address.ip());
+    let recipient = request
+        .extensions()
+        .get::<RecipientIndex>()
+        .map(|RecipientIndex(index)| *index);
+    // Unix socket clients can't be told apart, so they don't get a retry
+    let retrying = client.is_some()
+        && state.retry_window.is_some_and(|retry_window| {
+            state.uses.lock().unwrap().fetches.iter().any(
+                |(fetched_by, fetched_for, fetched_at)| {
+                    (*fetched_by, *fetched_for) == (client, recipient)
+                        && fetched_at.elapsed() < retry_window
+                },
+            )
+        });
+    if let Some(client) = client
+        && retrying
+    {
+        tracing::info!(
+            "Serving the secret again to {} within the retry window",
+            client
+        );
+        return next.run(request).await;
+    }
+    let first_client = state.uses.lock().unwrap().first_client;
+    if state.lock_first_client && first_client.is_some_and(|first_client| first_client != client) {
+        // Should the url leak after the handover started, it is of no use anymore
+        state.event_hooks.emit(Event::Denied {
+            peer: peer.address(),
+            user_agent: user_agent(request.headers()),
+        });
+        return (StatusCode::FORBIDDEN, "403 Forbidden").into_response();
+    }
+    if state.uses.lock().unwrap().used >= state.maximum_uses.load(Ordering::SeqCst) {
+        // If the maximum number of uses is reached, return a 404 response. This only happens
+        // during a retry window, otherwise the server is stopped before
+        return (StatusCode::NOT_FOUND, "404 Not Found").into_response();
+    }
+    if let Some(index) = recipient
+        && state.uses.lock().unwrap().recipients[index].closed()
+    {
+        // The other recipients keep the share running
+        return (StatusCode::GONE, "410 Gone").into_response();
+    }
+    // Prefetchers and download managers often check with HEAD first, that only reveals the headers
+    if request.method() == Method::HEAD {
+        return next.run(request).await;
+    }
+    let ranged = request.headers().contains_key(header::RANGE);
+    let user_agent = user_agent(request.headers());
+    let expired = state
+        .uses
+        .lock()
+        .unwrap()
+        .expire_range_sessions(state.maximum_uses.load(Ordering::SeqCst));
+    for (session, stop) in expired {
+        let event = Event::Accessed {
+            peer: session.peer,
+            user_agent: session.user_agent,
+            bytes: Some(
+                session
+                    .received
+                    .iter()
+                    .map(|range| range.end - range.start)
+                    .sum(),
+            ),
+            recipient: state.recipient_name(session.recipient),
+        };
+        report_use(&state, event, stop);
+    }
+    {
+        let mut count = state.uses.lock().unwrap();
+        let session = count
+            .range_sessions
+            .iter_mut()
+            .find(|session| ranged && session.client == client && session.recipient == recipient);
+        match session {
+            // The use was already taken by an earlier part
+            Some(session) => {
+                session.last_seen = Instant::now();
+                session.sending += 1;
+            }
+            None => {
+                // Claimed with the use, so clients starting at the same time can't both pass
+                let first_client = *count.first_client.get_or_insert(client);
+                if state.lock_first_client && first_client != client {
+                    drop(count);
+                    state.event_hooks.emit(Event::Denied {
+                        peer: peer.address(),
+                        user_agent,
+                    });
+                    return (StatusCode::FORBIDDEN, "403 Forbidden").into_response();
+                }
+                let recipient_busy = recipient.is_some_and(|index| {
+                    let recipient = &count.recipients[index];
+                    recipient.used + recipient.in_flight >= recipient.uses
+                });
+                if count.used + count.in_flight >= state.maximum_uses.load(Ordering::SeqCst)
+                    || recipient_busy
+                {
+                    // The last uses are taken by running downloads, which might still fail
+                    return (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        [(header::RETRY_AFTER, "5")],
+                        "503 Service Unavailable",
+                    )
+                        .into_response();
+                }
+                count.in_flight += 1;
+                if let Some(index) = recipient {
+                    count.recipients[index].in_flight += 1;
+                }
+                if ranged {
+                    count.range_sessions.push(RangeSession {
+                        client,
+                        recipient,
+                        peer: peer.address(),
+                        user_agent: user_agent.clone(),
+                        total: None,
+                        received: Vec::new(),
+                        sending: 1,
+                        last_seen: Instant::now(),
+                    });
+                }
+            }
+        }
+    }
+
+    let response = next.run(request).await;
+    let bytes = content_length(&response);
+    if ranged {
+        let part = match response.status() {
+            StatusCode::PARTIAL_CONTENT => content_range(&response),
+            StatusCode::OK => bytes.map(|bytes| (0..bytes, bytes)),
+            _ => None,
+        };
+        let Some(part) = part else {
+            // Nothing of the secret is sent, e.g. for an unsatisfiable range
+            state
+                .uses
+                .lock()
+                .unwrap()
+                .abandon_range_part(client, recipient);
+            return response;
+        };
+        let progress = ProgressTracker::new(state.clone(), peer.address(), bytes);
+        let part = RangePart {
+            state,
+            client,
+            recipient,
+            peer: peer.address(),
+            user_agent,
+            part,
+            delivered: false,
+        };
+        return count_when_delivered(response, bytes, part, progress);
+    }
+    let progress = ProgressTracker::new(state.clone(), peer.address(), bytes);
+    let reservation = UseReservation {
+        state,
+        recipient,
+        peer: peer.address(),
+        user_agent,
+        bytes,
+        delivered: false,
+    };
+    // Other answers, e.g. 304 Not Modified or an error from an unreachable upstream, hand out
+    // nothing of the secret, and dropping the reservation frees the use again
+    if !matches!(
+        response.status(),
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT
+    ) {
+        return response;
+    }
+    count_when_delivered(response, bytes, reservation, progress)
+}
+
+fn count_when_delivered(
+    response: Response,
+    bytes: Option<u64>,
+    mut delivery: impl Delivery,
+    progress: ProgressTracker,
+) -> Response {
+    // Empty bodies are never polled, so there is nothing left to wait for
+    if response.body().is_end_stream() {
+        delivery.mark_delivered();
+        return response;
+    }
+    response.map(|body| {
+        Body::new(CountingBody {
+            remaining: bytes,
+            body,
+            pending: Bytes::new(),
+            delivery: Some(delivery),
+            progress,
+        })
+    })
+}
+
+/// The byte range and the total size from a `Content-Range` header like `bytes 0-499/1234`.
+fn content_range(response: &Response) -> Option<(Range<u64>, u64)> {
+    let content_range = response
+        .headers()
+        .get(header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    let (range, total) = content_range.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let end: u64 = end.parse().ok()?;
+    Some((start.parse().ok()?..end + 1, total.parse().ok()?))
+}
+
+#[derive(Default)]
+struct UseCount {
+    used: u16,
+    in_flight: u16,
+    range_sessions: Vec<RangeSession>,
+    /// Indexed like the recipients of the share.
+    recipients: Vec<RecipientCount>,
+    /// When each client and recipient last fetched the secret, for the retry window.
+    fetches: Vec<(Option<IpAddr>, Option<usize>, Instant)>,
+    /// The client that fetched the secret first, or is fetching it.
+    first_client: Option<Option<IpAddr>>,
+    last_access: Option<SystemTime>,
+}
+
+impl UseCount {
+    /// Ends the ranged downloads that were given up halfway. Those that sent nothing give their
+    /// use back, the others count it, or all but the last byte could be fetched over and over.
+    /// Sessions with a part still being sent are kept, however long it takes.
+    ///
+    /// Returns the counted sessions, each with why the share stops now, if it does.
+    fn expire_range_sessions(
+        &mut self,
+        maximum_uses: u16,
+    ) -> Vec<(RangeSession, Option<ShutdownReason>)> {
+        let (active, expired): (Vec<_>, Vec<_>) = std::mem::take(&mut self.range_sessions)
+            .into_iter()
+            .partition(|session| {
+                session.sending > 0 || session.last_seen.elapsed() < RANGE_SESSION_TIMEOUT
+            });
+        self.range_sessions = active;
+        let mut counted = Vec::new();
+        for session in expired {
+            if session.received.is_empty() {
+                self.release(session.recipient);
+                continue;
+            }
+            let stop = self.settle(session.client, session.recipient, maximum_uses);
+            counted.push((session, stop));
+        }
+        counted
+    }
+
+    /// Ends a part of a ranged download that sends nothing. Unless another part of the session
+    /// was or is being sent, its use is given back right away instead of after the timeout.
+    fn abandon_range_part(&mut self, client: Option<IpAddr>, recipient: Option<usize>) {
+        let Some(index) = self
+            .range_sessions
+            .iter()
+            .position(|session| session.client == client && session.recipient == recipient)
+        else {
+            return;
+        };
+        let session = &mut self.range_sessions[index];
+        session.sending = session.sending.saturating_sub(1);
+        if session.sending == 0 && session.received.is_empty() {
+            self.range_sessions.remove(index);
+            self.release(recipient);
+        }
+    }
+
+    /// Ends a part of a ranged download, with the part and the total size if it was delivered.
+    /// Returns whether the whole file was received now, the session is over then and its use
+    /// is to be settled.
+    fn finish_range_part(
+        &mut self,
+        client: Option<IpAddr>,
+        recipient: Option<usize>,
+        delivered: Option<(Range<u64>, u64)>,
+    ) -> bool {
+        let Some(index) = self
+            .range_sessions
+            .iter()
+            .position(|session| session.client == client && session.recipient == recipient)
+        else {
+            return false;
+        };
+        let session = &mut self.range_sessions[index];
+        session.sending = session.sending.saturating_sub(1);
+        let Some((part, total)) = delivered else {
+            return false;
+        };
+        session.total = Some(total);
+        session.last_seen = Instant::now();
+        if !session.add(part) {
+            return false;
+        }
+        self.range_sessions.remove(index);
+        true
+    }
+
+    /// Gives back a use taken by a running download.
+    fn release(&mut self, recipient: Option<usize>) {
+        self.in_flight -= 1;
+        if let Some(recipient) = recipient.and_then(|index| self.recipients.get_mut(index)) {
+            recipient.in_flight -= 1;
+        }
+        // Nobody got the secret, the next client may be the first
+        if self.used == 0 && self.in_flight == 0 {
+            self.first_client = None;
+        }
+    }
+
+    /// Counts the use of a finished download, returns why the share stops now, if it does.
+    ///
+    /// Only a share whose every use was consumed is used up, a share whose remaining
+    /// recipients' links expired merely expired.
+    fn settle(
+        &mut self,
+        client: Option<IpAddr>,
+        recipient: Option<usize>,
+        maximum_uses: u16,
+    ) -> Option<ShutdownReason> {
+        self.release(recipient);
+        self.used += 1;
+        self.fetches.retain(|(fetched_by, fetched_for, _)| {
+            (*fetched_by, *fetched_for) != (client, recipient)
+        });
+        self.fetches.push((client, recipient, Instant::now()));
+        self.first_client.get_or_insert(client);
+        self.last_access = Some(SystemTime::now());
+        if let Some(recipient) = recipient.and_then(|index| self.recipients.get_mut(index)) {
+            recipient.used += 1;
+        }
+        let used_up = !self.recipients.is_empty()
+            && self
+                .recipients
+                .iter()
+                .all(|recipient| recipient.used >= recipient.uses);
+        if self.used >= maximum_uses || used_up {
+            Some(ShutdownReason::UsesExhausted)
+        } else if self.all_recipients_closed() {
+            Some(ShutdownReason::Expired)
+        } else {
+            None
+        }
+    }
+
+    fn all_recipients_closed(&self) -> bool {
+        !self.recipients.is_empty() && self.recipients.iter().all(RecipientCount::closed)
+    }
+}
+
+/// When the share expires, it can only be moved later.
+#[derive(Clone)]
+struct Deadline(Arc<std::sync::Mutex<Option<Instant>>>);
+
+impl Deadline {
+    fn new(expires_at: Option<Instant>) -> Self {
+        Deadline(Arc::new(std::sync::Mutex::new(expires_at)))
+    }
+
+    fn get(&self) -> Option<Instant> {
+        *self.0.lock().unwrap()
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        self.get()
+            .map(|expires_at| expires_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Returns how long until the share expires now, unless it doesn't expire at all.
+    fn extend(&self, duration: Duration) -> Option<Duration> {
+        let mut expires_at = self.0.lock().unwrap();
+        let expires_at = expires_at.as_mut()?;
+        *expires_at += duration;
+        Some(expires_at.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// The uses of a recipient's own link.
+struct RecipientCount {
+    uses: u16,
+    expires_at: Option<Instant>,
+    used: u16,
+    in_flight: u16,
+}
+
+impl RecipientCount {
+    fn closed(&self) -> bool {
+        self.used >= self.uses
+            || self
+                .expires_at
+                .is_some_and(|expires_at| Instant::now() >= expires_at)
+    }
+}
+
+/// Ranged downloads that make no progress for this long give their use back.
+const RANGE_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The parts a client downloaded with `Range` requests so far, e.g. a download manager.
+///
+/// Together they only take a single use.
+struct RangeSession {
+    client: Option<IpAddr>,
+    recipient: Option<usize>,
+    peer: Option<SocketAddr>,
+    user_agent: Option<String>,
+    total: Option<u64>,
+    received: Vec<Range<u64>>,
+    /// Parts that are being sent right now.
+    sending: u16,
+    last_seen: Instant,
+}
+
+impl RangeSession {
+    /// Adds a downloaded part, returns whether the whole file was received now.
+    fn add(&mut self, part: Range<u64>) -> bool {
+        self.received.push(part);
+        self.received.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<u64>> = Vec::new();
+        for range in self.received.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.received = merged;
+        self.total
+            .is_some_and(|total| self.received.first() == Some(&(0..total)))
+    }
+}
+
+/// Settles a use once a response body was delivered completely, or dropped before.
+trait Delivery: Send + Unpin + 'static {
+    fn mark_delivered(&mut self);
+}
+
+/// A use taken by a running download, it only counts once the whole body was delivered.
+///
+/// If the download is aborted, the use is given back so the recipient can try again.
+struct UseReservation {
+    state: AccessState,
+    recipient: Option<usize>,
+    peer: Option<SocketAddr>,
+    user_agent: Option<String>,
+    bytes: Option<u64>,
+    delivered: bool,
+}
+
+impl Delivery for UseReservation {
+    fn mark_delivered(&mut self) {
+        self.delivered = true;
+    }
+}
+
+impl Drop for UseReservation {
+    fn drop(&mut self) {
+        let stop = {
+            let mut count = self.state.uses.lock().unwrap();
+            if !self.delivered {
+                count.release(self.recipient);
+                return;
+            }
+            count.settle(
+                self.peer.map(|peer| peer.ip()),
+                self.recipient,
+                self.state.maximum_uses.load(Ordering::SeqCst),
+            )
+        };
+        let event = Event::Accessed {
+            peer: self.peer,
+            user_agent: self.user_agent.take(),
+            bytes: self.bytes,
+            recipient: self.state.recipient_name(self.recipient),
+        };
+        report_use(&self.state, event, stop);
+    }
+}
+
+/// A part of a ranged download, it is added to the session of the client once delivered.
+struct RangePart {
+    state: AccessState,
+    client: Option<IpAddr>,
+    recipient: Option<usize>,
+    peer: Option<SocketAddr>,
+    user_agent: Option<String>,
+    part: (Range<u64>, u64),
+    delivered: bool,
+}
+
+impl Delivery for RangePart {
+    fn mark_delivered(&mut self) {
+        self.delivered = true;
+    }
+}
+
+impl Drop for RangePart {
+    fn drop(&mut self) {
+        let total = self.part.1;
+        let stop = {
+            let mut count = self.state.uses.lock().unwrap();
+            let delivered = self.delivered.then(|| self.part.clone());
+            if !count.finish_range_part(self.client, self.recipient, delivered) {
+                return;
+            }
+            count.settle(
+                self.client,
+                self.recipient,
+                self.state.maximum_uses.load(Ordering::SeqCst),
+            )
+        };
+        let event = Event::Accessed {
+            peer: self.peer,
+            user_agent: self.user_agent.take(),
+            bytes: Some(total),
+            recipient: self.state.recipient_name(self.recipient),
+        };
+        report_use(&self.state, event, stop);
+    }
+}
+
+fn report_use(state: &AccessState, event: Event, stop: Option<ShutdownReason>) {
+    // This runs after the response left the handlers, out of reach of the panic layer
+    let emitted = panic::catch_unwind(AssertUnwindSafe(|| state.event_hooks.emit(event))).is_ok();
+    if !emitted {
+        state
+            .shutdown_channel
+            .try_send(ShutdownReason::Panicked)
+            .ok();
+    } else if let Some(reason) = stop {
+        // If the share is used up or its links expired, send a shutdown signal
+        match state.retry_window {
+            // Give the clients that fetched the secret the chance to fetch it again
+            Some(retry_window) => {
+                let shutdown_channel = state.shutdown_channel.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(retry_window).await;
+                    shutdown_channel.try_send(reason).ok();
+                });
+            }
+            None => {
+                state.shutdown_channel.try_send(reason).ok();
+            }
+        }
+    }
+}
+
+/// Only this much of the body is handed to the connection at once.
+const BODY_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Progress is reported at most this often while a download runs.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How much of the body was sent, reported to the progress hooks.
+struct ProgressTracker {
+    state: AccessState,
+    progress: Progress,
+    last_reported: Option<Instant>,
+}
+
+impl ProgressTracker {
+    fn new(state: AccessState, peer: Option<SocketAddr>, total: Option<u64>) -> Self {
+        ProgressTracker {
+            state,
+            progress: Progress {
+                peer,
+                sent: 0,
+                total,
+                done: false,
+            },
+            last_reported: None,
+        }
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        self.progress.sent += bytes as u64;
+        if self
+            .last_reported
+            .is_none_or(|last_reported| last_reported.elapsed() >= PROGRESS_INTERVAL)
+        {
+            self.report(false);
+        }
+    }
+
+    fn report(&mut self, done: bool) {
+        self.last_reported = Some(Instant::now());
+        self.progress.done = done;
+        // Bodies are polled and dropped out of reach of the panic layer
+        let progress = &self.progress;
+        let emitted = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.state.event_hooks.emit_progress(progress)
+        }))
+        .is_ok();
+        if !emitted {
+            self.state
+                .shutdown_channel
+                .try_send(ShutdownReason::Panicked)
+                .ok();
+        }
+    }
+}
+
+impl Drop for ProgressTracker {
+    fn drop(&mut self) {
+        self.report(true);
+    }
+}
+
+/// Passes the body on in small chunks, to notice when the client goes away halfway through.
+struct CountingBody<D> {
+    body: Body,
+    pending: Bytes,
+    remaining: Option<u64>,
+    delivery: Option<D>,
+    progress: ProgressTracker,
+}
+
+impl<D: Delivery> CountingBody<D> {
+    fn mark_delivered(&mut self) {
+        if let Some(mut delivery) = self.delivery.take() {
+            delivery.mark_delivered();
+        }
+    }
+}
+
+impl<D: Delivery> HttpBody for CountingBody<D> {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let this = self.get_mut();
+        if this.pending.is_empty() {
+            match ready!(Pin::new(&mut this.body).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => this.pending = data,
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                None => {
+                    this.mark_delivered();
+                    return Poll::Ready(None);
+                }
+                error => return Poll::Ready(error),
+            }
+        }
+        let chunk = this
+            .pending
+            .split_to(this.pending.len().min(BODY_CHUNK_SIZE));
+        this.remaining = this
+            .remaining
+            .map(|remaining| remaining.saturating_sub(chunk.len() as u64));
+        this.progress.advance(chunk.len());
+        // A body with a known length isn't polled again after its last byte
+        if this.remaining == Some(0) || this.is_end_stream() {
+            this.mark_delivered();
+        }
+        Poll::Ready(Some(Ok(Frame::data(chunk))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_empty() && self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.remaining {
+            Some(remaining) => SizeHint::with_exact(remaining),
+            None => {
+                let mut size_hint = self.body.size_hint();
+                if let Some(upper) = size_hint.upper() {
+                    size_hint.set_upper(upper + self.pending.len() as u64);
+                }
+                size_hint.set_lower(size_hint.lower() + self.pending.len() as u64);
+                size_hint
+            }
+        }
+    }
+}
+
+pub(crate) fn content_length(response: &Response) -> Option<u64> {
+    // In memory bodies don't have a content length header yet, but know their size
+    response.body().size_hint().exact().or_else(|| {
+        response
+            .headers()
+            .get(header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    })
+}
+
+pub(crate) fn user_agent(headers: &HeaderMap) -> Option<String> {
+    let user_agent = headers.get(header::USER_AGENT)?.to_str().ok()?;
+    Some(user_agent.to_string())
+}
+
+async fn handler_404(
+    State(state): State<FailState>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Response {
+    let fetching = matches!(method, Method::GET | Method::HEAD);
+    if state.instructions_page && fetching && uri.path() == "/" {
+        return html_page(
+            StatusCode::OK,
+            "Shared secret",
+            "<p>This is only the address of a shared secret. Ask the sender for the full link.</p>",
+        );
+    }
+    if is_ignored_path(&state.ignored_paths, uri.path()) {
+        return (StatusCode::NOT_FOUND, "404 Not Found").into_response();
+    }
+    let event = Event::Failed {
+        path: token::redact(uri.path(), &state.redacted_tokens),
+        peer: peer.address(),
+        user_agent: user_agent(&headers),
+    };
+    let client = peer.address().map(|address| address.ip());
+    let peer_failed_attempts = {
+        let mut peer_failed_attempts = state.peer_failed_attempts.lock().unwrap();
+        let count = peer_failed_attempts.entry(client).or_default();
+        *count = count.saturating_add(1);
+        *count
+    };
+    // Locked out clients are turned away before, so the server keeps running for everyone else
+    if state.lock_out_after.is_none() {
+        let mut lock = state.failed_attempts.lock().await;
+        *lock += 1;
+        state.event_hooks.emit(event);
+        if *lock >= state.maximum_failed_attempts {
+            // If the maximum number of failed attempts is reached, send a shutdown signal
+            // this happens when the user tries to access path other than the shared file
+            state
+                .shutdown_channel
+                .send(ShutdownReason::FailedAttemptsExhausted)
+                .await
+                .unwrap();
+        }
+    } else {
+        state.event_hooks.emit(event);
+    }
+    // Every further guess of the same client waits longer, the recipient never gets here
+    if let Some(tarpit) = state.tarpit {
+        tokio::time::sleep(tarpit * u32::from(peer_failed_attempts)).await;
+    }
+    (StatusCode::NOT_FOUND, "404 Not Found").into_response()
+}
+
+fn is_ignored_path(ignored_paths: &[String], path: &str) -> bool {
+    ignored_paths
+        .iter()
+        .any(|ignored| match ignored.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == ignored,
+        })
+}
+
+async fn lock_out_peers(
+    State(state): State<FailState>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(lock_out_after) = state.lock_out_after else {
+        return next.run(request).await;
+    };
+    let client = peer.address().map(|address| address.ip());
+    let failed_attempts = state
+        .peer_failed_attempts
+        .lock()
+        .unwrap()
+        .get(&client)
+        .copied()
+        .unwrap_or(0);
+    if failed_attempts < lock_out_after {
+        return next.run(request).await;
+    }
+    // A locked out client neither gets the secret nor counts as failed attempt anymore
+    state.event_hooks.emit(Event::Denied {
+        peer: peer.address(),
+        user_agent: user_agent(request.headers()),
+    });
+    (StatusCode::FORBIDDEN, "403 Forbidden").into_response()
+}
+
+fn validate_and_get_absolute_path(file_path: &Path) -> Result<PathBuf, Error> {
+    if !file_path.is_file() {
+        return Err(Error::SecretFileNotFound(file_path.to_path_buf()));
+    }
+    file_path
+        .canonicalize()
+        .map_err(|error| Error::AbsolutePath(file_path.to_path_buf(), error))
+}
+
+/// Hashes the whole file and returns its start to sniff the content type from.
+fn hash_file(file_path: &Path, hasher: &mut Sha256) -> io::Result<Vec<u8>> {
+    let mut file = File::open(file_path)?;
+    let mut prefix = Vec::new();
+    (&mut file)
+        .take(buffer::SNIFF_LENGTH as u64)
+        .read_to_end(&mut prefix)?;
+    hasher.update(&prefix);
+    io::copy(&mut file, hasher)?;
+    Ok(prefix)
+}
+
+/// The characters RFC 5987 doesn't allow unencoded in `filename*`, and a few more.
+const FILE_NAME_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'.').remove(b'-').remove(b'_');
+
+/// `attachment` or `inline`, with an ASCII fallback of the file name and the file name
+/// percent-encoded.
+fn content_disposition(disposition: &'static str, file_name: Option<&str>) -> HeaderValue {
+    let Some(file_name) = file_name else {
+        return HeaderValue::from_static(disposition);
+    };
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            ' '..='~' => c,
+            _ => '_',
+        })
+        .collect();
+    HeaderValue::from_str(&format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition,
+        fallback,
+        utf8_percent_encode(file_name, FILE_NAME_ENCODE_SET)
+    ))
+    .unwrap_or(HeaderValue::from_static(disposition))
+}
+
+fn generate_file_url_path(
+    file_path: &Option<PathBuf>,
+    random_prefix: String,
+) -> Result<String, Error> {
+    match file_path {
+        Some(file_path) => {
+            let file_name = file_path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .ok_or_else(|| Error::FileName(file_path.clone()))?;
+            Ok(format!("/{}/{}", random_prefix, file_name))
+        }
+        None => Ok(format!("/{}", random_prefix)),
+    }
+}
+
+#[cfg(test)]
+mod tests;
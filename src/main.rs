@@ -1,275 +1,1805 @@
-use axum::{
-    Router,
-    extract::{Request, State},
-    http::StatusCode,
-    middleware::{self, Next},
-    response::{IntoResponse, Response},
-    routing::get,
+use audit::AuditLog;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use clap_complete::Shell;
+use config::{config_to_args, default_config_path, read_config};
+use ipnet::{AddrParseError, IpNet};
+use localsecret::control::{self, ControlAddress, Controller};
+use localsecret::exposure::{HostsEntry, MdnsAdvertisement, SshTunnel, UpnpMapping};
+use localsecret::hooks::{HookRunner, HttpHook};
+use localsecret::network::{get_local_ip, get_tailscale_ip};
+use localsecret::simulate::{SimulatedClient, simulate, simulated_secret};
+use localsecret::{
+    Error, Event, Share, ShareBuilder, ShareHandle, ShutdownReason, ShutdownTrigger,
 };
-use clap::{CommandFactory, Parser};
-use http::{HeaderValue, header};
-use local_ip_address::local_ip;
+use phonetic::spell_token;
 use rand::{Rng, distr::Alphanumeric};
-use std::sync::Arc;
+use spool::{Spool, spool};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::net::{IpAddr, SocketAddr};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
 use std::{
-    io::{self, IsTerminal, Read},
+    io::{self, BufRead, IsTerminal, Read},
     process::exit,
 };
-use std::{net::IpAddr, path::PathBuf};
-use tokio::{
-    self, signal,
-    sync::{Mutex, mpsc},
-};
-use tower_http::services::ServeFile;
-use tower_http::set_header::SetResponseHeaderLayer;
+use tokio::{self, signal};
+use tracing::Level;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+use tui::{Dashboard, LogBuffer};
+use webhook::webhook_payload;
+
+mod audit;
+mod config;
+mod phonetic;
+mod spool;
+mod tui;
+mod webhook;
+
+const EXAMPLES: &str = "Examples:
+  Share a file once:
+    localsecret --secret-file credentials.txt
+  Share the output of a command for at most 10 minutes:
+    pass show db/prod | localsecret --expire-after 10m
+  Share a file three times, also announced via mDNS:
+    localsecret --secret-file token.txt --uses 3 --mdns
+  Hand over a snapshot of a git repository:
+    localsecret --git path/to/repo
+  Let pip install a wheel once:
+    localsecret --package dist/hotfix-1.0.1-py3-none-any.whl
+  Try it out with a harmless generated secret:
+    localsecret --demo";
+
+/// Where `localsecret ctl` takes the token of the control socket from.
+const CONTROL_TOKEN_VARIABLE: &str = "LOCALSECRET_CONTROL_TOKEN";
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Share secrets via a local http server", long_about = None, after_help = EXAMPLES)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(
+        short,
+        long,
+        value_hint = ValueHint::FilePath,
+        help = "The secret file to share. If not set, expects the input to be piped to stdin"
+    )]
+    secret_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "secret_file",
+        help = "Read the secret file into memory on start, so it can be deleted or overwritten while the share runs"
+    )]
+    in_memory: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "in_memory",
+        help = "Stop with an error if the shared file changes on disk while it is shared, instead of serving the changed file"
+    )]
+    abort_on_change: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["git", "package", "cloud_init", "proxy_url"],
+        help = "Also serve the SHA-256 checksum of the secret at its url with .sha256 appended, in the format of sha256sum. Fetching it doesn't consume a use, but passes the same checks as the secret, e.g. --release-at"
+    )]
+    checksum_url: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "checksum_url",
+        help = "Don't print the SHA-256 checksum of the secret at startup, as it gives away a small or guessable secret"
+    )]
+    no_checksum: bool,
+
+    #[arg(
+        long,
+        requires = "secret_file",
+        conflicts_with = "shred_after",
+        help = "Delete the secret file once all uses are used up, but not if the share expired or was stopped before"
+    )]
+    delete_after: bool,
+
+    #[arg(
+        long,
+        requires = "secret_file",
+        help = "Like --delete-after, but overwrite the secret file with zeros first. Copy-on-write file systems and SSDs might still keep the old content"
+    )]
+    shred_after: bool,
+
+    #[arg(
+        long,
+        value_name = "REPOSITORY",
+        value_hint = ValueHint::DirPath,
+        conflicts_with_all = ["secret_file", "demo", "click_through", "enroll_token"],
+        help = "Share a git repository read-only instead of a file, clone it with `git clone <url>`. Only fetching the objects consumes a use"
+    )]
+    git: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["secret_file", "git", "demo", "click_through", "enroll_token"],
+        help = "Share a Python wheel or source distribution as package index, install it with `pip install --index-url <url> --trusted-host <host> <project>`. Only downloading the package consumes a use"
+    )]
+    package: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "USER_DATA",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["secret_file", "git", "package", "demo", "click_through", "enroll_token"],
+        help = "Share a user data file with cloud-init, boot the VM with `ds=nocloud;s=<url>`. The meta data is generated and free, only fetching the user data consumes a use"
+    )]
+    cloud_init: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        conflicts_with_all = ["secret_file", "git", "package", "cloud_init", "demo"],
+        help = "Fetch the secret from this upstream url only when the recipient requests it, and pass it on. Credentials for the upstream stay on this machine, see --proxy-header"
+    )]
+    proxy_url: Option<String>,
+
+    #[arg(
+        long,
+        requires = "proxy_url",
+        value_name = "NAME: VALUE",
+        help = "Add a header to the upstream request, e.g. 'Authorization: Bearer {env:ARTIFACT_TOKEN}', can be given multiple times. {env:NAME} is replaced with the environment variable"
+    )]
+    proxy_header: Vec<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "secret_file",
+        help = "Share a harmless generated secret to try out the workflow, behind the --click-through page, with desktop notifications and for at most 5 minutes"
+    )]
+    demo: bool,
+
+    #[arg(
+        long,
+        value_hint = ValueHint::FilePath,
+        help = "Config file with defaults for the other arguments [default: ~/.config/localsecret/config.toml]"
+    )]
+    config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value_t = 42,
+        help = "Length of the randomly generated url prefix, other token styles are as hard to guess as this many letters and digits"
+    )]
+    url_prefix_length: u16,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = TokenStyle::Alphanumeric,
+        help = "Write the url prefix in letters and digits, in letters and digits without 0, O, o, 1, l and I to copy it by hand, as words like brisk-otter-cactus to type it from a phone into a TV or server console, or as digits to type it on a numeric keypad. Digits are tarpitted for 1s by default and can't be combined with --lock-out-after"
+    )]
+    token_style: TokenStyle,
+
+    #[arg(
+        long,
+        value_name = "LENGTH",
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "The number of characters, or words, of the url prefix in its --token-style, e.g. 9 digits, instead of as many as are as hard to guess as --url-prefix-length letters and digits"
+    )]
+    token_length: Option<u16>,
+
+    #[arg(
+        long,
+        help = "Generate the url prefix in lowercase, longer to be as hard to guess, and accept the url in any case, for recipients typing it"
+    )]
+    case_insensitive: bool,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "How often the shared url can be used"
+    )]
+    uses: u16,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Stop the server after this duration, even if uses are left, e.g. 10m or 1h"
+    )]
+    expire_after: Option<Duration>,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "After the share ended, keep answering for this duration with a page saying when it ended, and log late attempts. Nothing of the secret is kept, Ctrl+C ends it early"
+    )]
+    afterlife: Option<Duration>,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Let a client IP fetch the secret again within this duration after it did, without consuming another use, e.g. 60s. The server keeps running for this long after the last use"
+    )]
+    retry_window: Option<Duration>,
+
+    #[arg(
+        long,
+        help = "Only let the client IP that fetched the secret first use the remaining uses, everyone else gets 403 Forbidden"
+    )]
+    lock_first_client: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME[:EXPIRY[:USES]]",
+        value_parser = parse_recipient,
+        conflicts_with_all = ["demo", "speak_friendly"],
+        help = "Give a recipient a url of their own, e.g. alice:30m or bob:2h:3. The expiry and uses default to --expire-after and --uses, the server stops once all urls are used up or expired. Can be given multiple times"
+    )]
+    recipient: Vec<(String, Option<Duration>, Option<u16>)>,
+
+    #[arg(
+        long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u16).range(1..),
+        conflicts_with_all = ["recipient", "demo", "speak_friendly"],
+        help = "Generate this many urls for the same secret, named recipient-1 and so on, each valid --uses times. Which of them were used is logged"
+    )]
+    recipients: Option<u16>,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        default_value = "30s",
+        help = "How long running downloads may take to finish after the server stopped, before they are cut off"
+    )]
+    drain_timeout: Duration,
+
+    #[arg(
+        long,
+        value_parser = parse_release_time,
+        help = "Only serve the secret after this time, either a duration like 30m or a UTC timestamp like 2025-06-01T12:00:00Z. Earlier requests get a countdown page and don't consume uses"
+    )]
+    release_at: Option<SystemTime>,
+
+    #[arg(
+        long,
+        default_value_t = 3,
+        help = "How some invalid url can be used before the server stops. Paths browsers request on their own, like /favicon.ico, don't count, see --ignore-path"
+    )]
+    failed_attempts: u16,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Answer this path with 404 without counting a failed attempt, can be given multiple times. A trailing * matches any rest of the path. /favicon.ico, /apple-touch-icon.png, /robots.txt and /.well-known/* are always ignored"
+    )]
+    ignore_path: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "FAILED_ATTEMPTS",
+        conflicts_with = "failed_attempts",
+        help = "Count invalid urls per client IP and answer a client with 403 Forbidden after this many, instead of stopping the server after --failed-attempts. The server then only stops when the uses are exhausted"
+    )]
+    lock_out_after: Option<u16>,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Delay the 404 response to an invalid url by this duration, e.g. 2s, times the number of failed attempts the client made so far. Slows down guessing the url without affecting the recipient"
+    )]
+    tarpit: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "REQUESTS/DURATION",
+        value_parser = parse_rate_limit,
+        help = "Answer at most this many requests per client IP, e.g. 5/s or 100/10m, with bursts up to the number of requests. Further requests get 429 Too Many Requests and don't count as failed attempts"
+    )]
+    rate_limit: Option<(u32, Duration)>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = parse_size,
+        default_value = "1M",
+        help = "Turn away requests with larger bodies with 413 Payload Too Large, e.g. 64K or 10M"
+    )]
+    max_body_size: usize,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = parse_size,
+        default_value = "256M",
+        help = "Stop with an error if the secret piped to stdin is larger, e.g. 10M or 4G. Inputs over 16M are spooled to a temporary file"
+    )]
+    max_stdin_size: usize,
+
+    #[arg(
+        long,
+        help = "IP address to bind the server to. If not set, will try to find the local IP address"
+    )]
+    bind_ip: Option<IpAddr>,
+
+    #[arg(
+        long,
+        value_name = "CIDR",
+        value_parser = parse_network,
+        help = "Only answer clients from this network, e.g. 192.168.1.0/24, can be given multiple times. Other clients get 403 Forbidden, which neither consumes a use nor counts as failed attempt"
+    )]
+    allow_cidr: Vec<IpNet>,
+
+    #[arg(
+        long,
+        value_name = "ORIGIN",
+        help = "Let web pages from this origin read the secret with fetch(), e.g. https://tools.example.com, can be given multiple times"
+    )]
+    cors_origin: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "CIDR",
+        value_parser = parse_network,
+        help = "Turn away clients from this network with 403 Forbidden, e.g. a guest VLAN, even if --allow-cidr covers it. Can be given multiple times"
+    )]
+    deny_cidr: Vec<IpNet>,
+
+    #[arg(
+        long,
+        conflicts_with = "bind_ip",
+        help = "Use the local IPv6 address instead of the IPv4 one"
+    )]
+    ipv6: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["bind_ip", "ipv6"],
+        help = "Listen on both the local IPv4 and IPv6 address"
+    )]
+    dual_stack: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "bind_ip",
+        help = "Prefer the tailnet address (100.64.0.0/10 or fd7a:115c:a1e0::/48) of a tailscale interface over the local IP address"
+    )]
+    tailscale: bool,
+
+    #[arg(
+        long,
+        help = "Allow binding to a publicly routable IP address, or mapping a public port with --upnp. Without this, the server refuses to start on a public interface"
+    )]
+    allow_public: bool,
+
+    #[arg(
+        long,
+        help = "Host name or address to show in the printed url instead of the bound IP address, e.g. a DNS name"
+    )]
+    display_host: Option<String>,
+
+    #[arg(
+        long,
+        help = "Answer requests whatever host they are sent to, e.g. behind a reverse proxy. By default only the hosts of the printed urls and the bound addresses are answered, so a web page can't read the secret via DNS rebinding"
+    )]
+    any_host: bool,
+
+    #[arg(
+        long,
+        help = "Advertise the server via mDNS and print an additional url using the .local host name"
+    )]
+    mdns: bool,
+
+    #[arg(
+        long,
+        value_name = "USER@HOST",
+        help = "Open a SSH reverse tunnel to this host and print the url on the remote side. The SSH server needs to allow remote port forwarding, and GatewayPorts to be reachable from other hosts"
+    )]
+    ssh_tunnel: Option<String>,
+
+    #[arg(
+        long,
+        requires = "allow_public",
+        help = "Request a temporary port mapping via UPnP from the router and print the external url. WARNING: this makes the secret reachable from outside the local network, so it requires --allow-public"
+    )]
+    upnp: bool,
+
+    #[arg(
+        long,
+        value_hint = ValueHint::FilePath,
+        help = "Add a temporary host name like secret-7f3a.lan to this hosts file and print its url, e.g. for a dnsmasq hostsdir. The entry is removed on shutdown"
+    )]
+    hosts_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "lan",
+        requires = "hosts_file",
+        help = "The domain of the temporary host name"
+    )]
+    hosts_domain: String,
+
+    #[cfg(unix)]
+    #[arg(
+        long,
+        conflicts_with_all = ["bind_ip", "ipv6", "dual_stack", "display_host", "mdns", "ssh_tunnel", "upnp", "hosts_file", "allow_cidr", "deny_cidr", "afterlife"],
+        value_hint = ValueHint::AnyPath,
+        help = "Listen on a unix domain socket at this path instead of a TCP port"
+    )]
+    unix_socket: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "utf-8",
+        help = "Default charset to use for text files."
+    )]
+    default_charset: String,
+
+    #[arg(
+        long,
+        value_name = "NAME: VALUE",
+        help = "Add a header to the responses with the secret, e.g. 'Cache-Control: private', can be given multiple times. It replaces a header of the same name"
+    )]
+    header: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "MIME_TYPE",
+        conflicts_with_all = ["git", "package", "cloud_init"],
+        help = "Serve the secret with this content type, e.g. application/json, instead of guessing it from the file name or content. It replaces a Content-Type given with --header"
+    )]
+    content_type: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = ["git", "package", "cloud_init"],
+        help = "The file name in the url instead of the real one, which might reveal more than wanted in logs and browser histories"
+    )]
+    name: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["git", "package", "cloud_init"],
+        help = "Leave the file name out of the url, it is only sent in the Content-Disposition header, so the url tells shoulder-surfers and proxy logs nothing"
+    )]
+    no_filename: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["git", "package", "cloud_init"],
+        help = "Ask browsers to save the secret as a file instead of showing it, so .env files and keys aren't copied incompletely"
+    )]
+    download: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = ["git", "package", "cloud_init"],
+        help = "The file name browsers save the secret under, implies --download [default: the file name in the url]"
+    )]
+    filename: Option<String>,
+
+    #[arg(
+        long,
+        help = "Don't send the headers that keep caches and search engines from storing the secret: Cache-Control: no-store, X-Robots-Tag: noindex, Referrer-Policy: no-referrer and X-Content-Type-Options: nosniff"
+    )]
+    no_security_headers: bool,
+
+    #[arg(
+        long,
+        value_name = "KEY=VALUE",
+        value_parser = parse_label,
+        help = "Attach metadata to the share, e.g. ticket=OPS-1234, can be given multiple times. Labels are printed on startup and available as {label:KEY} in hooks"
+    )]
+    label: Vec<(String, String)>,
+
+    #[arg(
+        long,
+        help = "Ask in the terminal before every fetch of the secret, e.g. \"Allow 192.168.1.50 (curl/8.5.0) to fetch? [y/N]\". Denied requests get a 403 and don't consume uses"
+    )]
+    confirm_each: bool,
+
+    #[arg(
+        long,
+        help = "Serve a page on / telling visitors to ask for the full link, instead of counting it as failed attempt"
+    )]
+    instructions_page: bool,
+
+    #[arg(
+        long,
+        help = "Show a page with a button to reveal the secret, so link previews and prefetchers can't consume the uses. Scripts have to send a POST request, e.g. curl -X POST <url>"
+    )]
+    click_through: bool,
+
+    #[arg(
+        long,
+        value_name = "HMAC_KEY",
+        help = "Only serve clients that send the HMAC-SHA256 of a nonce with this key, for unattended provisioning scripts. Requests without it get a 401 with the nonce in the Localsecret-Nonce header, the HMAC goes hex encoded into the Localsecret-Hmac header"
+    )]
+    enroll_token: Option<String>,
+
+    #[arg(
+        long,
+        help = "Show a desktop notification with the peer IP whenever the secret is fetched"
+    )]
+    notify: bool,
+
+    #[arg(
+        long,
+        value_hint = ValueHint::FilePath,
+        help = "Append a JSON line with the time, peer IP, user agent and outcome of every access and failed attempt to this file"
+    )]
+    audit_log: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_hint = ValueHint::FilePath,
+        help = "Record the requests and responses in this HAR file, to debug a recipient's client. The token is redacted, bodies and headers that might carry credentials are left out"
+    )]
+    capture: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "capture",
+        help = "Record all headers in the capture file, with the values of credentials like cookies redacted"
+    )]
+    capture_headers: bool,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "POST a JSON event to this url when the secret is fetched and when the server stops, e.g. because the failed attempts are used up"
+    )]
+    webhook: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Call this url when the secret was fetched, e.g. to update the access request ticket. The url, body and headers can contain the placeholders {time}, {peer} and {bytes}, and {env:NAME} for environment variables"
+    )]
+    ticket_hook: Option<String>,
+
+    #[arg(
+        long,
+        requires = "ticket_hook",
+        help = "Send this body with a POST request to the ticket hook instead of a GET request"
+    )]
+    ticket_hook_body: Option<String>,
+
+    #[arg(
+        long,
+        requires = "ticket_hook",
+        value_name = "NAME: VALUE",
+        help = "Add a header to the ticket hook request, can be given multiple times"
+    )]
+    ticket_hook_header: Vec<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Output::Text,
+        help = "Print the urls as text, or a single JSON object with the urls, token, bind address, port, checksum, expiry and PID for scripts. Which recipients used their urls is then printed as JSON to stderr at shutdown"
+    )]
+    output: Output,
 
-#[derive(Parser, Debug)]
-#[command(version, about = "Share secrets via a local http server", long_about = None)]
-struct Args {
     #[arg(
-        short,
         long,
-        help = "The secret file to share. If not set, expects the input to be piped to stdin"
+        conflicts_with = "output",
+        help = "Only print the first url to stdout and no further notes to stderr, for scripts like URL=$(localsecret -s file --quiet &)"
     )]
-    secret_file: Option<PathBuf>,
+    quiet: bool,
 
     #[arg(
         long,
-        default_value_t = 42,
-        help = "Length of the randomly generated url prefix"
+        conflicts_with_all = ["output", "quiet"],
+        help = "Also print the token spelled in the NATO phonetic alphabet, for reading it out on a call. A shorter --url-prefix-length makes this practical"
     )]
-    url_prefix_length: u16,
+    speak_friendly: bool,
 
     #[arg(
         long,
-        default_value_t = 1,
-        help = "How often the shared url can be used"
+        conflicts_with_all = ["output", "quiet", "speak_friendly", "confirm_each"],
+        help = "Show a live dashboard with the remaining uses and time, running downloads, the recent requests and the log, with keys to revoke the share or allow more uses"
     )]
-    uses: u16,
+    tui: bool,
 
     #[arg(
         long,
-        default_value_t = 3,
-        help = "How some invalid url can be used before the server stops. Don't set this to 0, as browser e.g. try to fetch the favicon.ico file"
+        value_name = "PORT|ADDRESS|PATH",
+        value_hint = ValueHint::AnyPath,
+        help = "Listen for commands to adjust the running share, see `localsecret ctl`, on this port of 127.0.0.1, loopback address or unix socket path. Commands need the token printed on start, or the one in LOCALSECRET_CONTROL_TOKEN"
     )]
-    failed_attempts: u16,
+    control_socket: Option<ControlAddress>,
 
     #[arg(
         long,
-        help = "IP address to bind the server to. If not set, will try to find the local IP address"
+        value_name = "PORT|ADDRESS|PATH",
+        value_hint = ValueHint::AnyPath,
+        help = "Serve the uses and time left, failed attempts and last access as JSON on GET /status, on this port of 127.0.0.1, loopback address or unix socket path"
     )]
-    bind_ip: Option<IpAddr>,
+    status_address: Option<ControlAddress>,
 
     #[arg(
+        short,
         long,
-        default_value = "utf-8",
-        help = "Default charset to use for text files."
+        action = ArgAction::Count,
+        conflicts_with = "quiet",
+        help = "Log every request to stderr, -vv also logs the internals of the server"
     )]
-    default_charset: String,
+    verbose: u8,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TokenStyle {
+    Alphanumeric,
+    Unambiguous,
+    Words,
+    Digits,
+}
+
+impl From<TokenStyle> for localsecret::TokenStyle {
+    fn from(token_style: TokenStyle) -> Self {
+        match token_style {
+            TokenStyle::Alphanumeric => localsecret::TokenStyle::Alphanumeric,
+            TokenStyle::Unambiguous => localsecret::TokenStyle::Unambiguous,
+            TokenStyle::Words => localsecret::TokenStyle::Words,
+            TokenStyle::Digits => localsecret::TokenStyle::Digits,
+        }
+    }
 }
 
-#[derive(Clone)]
-struct AccessState {
-    uses: Arc<tokio::sync::Mutex<u16>>,
-    maximum_uses: u16,
-    shutdown_channel: mpsc::Sender<()>,
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    Text,
+    Json,
 }
 
-#[derive(Clone)]
-struct FailState {
-    failed_attempts: Arc<tokio::sync::Mutex<u16>>,
-    maximum_failed_attempts: u16,
-    shutdown_channel: mpsc::Sender<()>,
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(
+        about = "Print a shell completion script, e.g. `localsecret completions bash > /etc/bash_completion.d/localsecret`"
+    )]
+    Completions {
+        #[arg(help = "The shell to generate the completion script for")]
+        shell: Shell,
+    },
+    #[command(hide = true, about = "Print the man page in roff format")]
+    Man,
+    #[command(
+        about = "Run scripted fake clients against a share of a dummy secret on localhost, to see how the options given before the subcommand, e.g. --uses, --failed-attempts or --release-at, play out"
+    )]
+    Simulate {
+        #[arg(
+            help = "The clients to run in order: scanner, prefetch-bot, slow-downloader or recipient [default: all of them]"
+        )]
+        clients: Vec<SimulatedClient>,
+    },
+    #[command(
+        about = "Send a command to the --control-socket of a running share, given before the subcommand, with the token the share printed in LOCALSECRET_CONTROL_TOKEN: extend-uses <USES>, extend-time <DURATION>, revoke or status"
+    )]
+    Ctl {
+        #[arg(
+            required = true,
+            num_args = 1..,
+            help = "The command and its argument, e.g. extend-time 10m"
+        )]
+        command: Vec<String>,
+    },
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    let args = Args::parse();
-
-    let mut stdin = io::stdin();
-    let input_from_stdin = !stdin.is_terminal();
+    let args = parse_args();
+    match args.command {
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(
+                shell,
+                &mut Args::command(),
+                "localsecret",
+                &mut io::stdout(),
+            );
+            return;
+        }
+        Some(Command::Man) => {
+            clap_mangen::Man::new(Args::command())
+                .render(&mut io::stdout())
+                .unwrap();
+            return;
+        }
+        _ => {}
+    }
+    let log = init_logging(&args);
+    if let Some(Command::Simulate { clients }) = &args.command {
+        if let Err(error) = simulate_share(&args, clients).await {
+            tracing::error!("{}", error);
+            exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Ctl { command }) = &args.command {
+        let Some(control_socket) = &args.control_socket else {
+            tracing::error!("Pass the --control-socket of the share before the subcommand");
+            exit(1);
+        };
+        // Not an argument, those can be seen by other users of the machine
+        let Ok(token) = env::var(CONTROL_TOKEN_VARIABLE) else {
+            tracing::error!(
+                "Set {} to the token the share printed for its control socket",
+                CONTROL_TOKEN_VARIABLE
+            );
+            exit(1);
+        };
+        match control::send(control_socket, &token, &command.join(" ")).await {
+            Ok(reply) => println!("{}", reply),
+            Err(error) => {
+                tracing::error!("{}", error);
+                exit(1);
+            }
+        }
+        return;
+    }
+    if let Err(error) = run(args, log).await {
+        tracing::error!("{}", error);
+        exit(1);
+    }
+}
 
-    let file_url_path = generate_file_url_path(&args.secret_file, args.url_prefix_length);
+/// The log goes to stderr, or into the returned buffer while the dashboard shows.
+fn init_logging(args: &Args) -> LogBuffer {
+    let targets = match (args.quiet, args.verbose) {
+        (true, _) => Targets::new().with_default(Level::WARN),
+        (false, 0) => Targets::new().with_default(Level::INFO),
+        (false, 1) => Targets::new()
+            .with_target("localsecret", Level::DEBUG)
+            .with_default(Level::INFO),
+        (false, _) => Targets::new()
+            .with_target("localsecret", Level::TRACE)
+            .with_default(Level::DEBUG),
+    };
+    let log = LogBuffer::default();
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(log.clone())
+                .with_ansi(io::stderr().is_terminal() && !args.tui)
+                .with_target(false)
+                .without_time(),
+        )
+        .with(targets)
+        .init();
+    log
+}
 
-    let (shutdown_sender, shutdown_receiver) = mpsc::channel(16);
-    let access_state = AccessState {
-        uses: Arc::new(Mutex::new(0)),
-        maximum_uses: args.uses,
-        shutdown_channel: shutdown_sender.clone(),
+fn parse_args() -> Args {
+    let cli_args: Vec<OsString> = env::args_os().collect();
+    let command = Args::command();
+    let matches = command.clone().get_matches_from(&cli_args);
+    let config_path = match matches.get_one::<PathBuf>("config") {
+        Some(config_path) => Some(config_path.clone()),
+        // The default config file is optional
+        None => default_config_path().filter(|config_path| config_path.is_file()),
     };
-    let fail_state = FailState {
-        failed_attempts: Arc::new(Mutex::new(0)),
-        maximum_failed_attempts: args.failed_attempts,
-        shutdown_channel: shutdown_sender,
+    let config_args = match config_path {
+        Some(config_path) => read_config(&config_path)
+            .and_then(|config| config_to_args(&config, &command, &matches))
+            .unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                exit(1);
+            }),
+        None => Vec::new(),
     };
+    // Command line arguments come last, so they show up in error messages as given
+    let mut args = vec![cli_args[0].clone()];
+    args.extend(config_args);
+    args.extend(cli_args.into_iter().skip(1));
+    Args::parse_from(args)
+}
+
+/// The limits and behavior of the share given by `args`, without the secret, where it listens
+/// and what is reported elsewhere. The simulation shares its dummy secret with these as well.
+fn share_builder(args: &Args) -> ShareBuilder {
+    let mut builder = Share::builder()
+        .url_prefix_length(args.url_prefix_length)
+        .token_style(args.token_style.into())
+        .case_insensitive(args.case_insensitive)
+        .uses(args.uses)
+        .failed_attempts(args.failed_attempts)
+        .drain_timeout(args.drain_timeout)
+        .max_body_size(args.max_body_size)
+        .allow_public(args.allow_public)
+        .default_charset(&args.default_charset);
+    for (key, value) in &args.label {
+        builder = builder.label(key, value);
+    }
+    if args.no_security_headers {
+        builder = builder.security_headers(false);
+    }
+    if args.in_memory {
+        builder = builder.in_memory(true);
+    }
+    if args.abort_on_change {
+        builder = builder.abort_on_change(true);
+    }
+    if args.checksum_url {
+        builder = builder.checksum_url(true);
+    }
+    for header in &args.header {
+        builder = builder.response_header(header).unwrap_or_else(|error| {
+            tracing::error!("{}", error);
+            exit(1);
+        });
+    }
+    if let Some(name) = &args.name {
+        builder = builder.name(name).unwrap_or_else(|error| {
+            tracing::error!("{}", error);
+            exit(1);
+        });
+    }
+    if args.no_filename {
+        builder = builder.hide_file_name(true);
+    }
+    if args.download {
+        builder = builder.download(true);
+    }
+    if let Some(filename) = &args.filename {
+        builder = builder.download_name(filename);
+    }
+    if let Some(content_type) = &args.content_type {
+        builder = builder.content_type(content_type).unwrap_or_else(|error| {
+            tracing::error!("{}", error);
+            exit(1);
+        });
+    }
+    // Recipients expire on their own, some might get longer than --expire-after
+    if let Some(expire_after) = expire_after(args)
+        && recipients(args).is_empty()
+    {
+        builder = builder.expire_after(expire_after);
+    }
+    for (name, expire_after, uses) in recipients(args) {
+        builder = builder.recipient(
+            name,
+            uses.unwrap_or(args.uses),
+            expire_after.or(args.expire_after),
+        );
+    }
+    if let Some(token_length) = args.token_length {
+        builder = builder.token_length(token_length);
+    }
+    if let Some(lock_out_after) = args.lock_out_after {
+        // Locking out single clients lets everyone else go on guessing a short token
+        if args.token_style == TokenStyle::Digits {
+            tracing::error!(
+                "--lock-out-after can't be combined with --token-style digits, the share has to stop after --failed-attempts"
+            );
+            exit(1);
+        }
+        builder = builder.lock_out_after(lock_out_after);
+    }
+    if args.lock_first_client {
+        builder = builder.lock_first_client(true);
+    }
+    if let Some(retry_window) = args.retry_window {
+        builder = builder.retry_window(retry_window);
+    }
+    if let Some(tarpit) = args.tarpit {
+        builder = builder.tarpit(tarpit);
+    }
+    for path in &args.ignore_path {
+        builder = builder.ignore_path(path);
+    }
+    if let Some((requests, per)) = args.rate_limit {
+        builder = builder.rate_limit(requests, per);
+    }
+    for network in &args.allow_cidr {
+        builder = builder.allow_network(*network);
+    }
+    for network in &args.deny_cidr {
+        builder = builder.deny_network(*network);
+    }
+    for origin in &args.cors_origin {
+        builder = builder.cors_origin(origin);
+    }
+    if let Some(release_at) = args.release_at {
+        builder = builder.release_at(release_at);
+    }
+    if args.any_host {
+        builder = builder.check_host(false);
+    }
+    if args.confirm_each {
+        builder = builder.approve_with(confirm_fetch);
+    }
+    if args.instructions_page {
+        builder = builder.instructions_page(true);
+    }
+    if args.click_through || args.demo {
+        builder = builder.click_through(true);
+    }
+    if let Some(enroll_token) = &args.enroll_token {
+        builder = builder.enrollment_key(enroll_token.as_bytes());
+    }
+    builder
+}
+
+async fn run(args: Args, log: LogBuffer) -> Result<(), Error> {
+    let panic_stop = stop_on_panic();
+    let mut stdin = io::stdin();
+    let input_from_stdin = !stdin.is_terminal();
 
-    let router = match args.secret_file {
-        Some(file_path) => {
-            let absolute_path = validate_and_get_absolute_path(&file_path);
-            Router::new().route_service(&file_url_path, ServeFile::new(absolute_path))
+    let mut builder = share_builder(&args);
+    let shutdown_reason = Arc::new(Mutex::new(None));
+    let recorded_reason = shutdown_reason.clone();
+    builder = builder.on_event(move |event| {
+        if let Event::Shutdown(reason) = event {
+            *recorded_reason.lock().unwrap() = Some(*reason);
         }
+    });
+    let started = SystemTime::now();
+    let redemptions = Arc::new(Mutex::new(BTreeMap::new()));
+    let recorded_redemptions = redemptions.clone();
+    builder = builder.on_event(move |event| {
+        if let Event::Accessed {
+            peer,
+            recipient: Some(recipient),
+            ..
+        } = event
+        {
+            tracing::info!("The url of {} was used from {}", recipient, peer_ip(*peer));
+            recorded_redemptions
+                .lock()
+                .unwrap()
+                .entry(recipient.clone())
+                .or_insert_with(Vec::new)
+                .push(peer_ip(*peer));
+        }
+    });
+    if let Some(afterlife) = args.afterlife {
+        builder = builder.afterlife(afterlife);
+    }
+    if let Some(display_host) = &args.display_host {
+        builder = builder.display_host(display_host);
+    }
+    if let Some(log_path) = &args.audit_log {
+        let labels = args.label.iter().cloned().collect();
+        let audit_log = AuditLog::open(log_path, labels).unwrap_or_else(|error| {
+            tracing::error!("{}", error);
+            exit(1);
+        });
+        builder = builder.on_event(move |event| audit_log.record(event));
+    }
+    if let Some(capture_path) = &args.capture {
+        builder = builder.capture(capture_path, args.capture_headers);
+    }
+    if args.notify || args.demo {
+        builder = builder.on_event(|event| {
+            if let Event::Accessed { peer, .. } = event {
+                notify_accessed(*peer);
+            }
+        });
+    }
+    let hook_runner = HookRunner::default();
+    if let Some(webhook) = &args.webhook {
+        let hook_runner = hook_runner.clone();
+        let webhook = webhook.clone();
+        let labels: BTreeMap<_, _> = args.label.iter().cloned().collect();
+        builder = builder.on_event(move |event| {
+            if let Some(payload) = webhook_payload(event, &labels, SystemTime::now()) {
+                hook_runner.post_json("webhook", &webhook, payload.to_string());
+            }
+        });
+    }
+    if let Some(ticket_hook) = ticket_hook(&args) {
+        let hook_runner = hook_runner.clone();
+        let labels: Vec<_> = args
+            .label
+            .iter()
+            .map(|(key, value)| (format!("label:{}", key), value.clone()))
+            .collect();
+        builder = builder.on_event(move |event| {
+            if let Event::Accessed { peer, bytes, .. } = event {
+                let mut values = vec![
+                    (
+                        "time",
+                        humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                    ),
+                    (
+                        "peer",
+                        peer.map(|peer| peer.to_string()).unwrap_or_default(),
+                    ),
+                    (
+                        "bytes",
+                        bytes.map(|bytes| bytes.to_string()).unwrap_or_default(),
+                    ),
+                ];
+                values.extend(
+                    labels
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.clone())),
+                );
+                hook_runner.fire("ticket hook", &ticket_hook, &values);
+            }
+        });
+    }
+    // Large piped secrets are served from a temporary file, which is deleted right away on unix
+    // and once it is dropped elsewhere
+    let mut spooled_stdin = None;
+    builder = match &args.secret_file {
+        Some(file_path) => builder.file(file_path),
+        None if args.demo => builder.content(demo_secret()),
+        None if let Some(repository) = &args.git => builder.git_repository(repository),
+        None if let Some(package) = &args.package => builder.python_package(package),
+        None if let Some(user_data) = &args.cloud_init => builder.cloud_init(user_data),
+        None if let Some(url) = &args.proxy_url => builder.proxy(upstream(url, &args)),
         None => {
             if !input_from_stdin {
                 Args::command().print_help().unwrap();
-                eprintln!("Please provide a secret file to share or pipe the secret to stdin");
+                tracing::error!(
+                    "Please provide a secret file to share or pipe the secret to stdin"
+                );
                 exit(1);
             }
-            let mut buffer = String::new();
-            stdin.read_to_string(&mut buffer).unwrap();
-            Router::new().route(&file_url_path, get(|| async { buffer }))
-        }
-    }
-    .layer(middleware::from_fn_with_state(access_state, limit_uses))
-    .layer(SetResponseHeaderLayer::overriding(
-        header::CONTENT_TYPE,
-        move |response: &Response| -> Option<HeaderValue> {
-            add_charset_to_content_type(response, &args.default_charset)
-        },
-    ))
-    .fallback(handler_404)
-    .with_state(fail_state);
-
-    let local_address = get_local_ip(args.bind_ip);
-    let listener = create_listener(local_address).await;
-
-    println!(
-        "http://{}{}",
-        listener.local_addr().unwrap(),
-        &file_url_path
-    );
-    axum::serve(listener, router)
-        .with_graceful_shutdown(shutdown_signal(shutdown_receiver))
-        .await
-        .unwrap();
-}
-
-fn add_charset_to_content_type(response: &Response, default_charset: &str) -> Option<HeaderValue> {
-    let content_type = response.headers().get(header::CONTENT_TYPE);
-    match content_type {
-        Some(content_type_opt) => match content_type_opt.to_str() {
-            Ok(content_type_str) => {
-                if content_type_str.starts_with("text/") && !content_type_str.contains("charset") {
-                    let new_content_type =
-                        content_type_str.to_string() + "; charset=" + default_charset;
-                    match HeaderValue::from_str(&new_content_type) {
-                        Ok(new_content_type) => Some(new_content_type),
-                        Err(_) => {
-                            eprintln!("Failed to build new content type: {}", new_content_type);
-                            content_type.cloned()
-                        }
-                    }
-                } else {
-                    content_type.cloned()
+            match spool(&mut stdin, spool::MEMORY_LIMIT, args.max_stdin_size) {
+                Ok(Spool::Memory(content)) => builder.content(content),
+                Ok(Spool::File { file, temp_path }) => {
+                    spooled_stdin = temp_path;
+                    builder.spooled_content(file)
+                }
+                Err(error) if error.kind() == io::ErrorKind::FileTooLarge => {
+                    tracing::error!(
+                        "The secret piped to stdin is larger than {} bytes, pass a larger --max-stdin-size if this is intended",
+                        args.max_stdin_size
+                    );
+                    exit(1);
+                }
+                Err(error) => {
+                    tracing::error!("Can't read the secret from stdin: {}", error);
+                    exit(1);
+                }
+            }
+        }
+    };
+    let (dashboard_sender, dashboard_events) = std::sync::mpsc::channel();
+    let (progress_sender, dashboard_progress) = std::sync::mpsc::channel();
+    if args.tui {
+        builder = builder
+            .on_event(move |event| {
+                dashboard_sender.send(event.clone()).ok();
+            })
+            .on_progress(move |progress| {
+                progress_sender.send(progress.clone()).ok();
+            });
+    }
+    let handle = configure_listeners(builder, &args)?.serve().await?;
+    panic_stop.stop_on_panic(handle.shutdown_trigger());
+    let expires_at = expires_at(&args);
+    let mut urls = Vec::new();
+    let mut print_url = |url: String, recipient: Option<&str>| {
+        // Urls of tunnels and names reach the share with hosts of their own
+        handle.allow_url(&url);
+        // Every url goes to stdout, with --quiet only the first one
+        if args.output == Output::Text && (!args.quiet || urls.is_empty()) {
+            match recipient {
+                Some(name) if !args.quiet => println!("{}: {}", name, url),
+                _ => println!("{}", url),
+            }
+        }
+        urls.push(url);
+    };
+    // Exposures give every recipient a url of their own as well
+    let url_paths: Vec<(Option<String>, String)> = match handle.recipients().is_empty() {
+        true => {
+            for url in handle.urls() {
+                print_url(url.clone(), None);
+            }
+            vec![(None, handle.url_path().to_string())]
+        }
+        false => handle
+            .recipients()
+            .iter()
+            .map(|recipient| {
+                for url in &recipient.urls {
+                    print_url(url.clone(), Some(&recipient.name));
+                }
+                (Some(recipient.name.clone()), recipient.url_path.clone())
+            })
+            .collect(),
+    };
+    // To compare out-of-band, e.g. read out on a call
+    let checksum = handle.checksum().filter(|_| !args.no_checksum);
+    if let Some(checksum) = checksum {
+        match args.checksum_url {
+            true => tracing::info!("SHA-256: {}, also served at <url>.sha256", checksum),
+            false => tracing::info!("SHA-256: {}", checksum),
+        }
+    }
+    if args.speak_friendly {
+        println!("Token, spelled out:");
+        for (group, spelling) in spell_token(token(&handle)) {
+            println!("  {:4}  {}", group, spelling);
+        }
+    }
+    if !handle.labels().is_empty() {
+        let labels: Vec<_> = handle
+            .labels()
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        tracing::info!("Labels: {}", labels.join(" "));
+    }
+    #[cfg(unix)]
+    if let Some(socket_path) = &args.unix_socket {
+        tracing::info!(
+            "Listening on unix socket {:?}, e.g. use `curl --unix-socket {:?} <url>`",
+            socket_path,
+            socket_path
+        );
+    }
+
+    let shutdown_trigger = handle.shutdown_trigger();
+    tokio::spawn(async move {
+        // Another signal ends the afterlife, if there is one
+        loop {
+            shutdown_signal().await;
+            shutdown_trigger.trigger();
+        }
+    });
+
+    let control_socket = match &args.control_socket {
+        Some(address) => {
+            // Lets scripts, and --quiet, know the token beforehand
+            let control_socket = match env::var(CONTROL_TOKEN_VARIABLE) {
+                Ok(token) if !token.is_empty() => {
+                    handle.controller().listen_with_token(address, token)?
+                }
+                _ => handle.controller().listen(address)?,
+            };
+            tracing::info!(
+                "Listening for commands on {}, e.g. use `{}={} localsecret --control-socket {} ctl status`",
+                control_socket.address(),
+                CONTROL_TOKEN_VARIABLE,
+                control_socket.token().unwrap_or_default(),
+                control_socket.address()
+            );
+            Some(control_socket)
+        }
+        None => None,
+    };
+    let status_endpoint = match &args.status_address {
+        Some(address) => {
+            let status_endpoint = handle.controller().serve_status(address)?;
+            match status_endpoint.address() {
+                ControlAddress::Tcp(address) => {
+                    tracing::info!("Serving the status on http://{}/status", address)
                 }
+                #[cfg(unix)]
+                ControlAddress::Unix(socket_path) => tracing::info!(
+                    "Serving the status on unix socket {:?}, e.g. use `curl --unix-socket {:?} http://localhost/status`",
+                    socket_path,
+                    socket_path
+                ),
             }
-            Err(_) => None,
-        },
+            Some(status_endpoint)
+        }
         None => None,
+    };
+    // The terminal is free, unless the secret was piped in or the dashboard or prompts use it
+    if !input_from_stdin && !args.tui && !args.confirm_each && in_foreground() {
+        tracing::info!(
+            "Type r to revoke the share, e to allow one more use, s for the status or q to quit"
+        );
+        read_commands(handle.controller(), handle.shutdown_trigger());
     }
-}
 
-async fn limit_uses(State(state): State<AccessState>, request: Request, next: Next) -> Response {
-    let mut lock = state.uses.lock().await;
-    if *lock >= state.maximum_uses {
-        // If the maximum number of uses is reached, return a 404 response
-        // this should never happen, as the server should be stopped before this
-        return (StatusCode::NOT_FOUND, "404 Not Found").into_response();
+    let addresses = handle.local_addresses().to_vec();
+    // Should a step fail, what was exposed before is removed again, a UPnP mapping left behind
+    // would keep the machine reachable from the internet
+    let mut exposures = Exposures::default();
+    let exposed: Result<(), Error> = async {
+        if args.mdns {
+            let mdns_advertisement = exposures
+                .mdns_advertisement
+                .insert(MdnsAdvertisement::start(&addresses)?);
+            for (name, url_path) in &url_paths {
+                print_url(mdns_advertisement.url(url_path), name.as_deref());
+            }
+        }
+        if let Some(destination) = &args.ssh_tunnel {
+            let ssh_tunnel = exposures
+                .ssh_tunnel
+                .insert(SshTunnel::open(destination, &addresses[0]).await?);
+            for (name, url_path) in &url_paths {
+                print_url(ssh_tunnel.url(url_path), name.as_deref());
+            }
+        }
+        if args.upnp {
+            let upnp_mapping = exposures
+                .upnp_mapping
+                .insert(UpnpMapping::add(&addresses).await?);
+            tracing::warn!(
+                "The router forwards {} to this server, the secret can be fetched from outside the local network until the server stops",
+                upnp_mapping.external_address()
+            );
+            for (name, url_path) in &url_paths {
+                print_url(upnp_mapping.url(url_path), name.as_deref());
+            }
+        }
+        if let Some(hosts_file) = &args.hosts_file {
+            let hosts_entry = exposures.hosts_entry.insert(HostsEntry::add(
+                hosts_file,
+                &args.hosts_domain,
+                &addresses,
+            )?);
+            tracing::info!(
+                "Added {} to {:?} until the server stops",
+                hosts_entry.host_name(),
+                hosts_file
+            );
+            for (name, url_path) in &url_paths {
+                print_url(hosts_entry.url(url_path), name.as_deref());
+            }
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(error) = exposed {
+        exposures.remove().await;
+        return Err(error);
+    }
+    if args.output == Output::Json {
+        print_json(&handle, &urls, checksum, expires_at);
     }
+    let dashboard = args.tui.then(|| {
+        let dashboard = Dashboard {
+            urls,
+            expires_at,
+            maximum_failed_attempts: match args.lock_out_after {
+                Some(_) => None,
+                None => Some(args.failed_attempts),
+            },
+            use_limit: handle.use_limit(),
+            shutdown_trigger: handle.shutdown_trigger(),
+            events: dashboard_events,
+            progress: dashboard_progress,
+            log,
+        };
+        tokio::task::spawn_blocking(|| dashboard.run())
+    });
 
-    let response = next.run(request).await;
+    let result = handle.wait().await;
+    drop(spooled_stdin);
+    print_redemptions(&args, started, &redemptions.lock().unwrap());
+    let delivered = *shutdown_reason.lock().unwrap() == Some(ShutdownReason::UsesExhausted);
+    if (args.delete_after || args.shred_after)
+        && delivered
+        && let Some(file_path) = &args.secret_file
+    {
+        let removed = match args.shred_after {
+            true => shred_file(file_path),
+            false => std::fs::remove_file(file_path),
+        };
+        match removed {
+            Ok(()) => tracing::info!("Deleted the secret file {:?}", file_path),
+            Err(error) => {
+                tracing::error!("Can't delete the secret file {:?}: {}", file_path, error)
+            }
+        }
+    }
+    if let Some(dashboard) = dashboard {
+        match dashboard.await {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => tracing::error!("The dashboard failed: {}", error),
+            // The terminal might still be in raw mode
+            Err(_) => {
+                ratatui::restore();
+                tracing::error!("The dashboard panicked");
+            }
+        }
+    }
+    hook_runner.finish(Duration::from_secs(10)).await;
 
-    *lock += 1;
-    if *lock >= state.maximum_uses {
-        // If the maximum number of uses is reached, send a shutdown signal
-        state.shutdown_channel.send(()).await.unwrap();
+    if let Some(control_socket) = control_socket {
+        control_socket.close();
+    }
+    if let Some(status_endpoint) = status_endpoint {
+        status_endpoint.close();
     }
 
-    response
+    exposures.remove().await;
+    if result.is_ok() && *shutdown_reason.lock().unwrap() == Some(ShutdownReason::Panicked) {
+        return Err(Error::Panicked);
+    }
+    result
 }
 
-async fn handler_404(State(state): State<FailState>) -> impl IntoResponse {
-    let mut lock = state.failed_attempts.lock().await;
-    *lock += 1;
-    if *lock >= state.maximum_failed_attempts {
-        // If the maximum number of failed attempts is reached, send a shutdown signal
-        // this happens when the user tries to access path other than the shared file
-        state.shutdown_channel.send(()).await.unwrap();
-    }
-    (StatusCode::NOT_FOUND, "404 Not Found")
+/// What makes the share reachable under other addresses, removed again once it stops.
+#[derive(Default)]
+struct Exposures {
+    mdns_advertisement: Option<MdnsAdvertisement>,
+    ssh_tunnel: Option<SshTunnel>,
+    upnp_mapping: Option<UpnpMapping>,
+    hosts_entry: Option<HostsEntry>,
 }
 
-async fn create_listener(local_address: IpAddr) -> tokio::net::TcpListener {
-    match tokio::net::TcpListener::bind(format!("{}:0", local_address)).await {
-        Ok(listener) => listener,
-        Err(error) => {
-            eprintln!("Can't bind to local address: {:#?}", error);
-            std::process::exit(1);
+impl Exposures {
+    /// Removes them in the reverse order they were added.
+    async fn remove(self) {
+        if let Some(hosts_entry) = self.hosts_entry
+            && let Err(error) = hosts_entry.remove()
+        {
+            tracing::error!("{}", error);
+        }
+        if let Some(upnp_mapping) = self.upnp_mapping
+            && let Err(error) = upnp_mapping.remove().await
+        {
+            tracing::error!("{}", error);
+        }
+        if let Some(ssh_tunnel) = self.ssh_tunnel {
+            ssh_tunnel.close().await;
+        }
+        if let Some(mdns_advertisement) = self.mdns_advertisement {
+            mdns_advertisement.stop();
         }
     }
 }
 
-fn get_local_ip(bind_ip: Option<IpAddr>) -> IpAddr {
-    match bind_ip {
-        Some(ip) => ip,
-        None => match local_ip() {
-            Ok(ip) => ip,
-            Err(error) => {
-                eprintln!("Can't determine local ip: {:#?}", error);
-                std::process::exit(1);
+/// Overwrites the file before deleting it, so its content doesn't linger in the freed blocks.
+fn shred_file(file_path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(file_path)?;
+    let length = file.metadata()?.len();
+    io::copy(&mut io::repeat(0).take(length), &mut file)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::remove_file(file_path)
+}
+
+/// Whether the process is in the foreground of its terminal. Reading the terminal from the
+/// background, e.g. after `localsecret -s file &`, would stop the whole process.
+#[cfg(unix)]
+fn in_foreground() -> bool {
+    use nix::unistd::{getpgrp, tcgetpgrp};
+    tcgetpgrp(io::stdin()).is_ok_and(|foreground| foreground == getpgrp())
+}
+
+#[cfg(not(unix))]
+fn in_foreground() -> bool {
+    true
+}
+
+/// Runs the commands typed in the terminal while serving.
+fn read_commands(controller: Controller, shutdown_trigger: ShutdownTrigger) {
+    let (line_sender, mut lines) = tokio::sync::mpsc::unbounded_channel();
+    // A blocking task would keep the runtime from shutting down while waiting for input
+    std::thread::spawn(move || {
+        for line in io::stdin().lines().map_while(Result::ok) {
+            if line_sender.send(line).is_err() {
+                break;
             }
-        },
-    }
+        }
+    });
+    tokio::spawn(async move {
+        while let Some(line) = lines.recv().await {
+            let command = match line.trim() {
+                "r" => "revoke",
+                "e" => "extend-uses 1",
+                "s" => "status",
+                "q" => {
+                    // Stopping twice ends the afterlife too, like a second Ctrl+C
+                    shutdown_trigger.trigger();
+                    shutdown_trigger.trigger();
+                    continue;
+                }
+                "" => continue,
+                command => {
+                    tracing::warn!("Unknown command {:?}, type r, e, s or q", command);
+                    continue;
+                }
+            };
+            match controller.execute(command).await {
+                Ok(reply) => reply.lines().for_each(|line| tracing::info!("{}", line)),
+                Err(error) => tracing::error!("{}", error),
+            }
+        }
+    });
 }
 
-fn validate_and_get_absolute_path(file_path: &PathBuf) -> PathBuf {
-    if !file_path.is_file() {
-        eprintln!(
-            "The provided secret file doesn't exist or is not a file: {:?}",
-            file_path
-        );
-        std::process::exit(1);
+/// The share the panic hook stops, once it runs.
+#[derive(Clone, Default)]
+struct PanicStop(Arc<Mutex<Option<ShutdownTrigger>>>);
+
+impl PanicStop {
+    fn stop_on_panic(&self, shutdown_trigger: ShutdownTrigger) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(shutdown_trigger);
     }
-    match file_path.canonicalize() {
-        Ok(absolute_path) => absolute_path,
-        Err(error) => {
-            eprintln!(
-                "Can't determine absolute path of '{:?}': {:#?}",
-                file_path, error
-            );
-            std::process::exit(1);
+}
+
+/// Stops the share on a panic in any task, instead of possibly serving the secret without
+/// limits. It then ends the usual way, which also removes what exposed it, e.g. a UPnP mapping.
+fn stop_on_panic() -> PanicStop {
+    let panic_stop = PanicStop::default();
+    let shutdown_trigger = panic_stop.clone();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if let Some(shutdown_trigger) = &*shutdown_trigger
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            shutdown_trigger.trigger_panicked();
         }
+    }));
+    panic_stop
+}
+
+/// Demo shares stop after this time at the latest.
+const DEMO_DURATION: Duration = Duration::from_secs(5 * 60);
+
+fn expire_after(args: &Args) -> Option<Duration> {
+    match args.demo {
+        true => Some(args.expire_after.map_or(DEMO_DURATION, |expire_after| {
+            expire_after.min(DEMO_DURATION)
+        })),
+        false => args.expire_after,
+    }
+}
+
+/// The recipients as name, expiry and uses, either named or just counted.
+fn recipients(args: &Args) -> Vec<(String, Option<Duration>, Option<u16>)> {
+    match args.recipients {
+        Some(count) => (1..=count)
+            .map(|number| (format!("recipient-{}", number), None, None))
+            .collect(),
+        None => args.recipient.clone(),
     }
 }
 
-fn generate_file_url_path(file_path: &Option<PathBuf>, url_prefix_length: u16) -> String {
-    let random_prefix: String = rand::rng()
+/// When the server stops at the latest, with recipients once the last of them expired.
+fn expires_at(args: &Args) -> Option<SystemTime> {
+    let now = SystemTime::now();
+    let recipients = recipients(args);
+    if recipients.is_empty() {
+        return expire_after(args).map(|expire_after| now + expire_after);
+    }
+    recipients
+        .iter()
+        .map(|(_, expire_after, _)| expire_after.or(args.expire_after))
+        .try_fold(now, |latest, expire_after| {
+            expire_after.map(|expire_after| latest.max(now + expire_after))
+        })
+}
+
+fn demo_secret() -> String {
+    let token: String = rand::rng()
         .sample_iter(Alphanumeric)
-        .take(usize::from(url_prefix_length))
+        .take(16)
         .map(char::from)
         .collect();
-    match file_path {
-        Some(file_path) => {
-            let file_name = match file_path.file_name() {
-                Some(file_name) => match file_name.to_str() {
-                    Some(file_name) => file_name,
-                    None => {
-                        eprintln!("Can't decode file name: {:#?}", file_path);
-                        std::process::exit(1);
-                    }
-                },
-                None => {
-                    eprintln!("Can't determine file name from: {:#?}", file_path);
-                    std::process::exit(1);
-                }
+    format!(
+        "This is a localsecret demo, nothing in here is secret.\nDemo token: {}\n",
+        token
+    )
+}
+
+fn token(handle: &ShareHandle) -> &str {
+    // The random prefix is the first path segment
+    handle.url_path().split('/').nth(1).unwrap_or_default()
+}
+
+fn peer_ip(peer: Option<SocketAddr>) -> String {
+    peer.map_or("unix socket".to_string(), |peer| peer.ip().to_string())
+}
+
+/// Reports which recipients used their urls and from where, once the share stopped.
+fn print_redemptions(
+    args: &Args,
+    started: SystemTime,
+    redemptions: &BTreeMap<String, Vec<String>>,
+) {
+    let recipients = recipients(args);
+    if recipients.is_empty() || args.quiet {
+        return;
+    }
+    let now = SystemTime::now();
+    let report: Vec<_> = recipients
+        .iter()
+        .map(|(name, expire_after, _)| {
+            let peers = redemptions.get(name).cloned().unwrap_or_default();
+            let expired = expire_after
+                .or(args.expire_after)
+                .is_some_and(|expire_after| started + expire_after <= now);
+            let status = match (peers.is_empty(), expired) {
+                (false, _) => "used",
+                (true, true) => "expired",
+                (true, false) => "unused",
             };
+            (name, status, peers)
+        })
+        .collect();
+    match args.output {
+        Output::Text => {
+            println!("Recipients:");
+            for (name, status, peers) in report {
+                match status {
+                    "used" => println!("  {}: used from {}", name, peers.join(", ")),
+                    "expired" => println!("  {}: expired unused", name),
+                    _ => println!("  {}: unused", name),
+                }
+            }
+        }
+        Output::Json => {
+            let report: Vec<_> = report
+                .into_iter()
+                .map(|(name, status, peers)| {
+                    serde_json::json!({"name": name, "status": status, "peers": peers})
+                })
+                .collect();
+            // Stdout only carries the object printed at startup, for scripts parsing it whole
+            eprintln!("{}", serde_json::json!({ "redemptions": report }));
+        }
+    }
+}
+
+fn print_json(
+    handle: &ShareHandle,
+    urls: &[String],
+    checksum: Option<&str>,
+    expires_at: Option<SystemTime>,
+) {
+    let address = handle.local_addresses().first();
+    let output = serde_json::json!({
+        "url": urls.first(),
+        "urls": urls,
+        // With recipients the share's own url doesn't work, their tokens are listed with them
+        "token": handle.recipients().is_empty().then(|| token(handle)),
+        "bind_address": address.map(|address| address.ip()),
+        "port": address.map(|address| address.port()),
+        "checksum": checksum,
+        "expires_at": expires_at
+            .map(|expires_at| humantime::format_rfc3339_seconds(expires_at).to_string()),
+        "pid": std::process::id(),
+        "recipients": handle
+            .recipients()
+            .iter()
+            .map(|recipient| {
+                serde_json::json!({
+                    "name": recipient.name,
+                    "token": recipient.url_path.split('/').nth(1),
+                    "urls": recipient.urls,
+                })
+            })
+            .collect::<Vec<_>>(),
+        "labels": handle
+            .labels()
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<BTreeMap<_, _>>(),
+    });
+    println!("{}", output);
+}
+
+fn confirm_fetch(peer: Option<SocketAddr>, user_agent: Option<&str>) -> bool {
+    // Concurrent requests must not ask at the same time
+    static PROMPT: Mutex<()> = Mutex::new(());
+    let _prompt = PROMPT.lock().unwrap_or_else(|error| error.into_inner());
+    let peer = peer.map_or("the unix socket".to_string(), |peer| peer.ip().to_string());
+    let user_agent = user_agent.unwrap_or("unknown client");
+    // Stdin might be the piped secret, so read the answer from the terminal itself
+    let tty = match File::open(TERMINAL) {
+        Ok(tty) => tty,
+        Err(error) => {
+            tracing::warn!("Can't ask in the terminal, denying the request: {}", error);
+            return false;
+        }
+    };
+    eprint!("Allow {} ({}) to fetch? [y/N] ", peer, user_agent);
+    let mut answer = String::new();
+    io::BufReader::new(tty).read_line(&mut answer).ok();
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+#[cfg(unix)]
+const TERMINAL: &str = "/dev/tty";
+#[cfg(windows)]
+const TERMINAL: &str = "CONIN$";
+
+fn notify_accessed(peer: Option<SocketAddr>) {
+    let body = match peer {
+        Some(peer) => format!("The secret was fetched from {}", peer.ip()),
+        None => "The secret was fetched via the unix socket".to_string(),
+    };
+    // Showing the notification blocks, the runtime waits for it before exiting
+    tokio::task::spawn_blocking(move || {
+        if let Err(error) = notify_rust::Notification::new()
+            .summary("localsecret")
+            .body(&body)
+            .show()
+        {
+            tracing::warn!("Can't show a desktop notification: {}", error);
+        }
+    });
+}
+
+fn ticket_hook(args: &Args) -> Option<HttpHook> {
+    let mut hook = HttpHook::new(args.ticket_hook.as_ref()?);
+    if let Some(body) = &args.ticket_hook_body {
+        hook = hook.body(body);
+    }
+    for header in &args.ticket_hook_header {
+        hook = hook.header(header).unwrap_or_else(|error| {
+            tracing::error!("{}", error);
+            exit(1);
+        });
+    }
+    Some(hook)
+}
+
+fn upstream(url: &str, args: &Args) -> HttpHook {
+    let mut upstream = HttpHook::new(url);
+    for header in &args.proxy_header {
+        upstream = upstream.header(header).unwrap_or_else(|error| {
+            tracing::error!("{}", error);
+            exit(1);
+        });
+    }
+    upstream
+}
+
+async fn simulate_share(args: &Args, clients: &[SimulatedClient]) -> Result<(), Error> {
+    let clients = match clients.is_empty() {
+        true => SimulatedClient::ALL.to_vec(),
+        false => clients.to_vec(),
+    };
+    let shutdown_reason = Arc::new(Mutex::new(None));
+    let recorded_reason = shutdown_reason.clone();
+    let handle = share_builder(args)
+        .content(simulated_secret())
+        .bind_ip(IpAddr::from([127, 0, 0, 1]))
+        .on_event(move |event| {
+            if let Event::Shutdown(reason) = event {
+                *recorded_reason.lock().unwrap() = Some(*reason);
+            }
+        })
+        .serve()
+        .await?;
+    for attempt in simulate(&handle.urls()[0], &clients).await {
+        let status = attempt
+            .status
+            .map_or("no response".to_string(), |status| status.to_string());
+        println!(
+            "{:<16} {:<48} {} ({} bytes)",
+            attempt.client, attempt.path, status, attempt.bytes
+        );
+    }
+    handle.shutdown_trigger().trigger();
+    let result = handle.wait().await;
+    if let Some(reason) = *shutdown_reason.lock().unwrap() {
+        println!("The server stopped: {:?}", reason);
+    }
+    result
+}
+
+fn parse_label(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("Labels must look like KEY=VALUE".to_string()),
+    }
+}
+
+fn parse_release_time(value: &str) -> Result<SystemTime, String> {
+    if let Ok(duration) = humantime::parse_duration(value) {
+        return Ok(SystemTime::now() + duration);
+    }
+    humantime::parse_rfc3339_weak(value).map_err(|error| error.to_string())
+}
+
+/// A number of bytes with an optional binary unit, e.g. 512, 64K or 10MiB.
+fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits);
+    let factor: usize = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Invalid size unit: {}", unit)),
+    };
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(factor))
+        .ok_or_else(|| format!("Invalid size: {}", value))
+}
+
+/// A rate like 5/s or 100/10m, the unit alone means one of it.
+fn parse_rate_limit(value: &str) -> Result<(u32, Duration), String> {
+    let (requests, per) = value
+        .split_once('/')
+        .ok_or_else(|| format!("Rate limit must look like 5/s: {}", value))?;
+    let requests = requests
+        .trim()
+        .parse()
+        .ok()
+        .filter(|requests| *requests > 0)
+        .ok_or_else(|| format!("Invalid number of requests: {}", requests))?;
+    let per = per.trim();
+    let per = match per.starts_with(|c: char| c.is_ascii_digit()) {
+        true => humantime::parse_duration(per),
+        false => humantime::parse_duration(&format!("1{}", per)),
+    }
+    .map_err(|error| error.to_string())?;
+    if per.is_zero() {
+        return Err("The duration of the rate limit must not be 0".to_string());
+    }
+    Ok((requests, per))
+}
 
-            format!("/{}/{}", random_prefix, file_name)
+/// A recipient as name, optionally followed by the expiry and uses, e.g. alice:30m:2.
+fn parse_recipient(value: &str) -> Result<(String, Option<Duration>, Option<u16>), String> {
+    let mut parts = value.split(':');
+    let name = parts.next().unwrap_or_default().trim();
+    if name.is_empty() {
+        return Err(format!("Recipient must look like alice:30m: {}", value));
+    }
+    let expire_after = match parts.next().map(str::trim) {
+        Some(expire_after) if !expire_after.is_empty() => {
+            Some(humantime::parse_duration(expire_after).map_err(|error| error.to_string())?)
         }
-        None => format!("/{}", random_prefix),
+        _ => None,
+    };
+    let uses = match parts.next().map(str::trim) {
+        Some(uses) => Some(
+            uses.parse()
+                .ok()
+                .filter(|uses| *uses > 0)
+                .ok_or_else(|| format!("Invalid number of uses: {}", uses))?,
+        ),
+        None => None,
+    };
+    if parts.next().is_some() {
+        return Err(format!("Recipient must look like alice:30m: {}", value));
+    }
+    Ok((name.to_string(), expire_after, uses))
+}
+
+/// A network in CIDR notation, a single address is taken as network of its own.
+fn parse_network(value: &str) -> Result<IpNet, String> {
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Ok(IpNet::from(ip));
     }
+    value
+        .parse()
+        .map_err(|error: AddrParseError| error.to_string())
 }
 
-async fn shutdown_signal(mut shutdown_receiver: mpsc::Receiver<()>) {
+fn configure_listeners(builder: ShareBuilder, args: &Args) -> Result<ShareBuilder, Error> {
+    #[cfg(unix)]
+    if let Some(socket_path) = &args.unix_socket {
+        return Ok(builder.unix_socket(socket_path));
+    }
+
+    let detect_local_ip = |ipv6| match args.tailscale {
+        true => get_tailscale_ip(ipv6),
+        false => get_local_ip(args.bind_ip, ipv6),
+    };
+    let local_addresses = match args.dual_stack {
+        true => vec![detect_local_ip(false)?, detect_local_ip(true)?],
+        false => vec![detect_local_ip(args.ipv6)?],
+    };
+    Ok(local_addresses
+        .into_iter()
+        .fold(builder, ShareBuilder::bind_ip))
+}
+
+async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -290,9 +1820,5 @@ async fn shutdown_signal(mut shutdown_receiver: mpsc::Receiver<()>) {
     tokio::select! {
         _ = ctrl_c => {},
         _ = terminate => {},
-        _ = shutdown_receiver.recv() => {},
     }
 }
-
-#[cfg(test)]
-mod tests;
@@ -0,0 +1,137 @@
+//! Detection of the addresses to bind to and formatting of the shared urls.
+
+use crate::Error;
+use axum::{extract::connect_info::Connected, serve::IncomingStream};
+use local_ip_address::{list_afinet_netifas, local_ip, local_ipv6};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use tokio::net::TcpListener;
+
+/// Returns `bind_ip` if set, otherwise the local IPv4 or IPv6 address.
+pub fn get_local_ip(bind_ip: Option<IpAddr>, ipv6: bool) -> Result<IpAddr, Error> {
+    match bind_ip {
+        Some(ip) => Ok(ip),
+        None => match if ipv6 { local_ipv6() } else { local_ip() } {
+            Ok(ip) => Ok(ip),
+            Err(error) => Err(Error::LocalIp(error)),
+        },
+    }
+}
+
+/// Returns the tailnet address of a tailscale interface.
+pub fn get_tailscale_ip(ipv6: bool) -> Result<IpAddr, Error> {
+    let interfaces = list_afinet_netifas().map_err(Error::LocalIp)?;
+    interfaces
+        .into_iter()
+        .map(|(_, ip)| ip)
+        .find(|ip| ip.is_ipv6() == ipv6 && is_tailscale_ip(ip))
+        .ok_or(Error::NoTailnetAddress)
+}
+
+pub(crate) fn is_tailscale_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, _, _] = ip.octets();
+            a == 100 && (b & 0b1100_0000) == 64
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            segments[0] == 0xfd7a && segments[1] == 0x115c && segments[2] == 0xa1e0
+        }
+    }
+}
+
+/// Whether the address might be reachable from outside the local network.
+pub fn is_publicly_routable(ip: &IpAddr) -> bool {
+    match ip {
+        // Binding to all interfaces might include a public one
+        ip if ip.is_unspecified() => true,
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // Shared address space, e.g. carrier grade NAT or tailscale
+                || (a == 100 && (b & 0b1100_0000) == 64)
+                // IETF protocol assignments
+                || (a == 192 && b == 0 && c == 0)
+                // Benchmarking
+                || (a == 198 && (b & 0xfe) == 18)
+                // Reserved for future use
+                || a >= 240
+                || a == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_publicly_routable(&IpAddr::V4(ip)),
+            None => {
+                let first_segment = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    // Documentation
+                    || (first_segment == 0x2001 && ip.segments()[1] == 0x0db8))
+            }
+        },
+    }
+}
+
+/// Builds the url for a listener address, optionally showing another host.
+pub fn format_url(address: &SocketAddr, display_host: Option<&str>, url_path: &str) -> String {
+    match display_host {
+        // IPv6 addresses need to be wrapped in brackets to be usable in an url
+        Some(host) if host.parse::<Ipv6Addr>().is_ok() => {
+            format!("http://[{}]:{}{}", host, address.port(), url_path)
+        }
+        Some(host) => format!("http://{}:{}{}", host, address.port(), url_path),
+        // The Display implementation of SocketAddr already wraps IPv6 addresses in brackets
+        None => format!("http://{}{}", address, url_path),
+    }
+}
+
+pub(crate) async fn create_listener(
+    local_address: IpAddr,
+    port: u16,
+) -> Result<TcpListener, Error> {
+    let listener = match TcpListener::bind(SocketAddr::new(local_address, port)).await {
+        // Fall back to a dynamically assigned port, if the requested one is already taken
+        Err(_) if port != 0 => TcpListener::bind(SocketAddr::new(local_address, 0)).await,
+        result => result,
+    };
+    listener.map_err(Error::Bind)
+}
+
+#[cfg(unix)]
+pub(crate) fn create_unix_listener(
+    socket_path: &std::path::Path,
+) -> Result<tokio::net::UnixListener, Error> {
+    if socket_path.exists() {
+        // Don't remove an existing file, the path might have been mistyped
+        return Err(Error::UnixSocketExists(socket_path.to_path_buf()));
+    }
+    tokio::net::UnixListener::bind(socket_path)
+        .map_err(|error| Error::UnixSocketBind(socket_path.to_path_buf(), error))
+}
+
+/// The remote address of a connection, unix sockets don't have one.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Peer(Option<SocketAddr>);
+
+impl Peer {
+    pub(crate) fn address(&self) -> Option<SocketAddr> {
+        self.0
+    }
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Peer(Some(*stream.remote_addr()))
+    }
+}
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, tokio::net::UnixListener>> for Peer {
+    fn connect_info(_stream: IncomingStream<'_, tokio::net::UnixListener>) -> Self {
+        Peer(None)
+    }
+}
@@ -0,0 +1,99 @@
+//! Serving a Python package as a package index with a single project, so pip can install it.
+//!
+//! The index follows the simple repository API: pip looks up the project page and downloads
+//! the file linked there, only the latter consumes a use. Projects pip looks up for the
+//! dependencies are simply unknown, so they don't count as failed attempts.
+
+use axum::{
+    extract::State,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::Error;
+
+/// The project a package file belongs to, derived from its file name.
+#[derive(Debug, Clone)]
+pub(crate) struct Package {
+    pub(crate) project: String,
+    pub(crate) file_name: String,
+}
+
+impl Package {
+    pub(crate) fn from_file(file_path: &Path) -> Result<Package, Error> {
+        let error = |reason: &str| Error::Package(file_path.to_path_buf(), reason.to_string());
+        let file_name = file_path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .ok_or_else(|| error("invalid file name"))?;
+        // Wheels are named name-version-tags.whl, source distributions name-version.tar.gz
+        let name = if file_name.ends_with(".whl") {
+            file_name.split_once('-').map(|(name, _)| name)
+        } else if let Some(stem) = file_name
+            .strip_suffix(".tar.gz")
+            .or_else(|| file_name.strip_suffix(".zip"))
+        {
+            stem.rsplit_once('-').map(|(name, _)| name)
+        } else {
+            return Err(error(
+                "only Python wheels and source distributions are supported",
+            ));
+        };
+        let name = name
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| error("the file name doesn't contain a version"))?;
+        Ok(Package {
+            project: normalize_project_name(name),
+            file_name: file_name.to_string(),
+        })
+    }
+}
+
+/// Project names are compared case-insensitively, with runs of `-`, `_` and `.` being equal.
+pub(crate) fn normalize_project_name(name: &str) -> String {
+    let mut normalized = String::new();
+    for part in name.split(['-', '_', '.']).filter(|part| !part.is_empty()) {
+        if !normalized.is_empty() {
+            normalized.push('-');
+        }
+        normalized.push_str(&part.to_lowercase());
+    }
+    normalized
+}
+
+/// What the index pages link to, the checksum lets pip verify the download.
+#[derive(Debug)]
+pub(crate) struct PackageIndex {
+    pub(crate) package: Package,
+    pub(crate) sha256: String,
+}
+
+fn index_page(links: String) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/html"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        format!("<!DOCTYPE html>\n<html><body>\n{}\n</body></html>\n", links),
+    )
+        .into_response()
+}
+
+pub(crate) async fn project_list(State(index): State<Arc<PackageIndex>>) -> Response {
+    let project = &index.package.project;
+    index_page(format!("<a href=\"{}/\">{}</a>", project, project))
+}
+
+pub(crate) async fn project_page(State(index): State<Arc<PackageIndex>>) -> Response {
+    let file_name = &index.package.file_name;
+    index_page(format!(
+        "<a href=\"{}#sha256={}\">{}</a>",
+        file_name, index.sha256, file_name
+    ))
+}
+
+pub(crate) async fn unknown_project() -> Response {
+    (StatusCode::NOT_FOUND, "404 Not Found").into_response()
+}
@@ -0,0 +1,36 @@
+//! Spelling of the token in the NATO phonetic alphabet, for hand-offs over voice calls.
+
+const LETTERS: [&str; 26] = [
+    "alfa", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliett",
+    "kilo", "lima", "mike", "november", "oscar", "papa", "quebec", "romeo", "sierra", "tango",
+    "uniform", "victor", "whiskey", "x-ray", "yankee", "zulu",
+];
+
+const DIGITS: [&str; 10] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+];
+
+/// Splits the token into groups of four characters, each with its spelling.
+pub fn spell_token(token: &str) -> Vec<(String, String)> {
+    let chars: Vec<char> = token.chars().collect();
+    chars
+        .chunks(4)
+        .map(|group| {
+            let spelling: Vec<_> = group.iter().map(|&c| spell_char(c)).collect();
+            (group.iter().collect(), spelling.join(", "))
+        })
+        .collect()
+}
+
+fn spell_char(c: char) -> String {
+    match c {
+        'a'..='z' => LETTERS[usize::from(c as u8 - b'a')].to_string(),
+        // The token is case sensitive, so capitals have to be called out
+        'A'..='Z' => format!("capital {}", LETTERS[usize::from(c as u8 - b'A')]),
+        '0'..='9' => DIGITS[usize::from(c as u8 - b'0')].to_string(),
+        c => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests;
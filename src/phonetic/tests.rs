@@ -0,0 +1,15 @@
+use super::*;
+
+#[test]
+fn test_spell_token() {
+    assert_eq!(
+        spell_token("aB3xZ"),
+        [
+            (
+                "aB3x".to_string(),
+                "alfa, capital bravo, three, x-ray".to_string()
+            ),
+            ("Z".to_string(), "capital zulu".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,57 @@
+//! Fetching the secret from an upstream url only when the recipient asks for it, so the
+//! credentials for the upstream never leave this machine.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Method, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::hooks::HttpHook;
+
+pub(crate) struct Upstream {
+    pub(crate) client: reqwest::Client,
+    pub(crate) request: HttpHook,
+}
+
+/// The last segment of the upstream url, to name the shared url after it.
+pub(crate) fn file_name(upstream: &HttpHook) -> Option<PathBuf> {
+    let url = reqwest::Url::parse(upstream.url()).ok()?;
+    let file_name = url.path_segments()?.next_back()?;
+    (!file_name.is_empty()).then(|| PathBuf::from(file_name))
+}
+
+pub(crate) async fn fetch_upstream(
+    method: Method,
+    State(upstream): State<Arc<Upstream>>,
+) -> Response {
+    // A HEAD doesn't ask for the secret, the upstream is only asked for the download itself
+    if method == Method::HEAD {
+        return StatusCode::OK.into_response();
+    }
+    let response = match upstream
+        .request
+        .request(&upstream.client, &[])
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+    {
+        Ok(response) => response,
+        Err(error) => {
+            tracing::error!("Can't fetch the secret from upstream: {}", error);
+            return (StatusCode::BAD_GATEWAY, "502 Bad Gateway").into_response();
+        }
+    };
+    let mut proxied = Response::builder().status(StatusCode::OK);
+    for name in [header::CONTENT_TYPE, header::CONTENT_LENGTH] {
+        if let Some(value) = response.headers().get(&name) {
+            proxied = proxied.header(name, value);
+        }
+    }
+    proxied
+        .body(Body::from_stream(response.bytes_stream()))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
@@ -0,0 +1,96 @@
+//! Rate limiting per client IP address with a token bucket.
+//!
+//! Every client may send a burst of up to `requests` requests, afterwards it gets one more
+//! every `per / requests`. Requests over the limit get a 429 before anything else looks at
+//! them, so a scanner can't use up the failed attempts within a second.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::network::Peer;
+
+/// Allows `requests` requests `per` duration and client.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RateLimit {
+    pub(crate) requests: u32,
+    pub(crate) per: Duration,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    limit: Option<RateLimit>,
+    buckets: Arc<Mutex<HashMap<Option<IpAddr>, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: Option<RateLimit>) -> Self {
+        RateLimiter {
+            limit,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Takes a token from the bucket of the client, or returns how long until there is one.
+    fn acquire(&self, limit: RateLimit, client: Option<IpAddr>) -> Result<(), Duration> {
+        let capacity = f64::from(limit.requests);
+        let refill_interval = limit.per.as_secs_f64() / capacity;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        // Full buckets are no different from new ones, forgetting them keeps the map small
+        buckets.retain(|_, bucket| {
+            let refilled = now.duration_since(bucket.updated).as_secs_f64() / refill_interval;
+            bucket.tokens + refilled < capacity
+        });
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() / refill_interval;
+        bucket.tokens = (bucket.tokens + refilled).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) * refill_interval,
+            ));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+pub(crate) async fn limit_rate(
+    State(limiter): State<RateLimiter>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(limit) = limiter.limit.filter(|limit| limit.requests > 0) else {
+        return next.run(request).await;
+    };
+    let client = peer.address().map(|address| address.ip());
+    match limiter.acquire(limit, client) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(
+                header::RETRY_AFTER,
+                (wait.as_secs_f64().ceil() as u64).max(1).to_string(),
+            )],
+            "429 Too Many Requests",
+        )
+            .into_response(),
+    }
+}
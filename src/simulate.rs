@@ -0,0 +1,145 @@
+//! Scripted fake clients, to check the limits of a share end to end.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The dummy secret is larger than the socket buffers, so the share notices how slowly the
+/// slow downloader reads instead of handing the whole response off at once.
+const SIMULATED_SECRET_SIZE: usize = 8 * 1024 * 1024;
+
+/// The slow downloader reads at most this many bytes a second.
+const SLOW_DOWNLOAD_RATE: f64 = 2.0 * 1024.0 * 1024.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulatedClient {
+    /// Guesses some common paths on the server.
+    Scanner,
+    /// Fetches the url right after it was posted in a chat, like a link preview.
+    PrefetchBot,
+    /// Fetches the secret, but reads the response slowly.
+    SlowDownloader,
+    /// Fetches the secret like a browser.
+    Recipient,
+}
+
+impl SimulatedClient {
+    pub const ALL: [SimulatedClient; 4] = [
+        SimulatedClient::Scanner,
+        SimulatedClient::PrefetchBot,
+        SimulatedClient::SlowDownloader,
+        SimulatedClient::Recipient,
+    ];
+}
+
+impl Display for SimulatedClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SimulatedClient::Scanner => "scanner",
+            SimulatedClient::PrefetchBot => "prefetch-bot",
+            SimulatedClient::SlowDownloader => "slow-downloader",
+            SimulatedClient::Recipient => "recipient",
+        };
+        f.pad(name)
+    }
+}
+
+impl FromStr for SimulatedClient {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        SimulatedClient::ALL
+            .into_iter()
+            .find(|client| client.to_string() == name)
+            .ok_or_else(|| format!("Unknown client: {}", name))
+    }
+}
+
+/// The dummy secret shared for the simulation.
+pub fn simulated_secret() -> Vec<u8> {
+    "simulated secret\n"
+        .repeat(SIMULATED_SECRET_SIZE / 17)
+        .into_bytes()
+}
+
+/// A single request of a simulated client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attempt {
+    pub client: SimulatedClient,
+    pub path: String,
+    /// The response status, if the server answered at all.
+    pub status: Option<u16>,
+    pub bytes: usize,
+}
+
+/// Runs the clients one after another against the share at `url`.
+pub async fn simulate(url: &str, clients: &[SimulatedClient]) -> Vec<Attempt> {
+    let client = reqwest::Client::new();
+    let mut attempts = Vec::new();
+    for &simulated_client in clients {
+        let requests = match simulated_client {
+            SimulatedClient::Scanner => ["/", "/.env", "/admin"]
+                .iter()
+                .map(|path| {
+                    (
+                        origin(url).to_string() + path,
+                        "Mozilla/5.0 (compatible; scanner)",
+                    )
+                })
+                .collect(),
+            SimulatedClient::PrefetchBot => {
+                vec![(url.to_string(), "Slackbot-LinkExpanding 1.0")]
+            }
+            SimulatedClient::SlowDownloader | SimulatedClient::Recipient => {
+                vec![(
+                    url.to_string(),
+                    "Mozilla/5.0 (X11; Linux x86_64) Firefox/140.0",
+                )]
+            }
+        };
+        for (request_url, user_agent) in requests {
+            let response = client
+                .get(&request_url)
+                .header(reqwest::header::USER_AGENT, user_agent)
+                .send()
+                .await;
+            let (status, bytes) = match response {
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    (Some(status), read_body(simulated_client, response).await)
+                }
+                Err(_) => (None, 0),
+            };
+            attempts.push(Attempt {
+                client: simulated_client,
+                path: request_url[origin(&request_url).len()..].to_string(),
+                status,
+                bytes,
+            });
+        }
+    }
+    attempts
+}
+
+async fn read_body(client: SimulatedClient, mut response: reqwest::Response) -> usize {
+    let started = Instant::now();
+    let mut bytes = 0;
+    while let Ok(Some(chunk)) = response.chunk().await {
+        bytes += chunk.len();
+        if client == SimulatedClient::SlowDownloader {
+            let due = started + Duration::from_secs_f64(bytes as f64 / SLOW_DOWNLOAD_RATE);
+            tokio::time::sleep_until(due).await;
+        }
+    }
+    bytes
+}
+
+/// The scheme and host of an url, without the path.
+fn origin(url: &str) -> &str {
+    let host_start = url.find("://").map_or(0, |index| index + 3);
+    match url[host_start..].find('/') {
+        Some(index) => &url[..host_start + index],
+        None => url,
+    }
+}
@@ -0,0 +1,64 @@
+//! Reading the piped secret without holding all of it in memory, e.g. for `pg_dump | localsecret`.
+//!
+//! Small inputs stay in memory, larger ones are written to a temporary file as they come in.
+//! On unix, the file is deleted right after it was created and only read through its handle,
+//! so the secret doesn't stay on disk even if the process dies. Elsewhere it is deleted once
+//! it is dropped.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use tempfile::{NamedTempFile, TempPath};
+
+/// Inputs up to this size are kept in memory.
+pub const MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+pub enum Spool {
+    Memory(Vec<u8>),
+    /// The path is only left where an open file can't be deleted.
+    File {
+        file: File,
+        temp_path: Option<TempPath>,
+    },
+}
+
+/// Reads the input until its end, spilling over to a temporary file after `memory_limit` bytes.
+///
+/// Inputs larger than `maximum_size` fail with [`io::ErrorKind::FileTooLarge`].
+pub fn spool(input: impl Read, memory_limit: usize, maximum_size: usize) -> io::Result<Spool> {
+    let mut input = input.take(maximum_size as u64 + 1);
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            format!("The input is larger than {} bytes", maximum_size),
+        )
+    };
+    let mut buffer = Vec::new();
+    input
+        .by_ref()
+        .take(memory_limit as u64 + 1)
+        .read_to_end(&mut buffer)?;
+    if buffer.len() > maximum_size {
+        return Err(too_large());
+    }
+    if buffer.len() <= memory_limit {
+        return Ok(Spool::Memory(buffer));
+    }
+    let (mut file, temp_path) = NamedTempFile::new()?.into_parts();
+    #[cfg(unix)]
+    let temp_path = {
+        temp_path.close()?;
+        None
+    };
+    #[cfg(not(unix))]
+    let temp_path = Some(temp_path);
+    file.write_all(&buffer)?;
+    let size = buffer.len() as u64 + io::copy(&mut input, &mut file)?;
+    if size > maximum_size as u64 {
+        return Err(too_large());
+    }
+    file.flush()?;
+    Ok(Spool::File { file, temp_path })
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,34 @@
+use super::*;
+use std::io::{Seek, SeekFrom};
+
+#[test]
+fn test_spool_keeps_small_input_in_memory() {
+    let spool = spool(&b"secret: 42"[..], 16, 16).unwrap();
+    assert!(matches!(spool, Spool::Memory(content) if content == b"secret: 42"));
+}
+
+#[test]
+fn test_spool_spills_large_input_to_file() {
+    let spool = spool(&b"secret: 42"[..], 4, 16).unwrap();
+    let Spool::File {
+        mut file,
+        temp_path,
+    } = spool
+    else {
+        panic!("The input should have been spooled to a file");
+    };
+    let mut content = Vec::new();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.read_to_end(&mut content).unwrap();
+    assert_eq!(content, b"secret: 42");
+    // Nothing is left on disk
+    assert_eq!(temp_path.is_none(), cfg!(unix));
+}
+
+#[test]
+fn test_spool_refuses_input_over_maximum_size() {
+    for memory_limit in [4, 16] {
+        let error = spool(&b"secret: 42"[..], memory_limit, 8).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::FileTooLarge);
+    }
+}
@@ -1,29 +1,110 @@
 use super::*;
+use crate::control::ControlAddress;
+use crate::enrollment::{HMAC_HEADER, NONCE_HEADER, enrollment_hmac};
+use crate::exposure::{HostsEntry, parse_allocated_port};
+use crate::hooks::{HttpHook, render_template};
+use crate::network::{get_local_ip, is_tailscale_ip};
+use crate::package::Package;
+use crate::simulate::{SimulatedClient, simulate, simulated_secret};
 use std::fs::File;
 use tempfile::tempdir;
 
 #[test]
 fn test_get_local_ip_with_bind_ip() {
     let bind_ip = Some("10.11.12.13".parse().unwrap());
-    let result = get_local_ip(bind_ip);
+    let result = get_local_ip(bind_ip, false).unwrap();
     assert_eq!(result, "10.11.12.13".parse::<IpAddr>().unwrap());
 }
 
 #[test]
 fn test_get_local_ip_without_bind_ip() {
     let bind_ip = None;
-    let result = get_local_ip(bind_ip);
+    let result = get_local_ip(bind_ip, false).unwrap();
     assert!(result.is_ipv4() || result.is_ipv6());
     assert!(!result.is_loopback());
 }
 
+#[test]
+fn test_is_tailscale_ip() {
+    for ip in ["100.64.0.1", "100.101.102.103", "fd7a:115c:a1e0::1"] {
+        assert!(is_tailscale_ip(&ip.parse().unwrap()), "{}", ip);
+    }
+    for ip in ["100.128.0.1", "10.11.12.13", "fd00::2"] {
+        assert!(!is_tailscale_ip(&ip.parse().unwrap()), "{}", ip);
+    }
+}
+
+#[test]
+fn test_is_publicly_routable() {
+    for ip in [
+        "10.11.12.13",
+        "172.16.0.1",
+        "192.168.1.50",
+        "127.0.0.1",
+        "169.254.1.1",
+        "100.101.102.103",
+        "192.0.2.2",
+        "fd00::2",
+        "fe80::1",
+        "::1",
+        "::ffff:192.168.1.50",
+    ] {
+        assert!(
+            !is_publicly_routable(&ip.parse().unwrap()),
+            "{} should not be public",
+            ip
+        );
+    }
+    for ip in [
+        "8.8.8.8",
+        "0.0.0.0",
+        "2a00:1450:4001::1",
+        "::",
+        "::ffff:1.1.1.1",
+    ] {
+        assert!(
+            is_publicly_routable(&ip.parse().unwrap()),
+            "{} should be public",
+            ip
+        );
+    }
+}
+
+#[test]
+fn test_format_url_wraps_ipv6_in_brackets() {
+    let address: SocketAddr = "[fd00::2]:8080".parse().unwrap();
+    assert_eq!(
+        format_url(&address, None, "/prefix/file.txt"),
+        "http://[fd00::2]:8080/prefix/file.txt"
+    );
+
+    let address: SocketAddr = "10.11.12.13:8080".parse().unwrap();
+    assert_eq!(
+        format_url(&address, None, "/prefix"),
+        "http://10.11.12.13:8080/prefix"
+    );
+}
+
+#[test]
+fn test_format_url_with_display_host() {
+    let address: SocketAddr = "10.11.12.13:8080".parse().unwrap();
+    assert_eq!(
+        format_url(&address, Some("myhost.corp.example"), "/prefix"),
+        "http://myhost.corp.example:8080/prefix"
+    );
+    assert_eq!(
+        format_url(&address, Some("fd00::2"), "/prefix"),
+        "http://[fd00::2]:8080/prefix"
+    );
+}
+
 #[test]
 fn test_validate_and_get_absolute_path_valid_file() {
     let dir = tempdir().unwrap();
     let file_path = dir.path().join("test_file.txt");
     File::create(&file_path).unwrap();
 
-    let absolute_path = validate_and_get_absolute_path(&file_path);
+    let absolute_path = validate_and_get_absolute_path(&file_path).unwrap();
     assert_eq!(absolute_path, file_path.canonicalize().unwrap());
 }
 
@@ -31,7 +112,11 @@ fn test_validate_and_get_absolute_path_valid_file() {
 fn test_generate_file_url_path() {
     let file_path = PathBuf::from("test_file.txt");
     let url_prefix_length = 8;
-    let url_path = generate_file_url_path(&Some(file_path), url_prefix_length);
+    let url_path = generate_file_url_path(
+        &Some(file_path),
+        TokenStyle::Alphanumeric.generate(url_prefix_length, false),
+    )
+    .unwrap();
 
     assert!(url_path.starts_with('/'));
     assert!(url_path.ends_with("/test_file.txt"));
@@ -40,3 +125,2206 @@ fn test_generate_file_url_path() {
         url_prefix_length as usize
     );
 }
+
+#[test]
+fn test_generate_token_styles() {
+    // 42 letters and digits are 250 bits, which take 26 words of 10 bits
+    let token = TokenStyle::Words.generate(42, false);
+    let words: Vec<&str> = token.split('-').collect();
+    assert_eq!(words.len(), 26);
+    assert!(
+        words
+            .iter()
+            .all(|word| word.len() >= 2 && word.chars().all(|c| c.is_ascii_lowercase()))
+    );
+    assert_ne!(token, TokenStyle::Words.generate(42, false));
+    assert_eq!(TokenStyle::Words.generate(8, false).split('-').count(), 5);
+    assert_eq!(
+        TokenStyle::Words
+            .generate_exactly(4, false)
+            .split('-')
+            .count(),
+        4
+    );
+    // 3.3 bits per digit
+    assert_eq!(TokenStyle::Digits.generate(42, false).len(), 76);
+    // 5.8 instead of 6 bits per character
+    let token = TokenStyle::Unambiguous.generate(42, false);
+    assert_eq!(token.len(), 44);
+    assert!(
+        token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() && !"0Oo1lI".contains(c))
+    );
+}
+
+#[test]
+fn test_parse_allocated_port() {
+    assert_eq!(
+        parse_allocated_port("Allocated port 43210 for remote forward to 10.11.12.13:46497"),
+        Some(43210)
+    );
+    assert_eq!(
+        parse_allocated_port("Warning: Permanently added 'jumphost' to the list of known hosts."),
+        None
+    );
+}
+
+#[test]
+fn test_hosts_entry_is_added_and_removed() {
+    let dir = tempdir().unwrap();
+    let hosts_file = dir.path().join("hosts");
+    std::fs::write(&hosts_file, "192.168.1.1 router.lan\n").unwrap();
+    let addresses = ["10.11.12.13:8080".parse().unwrap()];
+
+    let hosts_entry = HostsEntry::add(&hosts_file, "lan", &addresses).unwrap();
+    let host_name = hosts_entry.host_name().to_string();
+    assert!(host_name.starts_with("secret-") && host_name.ends_with(".lan"));
+    assert_eq!(
+        hosts_entry.url("/prefix"),
+        format!("http://{}:8080/prefix", host_name)
+    );
+    let content = std::fs::read_to_string(&hosts_file).unwrap();
+    assert!(content.starts_with("192.168.1.1 router.lan\n10.11.12.13 secret-"));
+
+    hosts_entry.remove().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&hosts_file).unwrap(),
+        "192.168.1.1 router.lan\n"
+    );
+
+    // An entry that isn't removed explicitly, e.g. after an error, is removed when dropped
+    drop(HostsEntry::add(&hosts_file, "lan", &addresses).unwrap());
+    assert_eq!(
+        std::fs::read_to_string(&hosts_file).unwrap(),
+        "192.168.1.1 router.lan\n"
+    );
+}
+
+#[tokio::test]
+async fn test_share_builder_serves_content_once() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    assert_eq!(handle.urls().len(), 1);
+    let url = handle.urls()[0].clone();
+    assert!(url.ends_with(handle.url_path()));
+
+    let body = reqwest::get(&url).await.unwrap().text().await.unwrap();
+    assert_eq!(body, "secret: 42");
+
+    // The only use is consumed, so the server stops on its own
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_adds_response_headers() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .response_header("Cache-Control: private")
+        .unwrap()
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    let cache_control: Vec<_> = response
+        .headers()
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .collect();
+    assert_eq!(cache_control, ["private"]);
+    assert_eq!(response.headers()[header::REFERRER_POLICY], "no-referrer");
+    handle.wait().await.unwrap();
+
+    let handle = Share::builder()
+        .content("secret: 42")
+        .security_headers(false)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert!(
+        !response
+            .headers()
+            .contains_key(header::X_CONTENT_TYPE_OPTIONS)
+    );
+    handle.wait().await.unwrap();
+
+    assert!(Share::builder().response_header("no colon").is_err());
+    let handle = Share::builder()
+        .content("secret: 42")
+        .response_header("Content-Type: application/x-secret")
+        .unwrap()
+        .response_header("X-Hint: one")
+        .unwrap()
+        .response_header("x-hint: two")
+        .unwrap()
+        .failed_attempts(2)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+
+    // Only the secret gets the headers
+    let response = reqwest::get(format!("{}x", url)).await.unwrap();
+    assert!(!response.headers().contains_key("x-hint"));
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/x-secret"
+    );
+    let hints: Vec<_> = response.headers().get_all("x-hint").iter().collect();
+    assert_eq!(hints, ["one", "two"]);
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+    assert_eq!(response.headers()["x-robots-tag"], "noindex");
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_with_embargo_serves_countdown_without_consuming_uses() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .release_at(SystemTime::now() + Duration::from_secs(60))
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+
+    for _ in 0..3 {
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        assert!(response.text().await.unwrap().contains("released in"));
+    }
+
+    handle.shutdown_trigger().trigger();
+    handle.wait().await.unwrap();
+}
+
+#[test]
+fn test_is_preview_bot() {
+    assert!(is_preview_bot("facebookexternalhit/1.1"));
+    assert!(is_preview_bot("TelegramBot (like TwitterBot)"));
+    assert!(!is_preview_bot("curl/8.5.0"));
+    assert!(!is_preview_bot(
+        "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0"
+    ));
+}
+
+#[test]
+fn test_normalize_url_path() {
+    assert_eq!(normalize_url_path("/abc/secret.txt)."), "/abc/secret.txt");
+    assert_eq!(normalize_url_path("/a%62c/secret%2Etxt"), "/abc/secret.txt");
+    assert_eq!(
+        normalize_url_path("/abc/secret.txt%20%0A"),
+        "/abc/secret.txt"
+    );
+    assert_eq!(normalize_url_path("/abc/"), "/abc");
+}
+
+#[tokio::test]
+async fn test_normalize_url_keeps_query() {
+    let url_paths: Arc<[String]> = vec!["/abc/secret.txt".to_string()].into();
+    let router = Router::new().route(
+        "/abc/secret.txt",
+        get(|uri: Uri| async move { uri.to_string() }),
+    );
+    // Like the share, the url is fixed up before routing
+    let router = Router::new()
+        .fallback_service(router)
+        .layer(middleware::from_fn_with_state(
+            (url_paths, true),
+            normalize_url,
+        ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, router).into_future());
+    let response = reqwest::get(format!("http://{}/ABC/secret.txt).?x=1", address))
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "/abc/secret.txt?x=1");
+    let response = reqwest::get(format!("http://{}/abd/secret.txt?x=1", address))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_share_tolerates_mangled_urls() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+
+    let response = reqwest::get(format!("{}).", handle.urls()[0]))
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_asks_for_approval() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .approve_with(|peer, user_agent| peer.is_some() && user_agent != Some("prefetch-bot"))
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(&url)
+        .header(header::USER_AGENT, "prefetch-bot")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    // A HEAD isn't asked for, even a prefetcher may have it
+    let response = client
+        .head(&url)
+        .header(header::USER_AGENT, "prefetch-bot")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+    // The denied request didn't consume the use
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_turns_away_preview_bots() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+
+    for user_agent in [
+        "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)",
+        "Mozilla/5.0 (Windows NT 6.1; WOW64) SkypeUriPreview Preview/0.5",
+        "WhatsApp/2.23.20.0",
+    ] {
+        let response = client
+            .get(&url)
+            .header(header::USER_AGENT, user_agent)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.text().await.unwrap().contains("open the link"));
+    }
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[test]
+fn test_is_ignored_path() {
+    let ignored_paths = ["/favicon.ico".to_string(), "/.well-known/*".to_string()];
+    assert!(is_ignored_path(&ignored_paths, "/favicon.ico"));
+    assert!(is_ignored_path(&ignored_paths, "/.well-known/security.txt"));
+    assert!(!is_ignored_path(&ignored_paths, "/favicon.ico.bak"));
+    assert!(!is_ignored_path(&ignored_paths, "/.well-knownx"));
+}
+
+#[tokio::test]
+async fn test_share_ignores_paths_browsers_request() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .failed_attempts(1)
+        .ignore_path("/wpad.dat")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let address = handle.local_addresses()[0];
+
+    for path in ["/favicon.ico", "/.well-known/change-password", "/wpad.dat"] {
+        let response = reqwest::get(format!("http://{}{}", address, path))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_checks_host() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .display_host("secrets.example.test")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let address = handle.local_addresses()[0];
+    let url = format!("http://{}{}", address, handle.url_path());
+    let client = reqwest::Client::new();
+
+    for host in [
+        "rebound.example.com".to_string(),
+        format!("localhost:{}", address.port()),
+    ] {
+        let response = client
+            .get(&url)
+            .header(header::HOST, host)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+    }
+    handle.allow_url(&format!("http://localhost:{}/", address.port()));
+    let response = client
+        .get(&url)
+        .header(header::HOST, format!("LOCALHOST:{}", address.port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_with_instructions_page() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .failed_attempts(1)
+        .instructions_page(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let address = handle.local_addresses()[0];
+
+    for _ in 0..3 {
+        let response = reqwest::get(format!("http://{}/", address)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.text().await.unwrap().contains("full link"));
+    }
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_lock_out_after_failed_attempts() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .failed_attempts(1)
+        .lock_out_after(2)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let address = handle.local_addresses()[0];
+    let url = &handle.urls()[0];
+
+    // Loopback connections can come from any 127.0.0.0/8 address
+    let scanner = reqwest::Client::builder()
+        .local_address(IpAddr::from([127, 0, 0, 2]))
+        .build()
+        .unwrap();
+    for path in ["/.env", "/admin"] {
+        let response = scanner
+            .get(format!("http://{}{}", address, path))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let response = scanner.get(url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = reqwest::get(format!("http://{}/.git/config", address))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = reqwest::get(url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tarpit_delays_failed_attempts() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .failed_attempts(3)
+        .tarpit(Duration::from_millis(200))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let address = handle.local_addresses()[0];
+
+    for expected_delay in [200, 400] {
+        let start = Instant::now();
+        let response = reqwest::get(format!("http://{}/.env", address))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(start.elapsed() >= Duration::from_millis(expected_delay));
+    }
+    let start = Instant::now();
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    assert!(start.elapsed() < Duration::from_millis(200));
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_matches_case_insensitively() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("Report.txt");
+    std::fs::write(&file_path, "secret: 42").unwrap();
+    let handle = Share::builder()
+        .file(&file_path)
+        .uses(2)
+        .case_insensitive(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let token = url.split('/').nth(3).unwrap();
+    // 36 instead of 62 characters take 49 instead of 42
+    assert_eq!(token.len(), 49);
+    assert_eq!(token, token.to_lowercase());
+
+    let (origin, path) = url.split_at(url.find(token).unwrap());
+    let response = reqwest::get(format!("{}{}", origin, path.to_uppercase()))
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    let response = reqwest::get(format!("{}{}.", origin, path.to_lowercase()))
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_digit_token_is_tarpitted() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .token_style(TokenStyle::Digits)
+        .token_length(9)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let address = handle.local_addresses()[0];
+    let url = handle.urls()[0].clone();
+    let token = url.rsplit('/').next().unwrap();
+    assert_eq!(token.len(), 9);
+    assert!(token.chars().all(|c| c.is_ascii_digit()));
+
+    let start = Instant::now();
+    let response = reqwest::get(format!("http://{}/123456789", address))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rate_limit_spares_failed_attempts() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .failed_attempts(3)
+        .rate_limit(2, Duration::from_secs(2))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let address = handle.local_addresses()[0];
+
+    let mut statuses = Vec::new();
+    for _ in 0..4 {
+        let response = reqwest::get(format!("http://{}/.env", address))
+            .await
+            .unwrap();
+        statuses.push(response.status());
+    }
+    assert_eq!(statuses[..2], [StatusCode::NOT_FOUND; 2]);
+    assert_eq!(statuses[2..], [StatusCode::TOO_MANY_REQUESTS; 2]);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_filters_networks() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .failed_attempts(1)
+        .allow_network("127.0.0.0/8".parse().unwrap())
+        .deny_network("127.0.0.1/32".parse().unwrap())
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let address = handle.local_addresses()[0];
+    let url = &handle.urls()[0];
+
+    for url in [url.clone(), format!("http://{}/.env", address)] {
+        let response = reqwest::get(url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+    let client = reqwest::Client::builder()
+        .local_address(IpAddr::from([127, 0, 0, 2]))
+        .build()
+        .unwrap();
+    let response = client.get(url).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_allows_cors_origins() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .uses(2)
+        .failed_attempts(1)
+        .cors_origin("https://Tools.example.com/")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+
+    // The preflight neither consumes a use nor counts as failed attempt
+    let response = client
+        .request(reqwest::Method::OPTIONS, &url)
+        .header(header::ORIGIN, "https://tools.example.com")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "range")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://tools.example.com"
+    );
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS],
+        "range"
+    );
+
+    for (origin, allowed) in [
+        ("https://tools.example.com", true),
+        ("https://evil.example.com", false),
+    ] {
+        let response = client
+            .get(&url)
+            .header(header::ORIGIN, origin)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            allowed
+        );
+        assert_eq!(response.text().await.unwrap(), "secret: 42");
+    }
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_captures_requests() {
+    let dir = tempdir().unwrap();
+    let capture_path = dir.path().join("capture.har");
+    let handle = Share::builder()
+        .content("secret: 42")
+        .capture(&capture_path, false)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let address = handle.local_addresses()[0];
+    let token = url.rsplit('/').next().unwrap().to_string();
+    let client = reqwest::Client::new();
+    for url in [format!("{}x?q=1", url), url] {
+        client
+            .get(&url)
+            .header(header::COOKIE, "session=1")
+            .send()
+            .await
+            .unwrap();
+    }
+    handle.wait().await.unwrap();
+
+    let capture = std::fs::read_to_string(&capture_path).unwrap();
+    let har: serde_json::Value = serde_json::from_str(&capture).unwrap();
+    let entries = har["log"]["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(
+        entries[0]["request"]["url"],
+        format!("http://{}/REDACTED-WRONG-TOKEN?q=1", address)
+    );
+    assert_eq!(entries[0]["response"]["status"], 404);
+    assert_eq!(
+        entries[1]["request"]["url"],
+        format!("http://{}/REDACTED-TOKEN", address)
+    );
+    assert_eq!(entries[1]["response"]["status"], 200);
+    assert_eq!(entries[1]["response"]["bodySize"], 10);
+    // Neither the token nor the cookie end up in the file
+    assert!(!capture.contains(&token));
+    assert!(!capture.contains("session=1"));
+}
+
+#[tokio::test]
+async fn test_share_captures_all_headers_without_token() {
+    let dir = tempdir().unwrap();
+    let capture_path = dir.path().join("capture.har");
+    let handle = Share::builder()
+        .content("secret: 42")
+        .capture(&capture_path, true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let address = handle.local_addresses()[0];
+    let token = url.rsplit('/').next().unwrap().to_string();
+
+    // Like the reveal button of the click-through page, which sends the url as referer
+    reqwest::Client::new()
+        .get(&url)
+        .header(header::REFERER, format!("{}?reveal=1", url))
+        .send()
+        .await
+        .unwrap();
+    handle.wait().await.unwrap();
+
+    let capture = std::fs::read_to_string(&capture_path).unwrap();
+    let har: serde_json::Value = serde_json::from_str(&capture).unwrap();
+    let headers = har["log"]["entries"][0]["request"]["headers"]
+        .as_array()
+        .unwrap();
+    let referer = headers
+        .iter()
+        .find(|header| header["name"] == "referer")
+        .unwrap();
+    assert_eq!(
+        referer["value"],
+        format!("http://{}/REDACTED-TOKEN?reveal=1", address)
+    );
+    assert!(!capture.contains(&token));
+}
+
+#[tokio::test]
+async fn test_share_with_click_through() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .click_through(true)
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+
+    for _ in 0..3 {
+        let response = client.get(&url).send().await.unwrap();
+        assert!(
+            response
+                .text()
+                .await
+                .unwrap()
+                .contains("<form method=\"post\">")
+        );
+    }
+    let response = client.post(&url).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_requires_enrollment() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .enrollment_key("fleet key")
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+    let fetch_nonce = async || {
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        response.headers()[NONCE_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+
+    let nonce = fetch_nonce().await;
+    let response = client
+        .get(&url)
+        .header(NONCE_HEADER, &nonce)
+        .header(HMAC_HEADER, enrollment_hmac(b"wrong key", &nonce))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    // The nonce is used up, even by a wrong proof
+    let response = client
+        .get(&url)
+        .header(NONCE_HEADER, &nonce)
+        .header(HMAC_HEADER, enrollment_hmac(b"fleet key", &nonce))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let nonce = fetch_nonce().await;
+    // Another client asking for many nonces doesn't push out this one, nor can it use it
+    let other_client = reqwest::Client::builder()
+        .local_address("127.0.0.2".parse::<IpAddr>().unwrap())
+        .build()
+        .unwrap();
+    for _ in 0..100 {
+        let response = other_client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = other_client
+        .get(&url)
+        .header(NONCE_HEADER, &nonce)
+        .header(HMAC_HEADER, enrollment_hmac(b"fleet key", &nonce))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .get(&url)
+        .header(NONCE_HEADER, &nonce)
+        .header(HMAC_HEADER, enrollment_hmac(b"fleet key", &nonce))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_emits_events() {
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded_events = events.clone();
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .on_event(move |event| recorded_events.lock().unwrap().push(event.clone()))
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let address = handle.local_addresses()[0];
+
+    reqwest::get(format!("http://{}/nope", address))
+        .await
+        .unwrap();
+    reqwest::get(&url).await.unwrap();
+    handle.wait().await.unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[0], Event::UrlGenerated { url });
+    assert!(matches!(&events[1], Event::Failed { path, peer: Some(_), .. } if path == "/nope"));
+    assert!(matches!(
+        events[2],
+        Event::Accessed {
+            peer: Some(_),
+            bytes: Some(10),
+            ..
+        }
+    ));
+    assert_eq!(events[3], Event::Shutdown(ShutdownReason::UsesExhausted));
+}
+
+#[tokio::test]
+async fn test_share_reports_progress() {
+    let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded_reports = reports.clone();
+    let content = "secret: 42\n".repeat(20_000);
+    let handle = Share::builder()
+        .content(content.clone())
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .on_progress(move |progress| recorded_reports.lock().unwrap().push(progress.clone()))
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), content);
+    handle.wait().await.unwrap();
+
+    let reports = reports.lock().unwrap();
+    let total = Some(content.len() as u64);
+    assert!(reports.iter().all(|progress| progress.total == total));
+    assert!(reports.windows(2).all(|pair| pair[0].sent <= pair[1].sent));
+    let last = reports.last().unwrap();
+    assert!(last.done);
+    assert_eq!(last.sent, content.len() as u64);
+    assert!(last.peer.is_some());
+}
+
+#[tokio::test]
+async fn test_share_turns_away_wrong_tokens() {
+    let paths = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded_paths = paths.clone();
+    let handle = Share::builder()
+        .content("secret: 42")
+        .failed_attempts(3)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .on_event(move |event| {
+            if let Event::Failed { path, .. } = event {
+                recorded_paths.lock().unwrap().push(path.clone());
+            }
+        })
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let address = handle.local_addresses()[0];
+    let token = url.rsplit('/').next().unwrap();
+
+    let last = match token.ends_with('x') {
+        true => 'y',
+        false => 'x',
+    };
+    let almost_token = format!("{}{}", &token[..token.len() - 1], last);
+    for path in [format!("/{}", almost_token), format!("/{}/secret", token)] {
+        let response = reqwest::get(format!("http://{}{}?query", address, path))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+    // The failed paths are redacted, they still carry the token or most of it
+    assert_eq!(
+        *paths.lock().unwrap(),
+        ["/REDACTED-WRONG-TOKEN", "/REDACTED-TOKEN/secret"]
+    );
+}
+
+#[tokio::test]
+async fn test_share_with_recipients() {
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded_events = events.clone();
+    let handle = Share::builder()
+        .content("secret: 42")
+        .recipient("alice", 1, None)
+        .recipient("bob", 2, Some(Duration::from_secs(1)))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .on_event(move |event| match event {
+            Event::Accessed { recipient, .. } => recorded_events
+                .lock()
+                .unwrap()
+                .push(format!("accessed by {:?}", recipient)),
+            Event::Shutdown(reason) => recorded_events
+                .lock()
+                .unwrap()
+                .push(format!("{:?}", reason)),
+            _ => {}
+        })
+        .serve()
+        .await
+        .unwrap();
+    let [alice, bob] = handle.recipients() else {
+        panic!("expected two recipients");
+    };
+    assert_eq!(alice.name, "alice");
+    assert_eq!(handle.urls(), [alice.urls[0].clone(), bob.urls[0].clone()]);
+    assert_ne!(alice.url_path, bob.url_path);
+    let (alice, bob) = (alice.urls[0].clone(), bob.urls[0].clone());
+    let address = handle.local_addresses()[0];
+
+    // The url of the share itself is only for the recipients
+    let response = reqwest::get(format!("http://{}{}", address, handle.url_path()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = reqwest::get(&alice).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    let response = reqwest::get(&alice).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+    let response = reqwest::get(&bob).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+
+    // Bob has a use left, but the server stops once that url expired
+    handle.wait().await.unwrap();
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            "accessed by Some(\"alice\")",
+            "accessed by Some(\"bob\")",
+            "Expired"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_share_expires_when_last_use_follows_expired_link() {
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded_events = events.clone();
+    let handle = Share::builder()
+        .content("secret: 42")
+        .recipient("alice", 1, None)
+        .recipient("bob", 1, Some(Duration::from_millis(200)))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .on_event(move |event| {
+            if let Event::Shutdown(reason) = event {
+                recorded_events.lock().unwrap().push(*reason);
+            }
+        })
+        .serve()
+        .await
+        .unwrap();
+    let alice = handle.recipients()[0].urls[0].clone();
+
+    // Bob never fetched the secret, so the share isn't used up, even though no link is left
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    let response = reqwest::get(&alice).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+    assert_eq!(*events.lock().unwrap(), [ShutdownReason::Expired]);
+}
+
+#[tokio::test]
+async fn test_share_rejects_unexpected_requests() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+
+    let response = client.get(&url).body("payload").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let response = client
+        .get(&url)
+        .header("X-Padding", "x".repeat(MAX_HEADERS_SIZE))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+    );
+
+    let response = client.delete(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    // None of them consumed the use
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_limits_body_size() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .failed_attempts(1)
+        .max_body_size(16)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+
+    // Turned away before anything reads the body, so it doesn't count as failed attempt
+    let response = reqwest::Client::new()
+        .post(format!("{}/upload", url))
+        .body("x".repeat(32))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_with_afterlife() {
+    let late_attempts = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded_attempts = late_attempts.clone();
+    let handle = Share::builder()
+        .content("secret: 42")
+        .afterlife(Duration::from_secs(60))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .on_event(move |event| {
+            if let Event::LateAttempt { path, .. } = event {
+                recorded_attempts.lock().unwrap().push(path.clone());
+            }
+        })
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let shutdown_trigger = handle.shutdown_trigger();
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    let waiting = tokio::spawn(handle.wait());
+
+    // The listener comes back after the share stopped
+    let mut response = reqwest::get(&url).await;
+    for _ in 0..50 {
+        if response
+            .as_ref()
+            .is_ok_and(|response| response.status() == StatusCode::GONE)
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        response = reqwest::get(&url).await;
+    }
+    let response = response.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert!(response.text().await.unwrap().contains("has ended at"));
+    assert!(!late_attempts.lock().unwrap().is_empty());
+
+    // Stopping once more ends the afterlife
+    shutdown_trigger.trigger();
+    waiting.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_share_stops_after_panic() {
+    let reasons = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded_reasons = reasons.clone();
+    let handle = Share::builder()
+        .content("secret: 42")
+        .uses(2)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .on_event(move |event| match event {
+            Event::Failed { .. } => panic!("hook failed"),
+            Event::Shutdown(reason) => recorded_reasons.lock().unwrap().push(*reason),
+            _ => {}
+        })
+        .serve()
+        .await
+        .unwrap();
+
+    let address = handle.local_addresses()[0];
+    let response = reqwest::get(format!("http://{}/nope", address))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    handle.wait().await.unwrap();
+
+    assert_eq!(*reasons.lock().unwrap(), vec![ShutdownReason::Panicked]);
+}
+
+#[tokio::test]
+async fn test_share_stops_after_panic_elsewhere() {
+    let reasons = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded_reasons = reasons.clone();
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .on_event(move |event| {
+            if let Event::Shutdown(reason) = event {
+                recorded_reasons.lock().unwrap().push(*reason);
+            }
+        })
+        .serve()
+        .await
+        .unwrap();
+
+    handle.shutdown_trigger().trigger_panicked();
+    handle.wait().await.unwrap();
+
+    assert_eq!(*reasons.lock().unwrap(), vec![ShutdownReason::Panicked]);
+}
+
+#[tokio::test]
+async fn test_share_head_requests_dont_consume_uses() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+
+    for _ in 0..3 {
+        let response = client.head(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+    }
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_serves_file_from_memory() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("secret.json");
+    std::fs::write(&file_path, "0123456789").unwrap();
+    let handle = Share::builder()
+        .file(&file_path)
+        .in_memory(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    std::fs::remove_file(&file_path).unwrap();
+    let client = reqwest::Client::new();
+
+    // Like a served file, the parts of a ranged download take a single use together
+    let response = client
+        .get(&url)
+        .header(header::RANGE, "bytes=0-3")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 0-3/10");
+    assert_eq!(response.text().await.unwrap(), "0123");
+    let response = client
+        .get(&url)
+        .header(header::RANGE, "bytes=4-")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "456789");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_aborts_on_change() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("secret.txt");
+    std::fs::write(&file_path, "secret: 42").unwrap();
+    let handle = Share::builder()
+        .file(&file_path)
+        .abort_on_change(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+
+    std::fs::write(&file_path, "secret: 4").unwrap();
+    // Either the check before serving or the polling catches the change first
+    if let Ok(response) = reqwest::get(&url).await {
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+    let result = tokio::time::timeout(Duration::from_secs(3), handle.wait())
+        .await
+        .unwrap();
+    assert!(matches!(result, Err(Error::SecretChanged(_))));
+}
+
+#[tokio::test]
+async fn test_share_serves_binary_content() {
+    let content = vec![0x89, b'P', b'N', b'G', 0xff, 0x00, 0xfe];
+    let handle = Share::builder()
+        .content(content.clone())
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/octet-stream"
+    );
+    assert_eq!(response.bytes().await.unwrap(), content);
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_with_content_type() {
+    let handle = Share::builder()
+        .content(r#"{"secret": 42}"#)
+        .response_header("Content-Type: text/html")
+        .unwrap()
+        .content_type("application/json")
+        .unwrap()
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    let content_types: Vec<_> = response
+        .headers()
+        .get_all(header::CONTENT_TYPE)
+        .iter()
+        .collect();
+    assert_eq!(content_types, ["application/json"]);
+    handle.wait().await.unwrap();
+    assert!(Share::builder().content_type("json").is_err());
+
+    // The content type wins over a response header given after it as well
+    let handle = Share::builder()
+        .content(r#"{"secret": 42}"#)
+        .content_type("application/json")
+        .unwrap()
+        .response_header("Content-Type: text/html")
+        .unwrap()
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    let content_types: Vec<_> = response
+        .headers()
+        .get_all(header::CONTENT_TYPE)
+        .iter()
+        .collect();
+    assert_eq!(content_types, ["application/json"]);
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_with_name() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("prod-db-root-password.txt");
+    std::fs::write(&file_path, "hunter2").unwrap();
+    let handle = Share::builder()
+        .file(&file_path)
+        .name("password.txt")
+        .unwrap()
+        .download(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    assert!(url.ends_with("/password.txt"));
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"password.txt\"; filename*=UTF-8''password.txt"
+    );
+    assert_eq!(response.text().await.unwrap(), "hunter2");
+    handle.wait().await.unwrap();
+
+    let handle = Share::builder()
+        .content("hunter2")
+        .name("password.txt")
+        .unwrap()
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    assert!(url.ends_with("/password.txt"));
+    assert_eq!(
+        reqwest::get(&url).await.unwrap().text().await.unwrap(),
+        "hunter2"
+    );
+    handle.wait().await.unwrap();
+
+    for name in ["", "..", "secrets/password.txt"] {
+        assert!(Share::builder().name(name).is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_share_hides_file_name() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("prod.env");
+    std::fs::write(&file_path, "SECRET=42").unwrap();
+    let handle = Share::builder()
+        .file(&file_path)
+        .hide_file_name(true)
+        .checksum_url(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    assert!(!url.contains("prod"));
+    assert_eq!(
+        reqwest::Url::parse(&url)
+            .unwrap()
+            .path_segments()
+            .unwrap()
+            .count(),
+        1
+    );
+    let checksum = reqwest::get(format!("{}.sha256", url)).await.unwrap();
+    assert!(checksum.text().await.unwrap().ends_with("  prod.env\n"));
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "inline; filename=\"prod.env\"; filename*=UTF-8''prod.env"
+    );
+    assert_eq!(response.text().await.unwrap(), "SECRET=42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_sniffs_content_type() {
+    let handle = Share::builder()
+        .content(b"\x1f\x8b\x08\x00\x00\x00\x00\x00".to_vec())
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/gzip");
+    handle.wait().await.unwrap();
+
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("screenshot");
+    std::fs::write(&file_path, b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR").unwrap();
+    for in_memory in [false, true] {
+        let handle = Share::builder()
+            .file(&file_path)
+            .in_memory(in_memory)
+            .bind_ip("127.0.0.1".parse().unwrap())
+            .serve()
+            .await
+            .unwrap();
+        let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        handle.wait().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_share_asks_to_download() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("prod.env");
+    std::fs::write(&file_path, "SECRET=42").unwrap();
+    let handle = Share::builder()
+        .file(&file_path)
+        .download(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"prod.env\"; filename*=UTF-8''prod.env"
+    );
+    handle.wait().await.unwrap();
+
+    let handle = Share::builder()
+        .content("secret: 42")
+        .download_name("schlüssel \"1\".pem")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"schl_ssel _1_.pem\"; filename*=UTF-8''schl%C3%BCssel%20%221%22.pem"
+    );
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_serves_spooled_content() {
+    // More than one chunk is read from the file
+    let content = "secret: 42\n".repeat(20_000);
+    let mut spool_file = tempfile::tempfile().unwrap();
+    io::Write::write_all(&mut spool_file, content.as_bytes()).unwrap();
+    let handle = Share::builder()
+        .spooled_content(spool_file)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    // Like content, the url has no file name
+    assert_eq!(handle.url_path().matches('/').count(), 1);
+    let response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/plain; charset=utf-8"
+    );
+    assert_eq!(response.text().await.unwrap(), content);
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_serves_checksum() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .checksum_url(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let checksum = handle.checksum().unwrap().to_string();
+    let token = handle.url_path()[1..].to_string();
+
+    // Fetching the checksum doesn't consume the use
+    for _ in 0..2 {
+        let response = reqwest::get(format!("{}.sha256", url)).await.unwrap();
+        assert_eq!(
+            response.text().await.unwrap(),
+            format!("{}  {}\n", checksum, token)
+        );
+    }
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    assert_eq!(
+        checksum,
+        format!("{:x}", Sha256::digest("secret: 42".as_bytes()))
+    );
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_checksum_waits_for_release() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .checksum_url(true)
+        .release_at(SystemTime::now() + Duration::from_secs(60))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let checksum = handle.checksum().unwrap().to_string();
+
+    let response = reqwest::get(format!("{}.sha256", url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(!response.text().await.unwrap().contains(&checksum));
+
+    handle.shutdown_trigger().trigger();
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_with_retry_window() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .retry_window(Duration::from_secs(1))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let other_client = reqwest::Client::builder()
+        .local_address("127.0.0.2".parse::<IpAddr>().unwrap())
+        .build()
+        .unwrap();
+
+    for _ in 0..2 {
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "secret: 42");
+    }
+    // The only use is gone, the retry is only for the client that fetched the secret
+    let response = other_client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_locks_first_client() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .uses(2)
+        .lock_first_client(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let other_client = reqwest::Client::builder()
+        .local_address("127.0.0.2".parse::<IpAddr>().unwrap())
+        .build()
+        .unwrap();
+
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    let response = other_client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_locks_first_client_before_its_download_ends() {
+    let content = "secret: 42\n".repeat(1 << 20);
+    let handle = Share::builder()
+        .content(content.clone())
+        .uses(2)
+        .lock_first_client(true)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let other_client = reqwest::Client::builder()
+        .local_address("127.0.0.2".parse::<IpAddr>().unwrap())
+        .build()
+        .unwrap();
+
+    // The first download is still running when the other client asks
+    let response = reqwest::get(&url).await.unwrap();
+    let other_response = other_client.get(&url).send().await.unwrap();
+    assert_eq!(other_response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response.text().await.unwrap(), content);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), content);
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_is_adjusted_via_control_socket() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .expire_after(Duration::from_secs(60))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let control_socket = handle
+        .controller()
+        .listen(&"127.0.0.1:0".parse().unwrap())
+        .unwrap();
+    let address = control_socket.address().clone();
+    let token = control_socket.token().unwrap().to_string();
+
+    let error = control::send(&address, "guessed", "revoke")
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::Control(message) if message == "Wrong control token"));
+    let reply = control::send(&address, &token, "extend-uses 1")
+        .await
+        .unwrap();
+    assert_eq!(reply, "uses: 0/2");
+    let reply = control::send(&address, &token, "extend-time 10m")
+        .await
+        .unwrap();
+    assert!(reply.starts_with("expires in: 10m"), "{}", reply);
+    let error = control::send(&address, &token, "extend-uses many")
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::Control(_)));
+
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "secret: 42");
+    let status = handle.controller().status().await;
+    assert_eq!((status.used_uses, status.maximum_uses), (1, 2));
+    assert!(status.expires_in.unwrap() > Duration::from_secs(600));
+
+    let reply = control::send(&address, &token, "revoke").await.unwrap();
+    assert_eq!(reply, "revoked");
+    handle.wait().await.unwrap();
+    control_socket.close();
+}
+
+#[tokio::test]
+async fn test_share_with_recipients_isnt_extended() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .recipient("alice", 1, Some(Duration::from_secs(120)))
+        .recipient("bob", 1, Some(Duration::from_secs(60)))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let controller = handle.controller();
+
+    let error = controller.execute("extend-uses 1").await.unwrap_err();
+    assert_eq!(
+        error,
+        "The share has recipients, the uses of their links can't be extended"
+    );
+    let error = controller.execute("extend-time 10m").await.unwrap_err();
+    assert_eq!(
+        error,
+        "Recipient links of the share expire, their time can't be extended"
+    );
+    let status = controller.status().await;
+    assert_eq!((status.used_uses, status.maximum_uses), (0, 2));
+    // The share stops once the last link expired, even though the share itself doesn't expire
+    assert!(status.expires_in.unwrap() > Duration::from_secs(100));
+    let reply = controller.execute("status").await.unwrap();
+    assert!(!reply.contains("expires in: never"), "{}", reply);
+    controller.execute("revoke").await.unwrap();
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_serves_status() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .uses(2)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let status_endpoint = handle
+        .controller()
+        .serve_status(&"127.0.0.1:0".parse().unwrap())
+        .unwrap();
+    let status_url = format!("http://{}/status", status_endpoint.address());
+
+    let status = reqwest::get(&status_url)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let status: serde_json::Value = serde_json::from_str(&status).unwrap();
+    assert_eq!(status["uses_left"], 2);
+    assert_eq!(status["failed_attempts"], 0);
+    assert_eq!(status["expires_in_seconds"], serde_json::Value::Null);
+    assert_eq!(status["last_access"], serde_json::Value::Null);
+
+    reqwest::get(&url).await.unwrap().text().await.unwrap();
+    reqwest::get(format!("{}x", url)).await.unwrap();
+    let status = reqwest::get(&status_url)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let status: serde_json::Value = serde_json::from_str(&status).unwrap();
+    assert_eq!(status["uses_left"], 1);
+    assert_eq!(status["failed_attempts"], 1);
+    assert!(status["last_access"].is_string());
+
+    // A web page that rebound its domain to the loopback address can't read the status
+    let response = reqwest::Client::new()
+        .get(&status_url)
+        .header(header::HOST, "attacker.example")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+
+    handle.shutdown_trigger().trigger();
+    handle.wait().await.unwrap();
+    status_endpoint.close();
+}
+
+#[tokio::test]
+async fn test_status_has_labels() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .label("ticket", "OPS-1")
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+
+    let status = handle.controller().status().await;
+    assert_eq!(
+        status.to_json()["labels"],
+        serde_json::json!({"ticket": "OPS-1"})
+    );
+    assert!(status.to_string().ends_with("\nlabels: ticket=OPS-1"));
+
+    handle.shutdown_trigger().trigger();
+    handle.wait().await.unwrap();
+}
+
+#[test]
+fn test_control_address_is_local_only() {
+    assert_eq!(
+        "4242".parse::<ControlAddress>(),
+        Ok(ControlAddress::Tcp("127.0.0.1:4242".parse().unwrap()))
+    );
+    assert!("[::1]:4242".parse::<ControlAddress>().is_ok());
+    assert!("192.168.1.10:4242".parse::<ControlAddress>().is_err());
+}
+
+#[tokio::test]
+async fn test_share_counts_ranged_download_as_one_use() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("secret.txt");
+    std::fs::write(&file_path, "0123456789").unwrap();
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded_events = events.clone();
+    let handle = Share::builder()
+        .file(&file_path)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .on_event(move |event| recorded_events.lock().unwrap().push(event.clone()))
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+
+    let mut content = String::new();
+    for range in ["bytes=0-3", "bytes=2-6", "bytes=7-9"] {
+        let response = client
+            .get(&url)
+            .header(header::RANGE, range)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        content.push_str(&response.text().await.unwrap());
+    }
+    assert_eq!(content, "012323456789");
+    handle.wait().await.unwrap();
+
+    let events = events.lock().unwrap();
+    assert!(matches!(
+        events[1],
+        Event::Accessed {
+            bytes: Some(10),
+            ..
+        }
+    ));
+    assert_eq!(events[2], Event::Shutdown(ShutdownReason::UsesExhausted));
+}
+
+#[tokio::test]
+async fn test_share_gives_back_use_of_unsatisfiable_range() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("secret.txt");
+    std::fs::write(&file_path, "0123456789").unwrap();
+    let handle = Share::builder()
+        .file(&file_path)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(&url)
+        .header(header::RANGE, "bytes=20-30")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    // The only use isn't held by the failed range until the session times out
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "0123456789");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_gives_back_use_of_not_modified() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("secret.txt");
+    std::fs::write(&file_path, "0123456789").unwrap();
+    let handle = Share::builder()
+        .file(&file_path)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(&url)
+        .header(header::IF_MODIFIED_SINCE, "Fri, 01 Jan 2100 00:00:00 GMT")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    // The only use is still there, the 304 sent nothing of the secret
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "0123456789");
+    handle.wait().await.unwrap();
+}
+
+#[test]
+fn test_range_session_outlives_timeout_while_sending() {
+    let client = Some("127.0.0.1".parse().unwrap());
+    let mut count = UseCount {
+        in_flight: 1,
+        ..UseCount::default()
+    };
+    // A single part of the whole file that takes longer than the timeout to send
+    count.range_sessions.push(RangeSession {
+        client,
+        recipient: None,
+        peer: None,
+        user_agent: None,
+        total: None,
+        received: Vec::new(),
+        sending: 1,
+        last_seen: Instant::now() - RANGE_SESSION_TIMEOUT,
+    });
+
+    // Another request meanwhile doesn't give the use back
+    assert!(count.expire_range_sessions(1).is_empty());
+    assert_eq!(count.in_flight, 1);
+    assert_eq!(count.range_sessions.len(), 1);
+
+    assert!(count.finish_range_part(client, None, Some((0..10, 10))));
+    assert_eq!(
+        count.settle(client, None, 1),
+        Some(ShutdownReason::UsesExhausted)
+    );
+    assert_eq!(count.used, 1);
+    assert_eq!(count.in_flight, 0);
+}
+
+#[test]
+fn test_stalled_range_session_counts_its_use() {
+    let client = Some("127.0.0.1".parse().unwrap());
+    let mut count = UseCount {
+        in_flight: 2,
+        ..UseCount::default()
+    };
+    for received in [vec![0..4, 4..9], Vec::new()] {
+        let mut session = RangeSession {
+            client,
+            recipient: None,
+            peer: None,
+            user_agent: None,
+            total: None,
+            received: Vec::new(),
+            sending: 0,
+            last_seen: Instant::now(),
+        };
+        for part in received {
+            assert!(!session.add(part));
+        }
+        count.range_sessions.push(session);
+    }
+    assert!(count.expire_range_sessions(3).is_empty());
+
+    for session in &mut count.range_sessions {
+        session.last_seen -= RANGE_SESSION_TIMEOUT;
+    }
+    let counted = count.expire_range_sessions(3);
+    // All but the last byte is as good as the secret, only the session that sent nothing is free
+    assert_eq!(counted.len(), 1);
+    assert_eq!(counted[0].0.received.first(), Some(&(0..9)));
+    assert_eq!(count.used, 1);
+    assert_eq!(count.in_flight, 0);
+    assert!(count.range_sessions.is_empty());
+}
+
+#[tokio::test]
+async fn test_share_stops_waiting_for_stalled_downloads() {
+    let handle = Share::builder()
+        .content("secret: 42\n".repeat(4 << 20))
+        .drain_timeout(Duration::from_millis(100))
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+
+    // The download stalls, as the body is never read
+    let _response = reqwest::get(&handle.urls()[0]).await.unwrap();
+    handle.shutdown_trigger().trigger();
+    tokio::time::timeout(Duration::from_secs(5), handle.wait())
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_share_gives_aborted_downloads_another_try() {
+    let content = "secret: 42\n".repeat(1 << 20);
+    let handle = Share::builder()
+        .content(content.clone())
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+
+    let mut response = reqwest::get(&url).await.unwrap();
+    assert!(response.chunk().await.unwrap().is_some());
+    drop(response);
+
+    // The aborted download holds on to the use until the server notices
+    let response = loop {
+        let response = reqwest::get(&url).await.unwrap();
+        if response.status() != StatusCode::SERVICE_UNAVAILABLE {
+            break response;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    assert_eq!(response.text().await.unwrap(), content);
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_git_repository_can_be_cloned_once() {
+    let dir = tempdir().unwrap();
+    let repository = dir.path().join("project");
+    let work_dir = dir.path().to_path_buf();
+    let git = move |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&work_dir)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    };
+    git(&["init", "project"]);
+    std::fs::write(repository.join("secret.txt"), "secret: 42").unwrap();
+    git(&["-C", "project", "add", "secret.txt"]);
+    git(&["-C", "project", "commit", "-m", "Add secret"]);
+
+    let handle = Share::builder()
+        .git_repository(&repository)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    assert!(url.ends_with("/project.git"));
+
+    // Git fails on a commit it doesn't know, which doesn't consume the only use
+    let response = reqwest::Client::new()
+        .post(format!("{}/git-upload-pack", url))
+        .header(
+            header::CONTENT_TYPE,
+            "application/x-git-upload-pack-request",
+        )
+        .body(format!("0032want {}\n00000009done\n", "0".repeat(40)))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.bytes().await.is_err());
+
+    let cloned = {
+        let url = url.clone();
+        tokio::task::spawn_blocking(move || git(&["clone", &url, "cloned"]))
+    };
+    cloned.await.unwrap();
+    handle.wait().await.unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("cloned").join("secret.txt")).unwrap(),
+        "secret: 42"
+    );
+}
+
+#[test]
+fn test_package_from_file() {
+    let package = Package::from_file(Path::new("dist/Hotfix_Tool-1.0.1-py3-none-any.whl")).unwrap();
+    assert_eq!(package.project, "hotfix-tool");
+    let package = Package::from_file(Path::new("hotfix.tool-2.0.tar.gz")).unwrap();
+    assert_eq!(package.project, "hotfix-tool");
+    assert!(Package::from_file(Path::new("hotfix.deb")).is_err());
+}
+
+#[tokio::test]
+async fn test_share_python_package_as_index() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("Hotfix_Tool-1.0-py3-none-any.whl");
+    std::fs::write(&file_path, "not really a wheel").unwrap();
+    let handle = Share::builder()
+        .python_package(&file_path)
+        .failed_attempts(1)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    assert!(url.ends_with("/simple"));
+
+    let index = reqwest::get(format!("{}/", url)).await.unwrap();
+    assert!(
+        index
+            .text()
+            .await
+            .unwrap()
+            .contains("href=\"hotfix-tool/\"")
+    );
+    // Dependencies are looked up in the same index
+    let response = reqwest::get(format!("{}/requests/", url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let project = reqwest::get(format!("{}/hotfix-tool/", url)).await.unwrap();
+    let link = format!(
+        "href=\"Hotfix_Tool-1.0-py3-none-any.whl#sha256={}\"",
+        handle.checksum().unwrap()
+    );
+    assert!(project.text().await.unwrap().contains(&link));
+    let response = reqwest::get(format!(
+        "{}/hotfix-tool/Hotfix_Tool-1.0-py3-none-any.whl",
+        url
+    ))
+    .await
+    .unwrap();
+    assert_eq!(response.text().await.unwrap(), "not really a wheel");
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_cloud_init_user_data() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("user-data.yaml");
+    std::fs::write(&file_path, "#cloud-config\nhostname: db-1\n").unwrap();
+    let handle = Share::builder()
+        .cloud_init(&file_path)
+        .failed_attempts(1)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let seed = handle.urls()[0].clone();
+    assert!(seed.ends_with('/'));
+
+    let meta_data = reqwest::get(format!("{}meta-data", seed)).await.unwrap();
+    assert!(meta_data.text().await.unwrap().starts_with("instance-id: "));
+    for file_name in ["vendor-data", "network-config"] {
+        let response = reqwest::get(format!("{}{}", seed, file_name))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let user_data = reqwest::get(format!("{}user-data", seed)).await.unwrap();
+    assert_eq!(
+        user_data.text().await.unwrap(),
+        "#cloud-config\nhostname: db-1\n"
+    );
+    handle.wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_proxies_upstream_on_request() {
+    // The upstream is down for the first request
+    let available = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let upstream = Router::new().route(
+        "/builds/artifact.bin",
+        get(move |headers: HeaderMap| async move {
+            if !available.swap(true, Ordering::SeqCst) {
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+            match headers.get(header::AUTHORIZATION) {
+                Some(value) if value == "Bearer s3cret" => "artifact".into_response(),
+                _ => StatusCode::UNAUTHORIZED.into_response(),
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_address = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, upstream).into_future());
+
+    let upstream = HttpHook::new(format!("http://{}/builds/artifact.bin", upstream_address))
+        .header("Authorization: Bearer s3cret")
+        .unwrap();
+    let handle = Share::builder()
+        .proxy(upstream)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let url = handle.urls()[0].clone();
+    assert!(url.ends_with("/artifact.bin"));
+    assert_eq!(handle.checksum(), None);
+
+    // A HEAD doesn't reach the upstream, it would still be down otherwise
+    let client = reqwest::Client::new();
+    let response = client.head(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "artifact");
+    handle.wait().await.unwrap();
+}
+
+#[test]
+fn test_render_template() {
+    let values = [("peer", "10.11.12.13:4242".to_string())];
+    assert_eq!(
+        render_template(r#"{"body": "Fetched by {peer}{unknown}"}"#, &values),
+        r#"{"body": "Fetched by 10.11.12.13:4242{unknown}"}"#
+    );
+    assert_eq!(
+        render_template("Bearer {env:LOCALSECRET_TEST_UNSET_VARIABLE}", &values),
+        "Bearer "
+    );
+    assert_eq!(render_template("{peer", &values), "{peer");
+    assert_eq!(
+        render_template(
+            "{label:ticket-id}",
+            &[("label:ticket-id", "OPS-1".to_string())]
+        ),
+        "OPS-1"
+    );
+}
+
+#[tokio::test]
+async fn test_simulate() {
+    let handle = Share::builder()
+        .content("secret: 42")
+        .failed_attempts(5)
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let clients = [SimulatedClient::Scanner, SimulatedClient::Recipient];
+    let attempts = simulate(&handle.urls()[0], &clients).await;
+    handle.wait().await.unwrap();
+
+    let statuses: Vec<_> = attempts.iter().map(|attempt| attempt.status).collect();
+    assert_eq!(statuses, [Some(404), Some(404), Some(404), Some(200)]);
+    assert_eq!(attempts[1].path, "/.env");
+    assert_eq!(attempts[3].bytes, 10);
+}
+
+#[tokio::test]
+async fn test_simulate_slow_downloader() {
+    let secret = simulated_secret();
+    let handle = Share::builder()
+        .content(secret.clone())
+        .bind_ip("127.0.0.1".parse().unwrap())
+        .serve()
+        .await
+        .unwrap();
+    let started = Instant::now();
+    let attempts = simulate(&handle.urls()[0], &[SimulatedClient::SlowDownloader]).await;
+    handle.wait().await.unwrap();
+
+    assert_eq!(attempts[0].status, Some(200));
+    assert_eq!(attempts[0].bytes, secret.len());
+    assert!(started.elapsed() > Duration::from_secs(2));
+}
@@ -0,0 +1,115 @@
+//! The random part of the urls, which is all that keeps others from fetching the secret.
+
+use rand::seq::IndexedRandom;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 1024 short and common English words, so every word adds 10 bits.
+const WORDS: &str = include_str!("token/words.txt");
+
+const ALPHANUMERIC: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+const LOWERCASE_ALPHANUMERIC: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// Letters and digits without those easily mistaken for each other: 0, O and o, 1, l and I.
+const UNAMBIGUOUS: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnpqrstuvwxyz";
+
+const LOWERCASE_UNAMBIGUOUS: &[u8] = b"23456789abcdefghijkmnpqrstuvwxyz";
+
+const DIGITS: &[u8] = b"0123456789";
+
+/// A path segment sharing this many leading characters with a token is taken as a mangled one.
+const TOKEN_PREFIX_LENGTH: usize = 4;
+
+/// The tarpit for invalid urls when the token is only made of digits and none was given.
+pub(crate) const DIGITS_TARPIT: Duration = Duration::from_secs(1);
+
+/// How the token is written. Unless its length is given, any style is as hard to guess as
+/// letters and digits of the configured length.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TokenStyle {
+    /// Letters and digits.
+    #[default]
+    Alphanumeric,
+    /// Letters and digits except 0, O, o, 1, l and I, so a url copied by hand still works. The
+    /// token gets a little longer to make up for it.
+    Unambiguous,
+    /// Words joined by dashes, e.g. `brisk-otter-cactus`, to type the url from another screen.
+    Words,
+    /// Digits only, to type the url on a numeric keypad or dictate it over the phone. A short
+    /// token of digits is far easier to guess, so invalid urls are tarpitted by default.
+    Digits,
+}
+
+impl TokenStyle {
+    /// The characters of the token, words are drawn from [`WORDS`] instead.
+    fn alphabet(self, lowercase: bool) -> Option<&'static [u8]> {
+        match (self, lowercase) {
+            (TokenStyle::Alphanumeric, false) => Some(ALPHANUMERIC),
+            (TokenStyle::Alphanumeric, true) => Some(LOWERCASE_ALPHANUMERIC),
+            (TokenStyle::Unambiguous, false) => Some(UNAMBIGUOUS),
+            (TokenStyle::Unambiguous, true) => Some(LOWERCASE_UNAMBIGUOUS),
+            (TokenStyle::Words, _) => None,
+            (TokenStyle::Digits, _) => Some(DIGITS),
+        }
+    }
+
+    /// A token as hard to guess as `length` letters and digits, only in lowercase if asked to.
+    pub(crate) fn generate(self, length: u16, lowercase: bool) -> String {
+        let symbols = match self.alphabet(lowercase) {
+            Some(alphabet) => alphabet.len(),
+            None => WORDS.lines().count(),
+        };
+        if symbols == ALPHANUMERIC.len() {
+            return self.generate_exactly(length, lowercase);
+        }
+        let bits = f64::from(length) * (ALPHANUMERIC.len() as f64).log2();
+        let length = (bits / (symbols as f64).log2()).ceil() as u16;
+        self.generate_exactly(length, lowercase)
+    }
+
+    /// A token of `length` characters, or words, only in lowercase if asked to.
+    pub(crate) fn generate_exactly(self, length: u16, lowercase: bool) -> String {
+        let mut rng = rand::rng();
+        let length = usize::from(length);
+        match self.alphabet(lowercase) {
+            Some(alphabet) => (0..length)
+                .filter_map(|_| alphabet.choose(&mut rng).copied())
+                .map(char::from)
+                .collect(),
+            None => {
+                let words: Vec<&str> = WORDS.lines().collect();
+                (0..length)
+                    .filter_map(|_| words.choose(&mut rng).copied())
+                    .collect::<Vec<_>>()
+                    .join("-")
+            }
+        }
+    }
+}
+
+/// Replaces the token and anything that looks like a mangled token in the path, so it can be
+/// recorded without giving the token away.
+pub(crate) fn redact(path: &str, tokens: &[Arc<str>]) -> String {
+    let segment = path.split('/').nth(1).unwrap_or_default();
+    let looks_like_token = |token: &Arc<str>| {
+        let common = segment
+            .chars()
+            .zip(token.chars())
+            .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+            .count();
+        common >= TOKEN_PREFIX_LENGTH.min(token.len())
+    };
+    // Tokens might be matched regardless of case
+    let replacement = match tokens
+        .iter()
+        .any(|token| token.eq_ignore_ascii_case(segment))
+    {
+        true => "REDACTED-TOKEN",
+        false if !segment.is_empty() && tokens.iter().any(looks_like_token) => {
+            "REDACTED-WRONG-TOKEN"
+        }
+        false => return path.to_string(),
+    };
+    format!("/{}{}", replacement, &path[1 + segment.len()..])
+}
@@ -0,0 +1,300 @@
+//! Live dashboard while serving, see `--tui`.
+//!
+//! The log is kept in a [`LogBuffer`] while the dashboard owns the terminal, shown in the
+//! dashboard and written to stderr once it closed.
+
+use localsecret::{Event, Progress, ShutdownReason, ShutdownTrigger, UseLimit};
+use ratatui::crossterm::event::{self, Event as TerminalEvent, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::widgets::{Block, List, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+use tracing_subscriber::fmt::MakeWriter;
+
+const RECENT_REQUESTS: usize = 100;
+
+/// The last lines of the log shown in the dashboard.
+const LOG_LINES: usize = 4;
+
+/// Where the log goes, stderr unless the dashboard captures it.
+#[derive(Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<Option<Vec<u8>>>>);
+
+impl LogBuffer {
+    fn capture(&self) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(Vec::new());
+    }
+
+    /// Returns what was logged meanwhile, the log goes to stderr again.
+    fn release(&self) -> Vec<u8> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .unwrap_or_default()
+    }
+
+    fn last_lines(&self, count: usize) -> Vec<String> {
+        let buffer = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let log = String::from_utf8_lossy(buffer.as_deref().unwrap_or_default());
+        let lines: Vec<&str> = log.lines().collect();
+        lines[lines.len().saturating_sub(count)..]
+            .iter()
+            .map(|line| line.to_string())
+            .collect()
+    }
+}
+
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+        {
+            Some(buffer) => {
+                buffer.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = LogBuffer;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+pub struct Dashboard {
+    pub urls: Vec<String>,
+    pub expires_at: Option<SystemTime>,
+    /// Not set if clients are locked out one by one instead of stopping the server.
+    pub maximum_failed_attempts: Option<u16>,
+    pub use_limit: UseLimit,
+    pub shutdown_trigger: ShutdownTrigger,
+    pub events: Receiver<Event>,
+    pub progress: Receiver<Progress>,
+    pub log: LogBuffer,
+}
+
+#[derive(Default)]
+struct Counters {
+    uses: u16,
+    failed_attempts: u16,
+    bytes_sent: u64,
+    transfers: Vec<Progress>,
+    requests: VecDeque<String>,
+    stopped: Option<ShutdownReason>,
+}
+
+impl Dashboard {
+    /// Shows the dashboard until the share stops, this blocks.
+    pub fn run(self) -> io::Result<()> {
+        self.log.capture();
+        let mut terminal = ratatui::init();
+        let result = self.show(&mut terminal);
+        ratatui::restore();
+        io::stderr().write_all(&self.log.release())?;
+        result
+    }
+
+    fn show(&self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        let mut counters = Counters::default();
+        while counters.stopped.is_none() {
+            loop {
+                match self.events.try_recv() {
+                    Ok(event) => counters.record(event),
+                    Err(TryRecvError::Empty) => break,
+                    // The share is gone without saying why, e.g. because the server failed
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            for progress in self.progress.try_iter() {
+                counters.record_progress(progress);
+            }
+            terminal.draw(|frame| self.draw(frame, &counters))?;
+            if event::poll(Duration::from_millis(250))?
+                && let TerminalEvent::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Char('r') => self.shutdown_trigger.trigger(),
+                    KeyCode::Char('+') => self.use_limit.add(1),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&self, frame: &mut Frame, counters: &Counters) {
+        let [
+            urls_area,
+            status_area,
+            transfers_area,
+            requests_area,
+            log_area,
+            help_area,
+        ] = Layout::vertical([
+            Constraint::Length(self.urls.len() as u16 + 2),
+            Constraint::Length(6),
+            Constraint::Length(counters.transfers.len().max(1) as u16 + 2),
+            Constraint::Min(3),
+            Constraint::Length(LOG_LINES as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Paragraph::new(self.urls.join("\n")).block(Block::bordered().title(" localsecret ")),
+            urls_area,
+        );
+
+        let maximum_uses = self.use_limit.maximum();
+        let remaining_time = match self.expires_at {
+            Some(expires_at) => {
+                let remaining = expires_at
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+                humantime::format_duration(Duration::from_secs(remaining.as_secs())).to_string()
+            }
+            None => "never expires".to_string(),
+        };
+        let failed_attempts = match self.maximum_failed_attempts {
+            Some(maximum) => format!("{} of {}", counters.failed_attempts, maximum),
+            None => counters.failed_attempts.to_string(),
+        };
+        let status = format!(
+            "Uses: {} of {} ({} left)\nFailed attempts: {}\nRemaining time: {}\nSent: {} bytes",
+            counters.uses,
+            maximum_uses,
+            maximum_uses.saturating_sub(counters.uses),
+            failed_attempts,
+            remaining_time,
+            counters.bytes_sent,
+        );
+        frame.render_widget(
+            Paragraph::new(status).block(Block::bordered().title(" Status ")),
+            status_area,
+        );
+
+        let transfers: Vec<String> = match counters.transfers.is_empty() {
+            true => vec!["No running downloads".to_string()],
+            false => counters.transfers.iter().map(describe).collect(),
+        };
+        frame.render_widget(
+            List::new(transfers).block(Block::bordered().title(" Downloads ")),
+            transfers_area,
+        );
+
+        frame.render_widget(
+            List::new(counters.requests.iter().map(String::as_str))
+                .block(Block::bordered().title(" Recent requests ")),
+            requests_area,
+        );
+        frame.render_widget(
+            List::new(self.log.last_lines(LOG_LINES)).block(Block::bordered().title(" Log ")),
+            log_area,
+        );
+        frame.render_widget(
+            Paragraph::new(" q/r: revoke the share   +: allow one more use"),
+            help_area,
+        );
+    }
+}
+
+fn peer(peer: Option<SocketAddr>) -> String {
+    peer.map_or("unix socket".to_string(), |peer| peer.to_string())
+}
+
+/// A running download, e.g. `127.0.0.1:51234: 512 of 1024 bytes (50%)`.
+fn describe(transfer: &Progress) -> String {
+    let peer = peer(transfer.peer);
+    match transfer.total {
+        Some(total) if total > 0 => format!(
+            "{}: {} of {} bytes ({}%)",
+            peer,
+            transfer.sent,
+            total,
+            transfer.sent * 100 / total
+        ),
+        _ => format!("{}: {} bytes", peer, transfer.sent),
+    }
+}
+
+impl Counters {
+    fn record_progress(&mut self, progress: Progress) {
+        let index = self
+            .transfers
+            .iter()
+            .position(|transfer| transfer.peer == progress.peer);
+        let before = index.map_or(0, |index| self.transfers[index].sent);
+        self.bytes_sent += progress.sent.saturating_sub(before);
+        match (index, progress.done) {
+            (Some(index), true) => {
+                self.transfers.remove(index);
+            }
+            (Some(index), false) => self.transfers[index] = progress,
+            (None, true) => {}
+            (None, false) => self.transfers.push(progress),
+        }
+    }
+
+    fn record(&mut self, event: Event) {
+        let time = humantime::format_rfc3339_seconds(SystemTime::now());
+        let request = match event {
+            Event::Accessed {
+                peer: address,
+                bytes,
+                recipient,
+                ..
+            } => {
+                self.uses += 1;
+                let bytes = bytes.map_or("unknown size".to_string(), |bytes| {
+                    format!("{} bytes", bytes)
+                });
+                let recipient = recipient
+                    .map(|recipient| format!(" as {}", recipient))
+                    .unwrap_or_default();
+                format!(
+                    "{} {} fetched the secret{} ({})",
+                    time,
+                    peer(address),
+                    recipient,
+                    bytes
+                )
+            }
+            Event::Failed {
+                path,
+                peer: address,
+                ..
+            } => {
+                self.failed_attempts += 1;
+                format!("{} {} requested {}", time, peer(address), path)
+            }
+            Event::Denied { peer: address, .. } => format!("{} {} was denied", time, peer(address)),
+            Event::Shutdown(reason) => {
+                self.stopped = Some(reason);
+                return;
+            }
+            Event::UrlGenerated { .. } | Event::LateAttempt { .. } => return,
+        };
+        // Newest first
+        self.requests.push_front(request);
+        self.requests.truncate(RECENT_REQUESTS);
+    }
+}
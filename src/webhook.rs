@@ -0,0 +1,55 @@
+//! The JSON events posted to the `--webhook` url.
+
+use localsecret::{Event, ShutdownReason};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+pub fn webhook_payload(
+    event: &Event,
+    labels: &BTreeMap<String, String>,
+    timestamp: SystemTime,
+) -> Option<Value> {
+    let timestamp = humantime::format_rfc3339_seconds(timestamp).to_string();
+    match event {
+        Event::Accessed {
+            peer,
+            user_agent,
+            bytes,
+            recipient,
+        } => Some(json!({
+            "event": "accessed",
+            "timestamp": timestamp,
+            "peer_ip": peer.map(|peer| peer.ip()),
+            "user_agent": user_agent,
+            "bytes": bytes,
+            "recipient": recipient,
+            "labels": labels,
+        })),
+        Event::Denied { peer, user_agent } => Some(json!({
+            "event": "denied",
+            "timestamp": timestamp,
+            "peer_ip": peer.map(|peer| peer.ip()),
+            "user_agent": user_agent,
+            "labels": labels,
+        })),
+        Event::Shutdown(reason) => Some(json!({
+            "event": "shutdown",
+            "timestamp": timestamp,
+            "reason": match reason {
+                ShutdownReason::UsesExhausted => "uses_exhausted",
+                ShutdownReason::FailedAttemptsExhausted => "failed_attempts_exhausted",
+                ShutdownReason::Expired => "expired",
+                ShutdownReason::Triggered => "triggered",
+                ShutdownReason::Panicked => "panicked",
+                ShutdownReason::SecretChanged => "secret_changed",
+            },
+            "labels": labels,
+        })),
+        // Single failed attempts are just noise, e.g. browsers fetching the favicon
+        Event::UrlGenerated { .. } | Event::Failed { .. } | Event::LateAttempt { .. } => None,
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,21 @@
+use super::*;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn test_webhook_payload() {
+    let timestamp = UNIX_EPOCH + Duration::from_secs(1_750_000_000);
+    let labels = BTreeMap::from([("ticket".to_string(), "OPS-1".to_string())]);
+    let event = Event::Shutdown(ShutdownReason::FailedAttemptsExhausted);
+    assert_eq!(
+        webhook_payload(&event, &labels, timestamp)
+            .unwrap()
+            .to_string(),
+        r#"{"event":"shutdown","labels":{"ticket":"OPS-1"},"reason":"failed_attempts_exhausted","timestamp":"2025-06-15T15:06:40Z"}"#
+    );
+    let event = Event::Failed {
+        path: "/favicon.ico".to_string(),
+        peer: None,
+        user_agent: None,
+    };
+    assert_eq!(webhook_payload(&event, &labels, timestamp), None);
+}
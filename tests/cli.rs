@@ -19,6 +19,30 @@ fn secret_file_doesnt_exist() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn upnp_requires_allow_public() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("localsecret")?;
+
+    cmd.arg("--demo").arg("--upnp");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--allow-public"));
+
+    Ok(())
+}
+
+#[test]
+fn completions_can_be_generated() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("localsecret")?;
+
+    cmd.arg("completions").arg("bash");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("--secret-file"));
+
+    Ok(())
+}
+
 #[test]
 fn secret_file_can_be_retrieved_once() -> Result<(), Box<dyn std::error::Error>> {
     // Set up test file
@@ -77,3 +101,338 @@ fn secret_file_can_be_retrieved_once() -> Result<(), Box<dyn std::error::Error>>
     }
     Ok(())
 }
+
+#[test]
+fn demo_shares_a_generated_secret() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("localsecret")?;
+    let mut child = cmd
+        .arg("--demo")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let mut reader = std::io::BufReader::new(stdout);
+    let mut url = String::new();
+    reader.read_line(&mut url)?;
+
+    // The demo shows the page of --click-through first
+    let page = reqwest::blocking::get(url.trim())?.text()?;
+    assert!(page.contains("Reveal the secret"));
+    let body = reqwest::blocking::Client::new()
+        .post(url.trim())
+        .send()?
+        .text()?;
+    assert!(body.contains("Demo token: "));
+
+    match child.wait_timeout(Duration::from_secs(3))? {
+        Some(exit_code) => assert_eq!(exit_code.code(), Some(0)),
+        None => {
+            child.kill()?;
+            panic!("Process didn't terminate in time");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn secret_can_be_retrieved_via_unix_socket() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    let dir = tempdir()?;
+    let socket_path = dir.path().join("localsecret.sock");
+
+    // Start the command and web server, piping the secret to stdin
+    let mut cmd = Command::cargo_bin("localsecret")?;
+    let mut child = cmd
+        .arg("--unix-socket")
+        .arg(&socket_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("Failed to capture stdin");
+    write!(stdin, "secret: 42")?;
+    drop(stdin);
+
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let mut reader = std::io::BufReader::new(stdout);
+    let mut url = String::new();
+    reader.read_line(&mut url)?;
+    let path = url.trim().trim_start_matches("http://localhost");
+
+    // Speak plain HTTP over the socket, as reqwest can't use unix sockets
+    let mut stream = UnixStream::connect(&socket_path)?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with("\r\n\r\nsecret: 42"));
+
+    match child.wait_timeout(Duration::from_secs(3))? {
+        Some(exit_code) => assert_eq!(exit_code.code(), Some(0)),
+        None => {
+            child.kill()?;
+            panic!("Process didn't terminate in time");
+        }
+    }
+    assert!(!socket_path.exists(), "the socket should be removed");
+    Ok(())
+}
+
+#[test]
+fn json_output_describes_the_share() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("localsecret")?;
+    let mut child = cmd
+        .arg("--output")
+        .arg("json")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("Failed to capture stdin");
+    write!(stdin, "secret: 42")?;
+    drop(stdin);
+
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let mut reader = std::io::BufReader::new(stdout);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let output: serde_json::Value = serde_json::from_str(&line)?;
+
+    assert_eq!(output["pid"], child.id());
+    assert_eq!(
+        output["checksum"],
+        "ba37d4f33e6b1d0108bf9b0d40d2c2fb763ca0fe8add34888d3cd3483090203d"
+    );
+    let url = output["url"].as_str().unwrap();
+    let token = output["token"].as_str().unwrap();
+    assert!(url.ends_with(&format!(":{}/{}", output["port"], token)));
+
+    let body = reqwest::blocking::get(url)?.text()?;
+    assert_eq!(body, "secret: 42");
+
+    match child.wait_timeout(Duration::from_secs(3))? {
+        Some(exit_code) => assert_eq!(exit_code.code(), Some(0)),
+        None => {
+            child.kill()?;
+            panic!("Process didn't terminate in time");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn checksum_is_printed_at_startup() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Read;
+
+    let mut cmd = Command::cargo_bin("localsecret")?;
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("Failed to capture stdin");
+    write!(stdin, "secret: 42")?;
+    drop(stdin);
+
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let mut reader = std::io::BufReader::new(stdout);
+    let mut url = String::new();
+    reader.read_line(&mut url)?;
+    reqwest::blocking::get(url.trim())?.text()?;
+
+    match child.wait_timeout(Duration::from_secs(3))? {
+        Some(exit_code) => assert_eq!(exit_code.code(), Some(0)),
+        None => {
+            child.kill()?;
+            panic!("Process didn't terminate in time");
+        }
+    }
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .expect("Failed to capture stderr")
+        .read_to_string(&mut stderr)?;
+    assert!(
+        stderr
+            .contains("SHA-256: ba37d4f33e6b1d0108bf9b0d40d2c2fb763ca0fe8add34888d3cd3483090203d"),
+        "{}",
+        stderr
+    );
+    Ok(())
+}
+
+#[test]
+fn recipients_get_urls_of_their_own() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("localsecret")?;
+    let mut child = cmd
+        .arg("--recipients")
+        .arg("2")
+        .arg("--output")
+        .arg("json")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("Failed to capture stdin");
+    write!(stdin, "secret: 42")?;
+    drop(stdin);
+
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let mut reader = std::io::BufReader::new(stdout);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let output: serde_json::Value = serde_json::from_str(&line)?;
+
+    assert!(output["token"].is_null());
+    let recipients = output["recipients"].as_array().unwrap();
+    assert_eq!(recipients.len(), 2);
+    assert_eq!(recipients[1]["name"], "recipient-2");
+    for recipient in recipients {
+        let url = recipient["urls"][0].as_str().unwrap();
+        let body = reqwest::blocking::get(url)?.text()?;
+        assert_eq!(body, "secret: 42");
+    }
+
+    // Both urls are used up, so the server stops on its own and reports who used them
+    match child.wait_timeout(Duration::from_secs(3))? {
+        Some(exit_code) => assert_eq!(exit_code.code(), Some(0)),
+        None => {
+            child.kill()?;
+            panic!("Process didn't terminate in time");
+        }
+    }
+    // Stdout stays a single JSON object, the report goes to stderr
+    line.clear();
+    assert_eq!(reader.read_line(&mut line)?, 0, "{}", line);
+    let stderr = std::io::BufReader::new(child.stderr.take().expect("Failed to capture stderr"));
+    let report = stderr
+        .lines()
+        .map_while(Result::ok)
+        .find(|line| line.starts_with(r#"{"redemptions":"#))
+        .expect("The report should be printed to stderr");
+    let report: serde_json::Value = serde_json::from_str(&report)?;
+    assert_eq!(report["redemptions"][0]["name"], "recipient-1");
+    assert_eq!(report["redemptions"][0]["status"], "used");
+    assert_eq!(
+        report["redemptions"][1]["peers"].as_array().unwrap().len(),
+        1
+    );
+    Ok(())
+}
+
+#[test]
+fn secret_file_is_shredded_after_delivery() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let file_path = dir.path().join("test_file.txt");
+    std::fs::write(&file_path, "secret: 42")?;
+
+    let mut cmd = Command::cargo_bin("localsecret")?;
+    let mut child = cmd
+        .arg("--secret-file")
+        .arg(&file_path)
+        .arg("--shred-after")
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let mut url = String::new();
+    std::io::BufReader::new(stdout).read_line(&mut url)?;
+    let body = reqwest::blocking::get(url.trim())?.text()?;
+    assert_eq!(body, "secret: 42");
+
+    match child.wait_timeout(Duration::from_secs(3))? {
+        Some(exit_code) => assert_eq!(exit_code.code(), Some(0)),
+        None => {
+            child.kill()?;
+            panic!("Process didn't terminate in time");
+        }
+    }
+    assert!(!file_path.exists());
+    Ok(())
+}
+
+#[test]
+fn spooled_stdin_leaves_no_file_behind() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempdir()?;
+    // Larger than what is kept in memory
+    let secret = vec![b'x'; 17 * 1024 * 1024];
+
+    let mut cmd = Command::cargo_bin("localsecret")?;
+    let mut child = cmd
+        .env("TMPDIR", temp_dir.path())
+        .env("TMP", temp_dir.path())
+        .env("TEMP", temp_dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("Failed to capture stdin");
+    stdin.write_all(&secret)?;
+    drop(stdin);
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let mut url = String::new();
+    std::io::BufReader::new(stdout).read_line(&mut url)?;
+    // On unix, the file is gone while the secret is still served
+    if cfg!(unix) {
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 0);
+    }
+    let body = reqwest::blocking::get(url.trim())?.bytes()?;
+    assert_eq!(body.len(), secret.len());
+
+    match child.wait_timeout(Duration::from_secs(3))? {
+        Some(exit_code) => assert_eq!(exit_code.code(), Some(0)),
+        None => {
+            child.kill()?;
+            panic!("Process didn't terminate in time");
+        }
+    }
+    assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn audit_log_doesnt_record_the_token() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let file_path = dir.path().join("test_file.txt");
+    std::fs::write(&file_path, "secret: 42")?;
+    let log_path = dir.path().join("audit.jsonl");
+
+    let mut cmd = Command::cargo_bin("localsecret")?;
+    let mut child = cmd
+        .arg("--secret-file")
+        .arg(&file_path)
+        .arg("--audit-log")
+        .arg(&log_path)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let mut url = String::new();
+    std::io::BufReader::new(stdout).read_line(&mut url)?;
+    let url = url.trim();
+    let token = url.rsplit('/').nth(1).unwrap();
+    let wrong_name = format!("{}/wrong-name", &url[..url.rfind('/').unwrap()]);
+    let response = reqwest::blocking::get(&wrong_name)?;
+    assert_eq!(response.status(), 404);
+    let body = reqwest::blocking::get(url)?.text()?;
+    assert_eq!(body, "secret: 42");
+
+    match child.wait_timeout(Duration::from_secs(3))? {
+        Some(exit_code) => assert_eq!(exit_code.code(), Some(0)),
+        None => {
+            child.kill()?;
+            panic!("Process didn't terminate in time");
+        }
+    }
+    let audit_log = std::fs::read_to_string(&log_path)?;
+    assert_eq!(audit_log.lines().count(), 2);
+    assert!(audit_log.contains(r#""path":"/REDACTED-TOKEN/wrong-name""#));
+    assert!(!audit_log.contains(token));
+    Ok(())
+}